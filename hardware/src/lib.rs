@@ -4,3 +4,4 @@ pub mod pl011;
 pub mod ns16550;
 pub mod mmu;
 pub mod virtio_mmio;
+pub mod virtio_pci;
@@ -338,3 +338,75 @@ impl Ns16550Mmio32 {
     pub const fn new(base_addr: usize) -> Self { Ns16550Mmio32 { base_addr } }
     fn ptr(&self) -> *const Ns16550Mmio32Block { self.base_addr as *const _ }
 }
+
+/// A UART register reached through an x86 I/O port
+#[cfg(target_arch = "x86_64")]
+pub struct PortRegister<R: tock_registers::RegisterLongName = ()> {
+    port: u16,
+    associated_register: core::marker::PhantomData<R>,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl<R: tock_registers::RegisterLongName> PortRegister<R> {
+    const fn new(port: u16) -> Self {
+        PortRegister { port, associated_register: core::marker::PhantomData }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl<R: tock_registers::RegisterLongName> tock_registers::interfaces::Readable for PortRegister<R> {
+    type T = u8;
+    type R = R;
+
+    fn get(&self) -> u8 {
+        let value: u8;
+        unsafe {
+            core::arch::asm!("in al, dx", out("al") value, in("dx") self.port, options(nomem, nostack, preserves_flags));
+        }
+        value
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl<R: tock_registers::RegisterLongName> tock_registers::interfaces::Writeable for PortRegister<R> {
+    type T = u8;
+    type R = R;
+
+    fn set(&self, value: u8) {
+        unsafe {
+            core::arch::asm!("out dx, al", in("dx") self.port, in("al") value, options(nomem, nostack, preserves_flags));
+        }
+    }
+}
+
+/// Representation of the UART registers at consecutive I/O ports, e.g., PC COM ports.
+///
+/// The caller must be allowed to access the ports (see `SYS_IO_PORT_GRANT`).
+#[cfg(target_arch = "x86_64")]
+#[allow(non_snake_case)]
+pub struct Ns16550Port {
+    pub RHR_THR_DLL: PortRegister<RHR_THR_DLL::Register>,
+    pub IER_DLM: PortRegister<IER_DLM::Register>,
+    pub ISR_FCR: PortRegister<ISR_FCR::Register>,
+    pub LCR: PortRegister<LCR::Register>,
+    pub MCR: PortRegister<MCR::Register>,
+    pub LSR: PortRegister<LSR::Register>,
+    pub MSR: PortRegister<MSR::Register>,
+    pub SPR: PortRegister<SPR::Register>,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Ns16550Port {
+    pub const fn new(base_port: u16) -> Self {
+        Ns16550Port {
+            RHR_THR_DLL: PortRegister::new(base_port),
+            IER_DLM: PortRegister::new(base_port + 1),
+            ISR_FCR: PortRegister::new(base_port + 2),
+            LCR: PortRegister::new(base_port + 3),
+            MCR: PortRegister::new(base_port + 4),
+            LSR: PortRegister::new(base_port + 5),
+            MSR: PortRegister::new(base_port + 6),
+            SPR: PortRegister::new(base_port + 7),
+        }
+    }
+}
@@ -102,4 +102,11 @@ impl VirtioMmio {
   fn ptr(&self) -> *const VirtioMmioBlock {
     self.base_addr as *const _
   }
+
+  /// Address of the device-specific configuration space
+  pub fn config_addr(&self) -> usize {
+    self.base_addr + CONFIG_OFFSET
+  }
 }
+
+pub const CONFIG_OFFSET: usize = 0x100;
@@ -0,0 +1,67 @@
+use tock_registers::registers::*;
+use tock_registers::*;
+
+/// `cfg_type` of virtio vendor-specific PCI capabilities
+pub mod cap {
+  pub const VENDOR_ID: u8 = 0x09;
+  pub const COMMON_CFG: u8 = 1;
+  pub const NOTIFY_CFG: u8 = 2;
+  pub const ISR_CFG: u8 = 3;
+  pub const DEVICE_CFG: u8 = 4;
+
+  // offsets within a capability
+  pub const OFFSET_CFG_TYPE: usize = 3;
+  pub const OFFSET_BAR: usize = 4;
+  pub const OFFSET_OFFSET: usize = 8;
+  pub const OFFSET_LENGTH: usize = 12;
+  pub const OFFSET_NOTIFY_OFF_MULTIPLIER: usize = 16;
+}
+
+register_structs! {
+  /// Common configuration structure of the modern virtio PCI transport
+  #[allow(non_snake_case)]
+  pub VirtioPciCommonCfgBlock {
+    (0x00 => pub DeviceFeatureSelect: ReadWrite<u32>),
+    (0x04 => pub DeviceFeature: ReadOnly<u32>),
+    (0x08 => pub DriverFeatureSelect: ReadWrite<u32>),
+    (0x0c => pub DriverFeature: ReadWrite<u32>),
+    (0x10 => pub MsixConfig: ReadWrite<u16>),
+    (0x12 => pub NumQueues: ReadOnly<u16>),
+    (0x14 => pub DeviceStatus: ReadWrite<u8>),
+    (0x15 => pub ConfigGeneration: ReadOnly<u8>),
+    (0x16 => pub QueueSelect: ReadWrite<u16>),
+    (0x18 => pub QueueSize: ReadWrite<u16>),
+    (0x1a => pub QueueMsixVector: ReadWrite<u16>),
+    (0x1c => pub QueueEnable: ReadWrite<u16>),
+    (0x1e => pub QueueNotifyOff: ReadOnly<u16>),
+    (0x20 => pub QueueDescLow: ReadWrite<u32>),
+    (0x24 => pub QueueDescHigh: ReadWrite<u32>),
+    (0x28 => pub QueueDriverLow: ReadWrite<u32>),
+    (0x2c => pub QueueDriverHigh: ReadWrite<u32>),
+    (0x30 => pub QueueDeviceLow: ReadWrite<u32>),
+    (0x34 => pub QueueDeviceHigh: ReadWrite<u32>),
+    (0x38 => @END),
+  }
+}
+
+pub struct VirtioPciCommonCfg {
+  base_addr: usize,
+}
+
+impl core::ops::Deref for VirtioPciCommonCfg {
+  type Target = VirtioPciCommonCfgBlock;
+
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*self.ptr() }
+  }
+}
+
+impl VirtioPciCommonCfg {
+  pub const fn new(base_addr: usize) -> Self {
+    VirtioPciCommonCfg { base_addr }
+  }
+
+  fn ptr(&self) -> *const VirtioPciCommonCfgBlock {
+    self.base_addr as *const _
+  }
+}
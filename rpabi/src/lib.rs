@@ -16,6 +16,8 @@ cfg_if::cfg_if! {
 }
 
 // functions in `string.h`
+// Note: host tests link the C library, whose symbols these would replace
+#[cfg(not(test))]
 pub mod string;
 
 pub const CONFIG_USER_LIMIT: usize = 0x3f_a000_0000;
@@ -27,6 +29,7 @@ pub const CONFIG_VIRTUAL_HEAP_BTM: usize = 0x20_0000_0000;
 pub const CONFIG_VIRTUAL_HEAP_TOP: usize = 0x20_1000_0000;
 
 pub const CONFIG_TRUSTED_PLATFORM_INFO: usize = 0x4000_0000;
+pub const CONFIG_TRUSTED_DEVICE_TREE: usize = 0x4010_0000;
 
 pub const CONFIG_ELF_IMAGE: usize = 0x8000_0000;
 
@@ -34,34 +37,7 @@ pub const PAGE_SIZE: usize = 4096;
 pub const WORD_SHIFT: usize = 3;
 pub const WORD_SIZE: usize = 1 << WORD_SHIFT;
 
-pub mod platform {
-  pub const DEVICE_NAME_LEN: usize = 32;
-  pub const PLATFORM_DEVICE_LEN: usize = 8;
-  pub const USER_SPACE_DRIVER_MMIO_OFFSET: usize = 0x8_0000_0000;
-  #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-  pub enum Driver {
-    Nil,
-    VirtioBlk,
-    Ns16550,
-    Pl011,
-    Pl031,
-    GoldfishRtc,
-    Ramdisk,
-  }
-  #[derive(Debug)]
-  pub struct Device {
-    pub name: [u8; DEVICE_NAME_LEN],
-    pub register: core::ops::Range<usize>,
-    pub interrupt: Option<usize>,
-    pub driver: Option<Driver>,
-  }
-
-  #[repr(align(4096))]
-  #[derive(Debug, Default)]
-  pub struct PlatformInfo {
-    pub devices: [Option<Device>; PLATFORM_DEVICE_LEN],
-  }
-}
+pub mod platform;
 
 pub mod syscall {
   pub const SYS_NULL: usize = 0;
@@ -87,7 +63,23 @@ pub mod syscall {
   pub const SYS_GETC: usize = 20;
   pub const SYS_YIELD_TO: usize = 21;
   pub const SYS_REPLY_RECV: usize = 22;
-  pub const SYS_MAX: usize = 23;
+  pub const SYS_THREAD_SUSPEND: usize = 23;
+  pub const SYS_THREAD_RESUME: usize = 24;
+  pub const SYS_THREAD_REGISTER_READ: usize = 25;
+  pub const SYS_THREAD_REGISTER_WRITE: usize = 26;
+  pub const SYS_EXCEPTION_RETURN: usize = 27;
+  pub const SYS_SET_PAGER: usize = 28;
+  pub const SYS_SET_PAGE_LIMIT: usize = 29;
+  pub const SYS_KERNEL_STAT: usize = 30;
+  pub const SYS_IRQ_BIND: usize = 31;
+  pub const SYS_IRQ_MASK: usize = 32;
+  pub const SYS_IRQ_UNMASK: usize = 33;
+  pub const SYS_IRQ_ACK: usize = 34;
+  pub const SYS_IRQ_CONFIG: usize = 35;
+  pub const SYS_MSI_ALLOC: usize = 36;
+  pub const SYS_MSI_FREE: usize = 37;
+  pub const SYS_IO_PORT_GRANT: usize = 38;
+  pub const SYS_MAX: usize = 39;
 
   pub mod error {
     pub const ERROR_INVARG: usize = 1;
@@ -98,6 +90,7 @@ pub mod syscall {
     pub const ERROR_HOLD_ON: usize = 6;
     pub const ERROR_OOR: usize = 7;
     pub const ERROR_PANIC: usize = 8;
+    pub const ERROR_STALE: usize = 9;
   }
 
   pub mod mm {
@@ -271,6 +264,13 @@ pub mod server {
   pub const SERVER_PM: usize = 4;
   pub const SERVER_RTC: usize = 5;
   pub const SERVER_TEST: usize = 6;
+  pub const SERVER_NET: usize = 7;
+  pub const SERVER_UNIX: usize = 8;
+  pub const SERVER_CONSOLE: usize = 9;
+  pub const SERVER_RNG: usize = 10;
+  pub const SERVER_BLK_CACHE: usize = 11;
+  // server ids are below this
+  pub const SERVER_MAX: usize = 32;
 }
 
 pub mod thread {
@@ -281,6 +281,82 @@ pub mod thread {
 pub mod event {
   pub const EVENT_INTERRUPT: usize = 1;
   pub const EVENT_THREAD_EXIT: usize = 2;
+  pub const EVENT_THREAD_SUSPEND: usize = 3;
+  pub const EVENT_TIMER: usize = 4;
+}
+
+/// flags of `SYS_IRQ_BIND` and items of `SYS_IRQ_CONFIG`
+pub mod irq {
+  // the line may be bound to several threads, each of them is woken upon an interrupt
+  pub const BIND_SHARED: usize = 1;
+
+  // value: 0 (least urgent) ..= `PRIORITY_MAX`
+  pub const CONFIG_PRIORITY: usize = 0;
+  // value: `TRIGGER_*`
+  pub const CONFIG_TRIGGER: usize = 1;
+  // value: index of the core the line is routed to
+  pub const CONFIG_AFFINITY: usize = 2;
+
+  pub const PRIORITY_MAX: usize = 7;
+  pub const PRIORITY_DEFAULT: usize = 3;
+
+  pub const TRIGGER_EDGE: usize = 0;
+  pub const TRIGGER_LEVEL: usize = 1;
+}
+
+/// items of `SYS_KERNEL_STAT`
+pub mod kstat {
+  // (total, requested, actual, peak, failures) in bytes
+  pub const ITEM_HEAP: usize = 0;
+  // slab caches: (slot size, objects in use, pages)
+  pub const ITEM_CACHE_THREAD: usize = 1;
+  pub const ITEM_CACHE_ADDRESS_SPACE: usize = 2;
+  // monotonic clock: (milliseconds, microseconds) since boot
+  pub const ITEM_CLOCK: usize = 3;
+  pub const ITEM_MAX: usize = 4;
+}
+
+/// protocol between kernel and the pager thread of an address space (see `SYS_SET_PAGER`)
+///
+/// Upon a user exception, the kernel sends message (a, b, c, d) = (`MESSAGE_FAULT`, fault address, access, pc)
+/// to the pager on behalf of the faulting thread, which stays blocked until the pager replies `REPLY_*`
+pub mod pager {
+  pub const MESSAGE_FAULT: usize = usize::MAX;
+
+  pub const ACCESS_READ: usize = 0;
+  pub const ACCESS_WRITE: usize = 1;
+  pub const ACCESS_EXECUTE: usize = 2;
+  // not a memory access fault (e.g., undefined instruction)
+  pub const ACCESS_UNKNOWN: usize = 3;
+
+  pub const REPLY_RETRY: usize = 0;
+  pub const REPLY_KILL: usize = 1;
+  // let the exception handler of the address space take it instead (killed if none)
+  pub const REPLY_UPCALL: usize = 2;
+}
+
+/// register indices used by `SYS_THREAD_REGISTER_READ/WRITE`, following GDB register numbering of each arch
+pub mod debug {
+  cfg_if::cfg_if! {
+    if #[cfg(target_arch = "aarch64")] {
+      // x0 ~ x30, sp, pc, cpsr
+      pub const REGISTER_SP: usize = 31;
+      pub const REGISTER_PC: usize = 32;
+      pub const REGISTER_STATUS: usize = 33;
+      pub const REGISTER_NUM: usize = 34;
+    } else if #[cfg(target_arch = "riscv64")] {
+      // x0 ~ x31, pc
+      pub const REGISTER_SP: usize = 2;
+      pub const REGISTER_PC: usize = 32;
+      pub const REGISTER_NUM: usize = 33;
+    } else if #[cfg(target_arch = "x86_64")] {
+      // rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8 ~ r15, rip, eflags, cs, ss
+      pub const REGISTER_SP: usize = 7;
+      pub const REGISTER_PC: usize = 16;
+      pub const REGISTER_STATUS: usize = 17;
+      pub const REGISTER_NUM: usize = 20;
+    }
+  }
 }
 
 pub mod time {
@@ -0,0 +1,453 @@
+//! Device table handed from the kernel to the trusted root
+//!
+//! The table occupies `PLATFORM_INFO_SIZE` bytes mapped read-only at `CONFIG_TRUSTED_PLATFORM_INFO`.
+//! It starts with a header carrying magic, version and used size, followed by variable-length device records.
+//! Each record holds its register ranges, interrupts, name and `compatible` strings right after the record head.
+//! On FDT platforms the original device tree blob is also mapped read-only at `CONFIG_TRUSTED_DEVICE_TREE`.
+//! On ACPI platforms the RSDP and the tables it leads to are mapped read-only at `USER_SPACE_ACPI_OFFSET + pa`.
+
+use core::mem::{align_of, size_of};
+use core::ops::Range;
+
+pub const PLATFORM_INFO_MAGIC: u32 = 0x5250_4946; // "RPIF"
+pub const PLATFORM_INFO_VERSION: u32 = 5;
+pub const PLATFORM_INFO_SIZE: usize = 4 * crate::PAGE_SIZE;
+pub const USER_SPACE_DRIVER_MMIO_OFFSET: usize = 0x8_0000_0000;
+pub const USER_SPACE_ACPI_OFFSET: usize = 0x7_0000_0000;
+pub const PCI_BAR_NUM: usize = 6;
+
+#[repr(u32)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Driver {
+  Nil = 0,
+  VirtioBlk = 1,
+  Ns16550 = 2,
+  Pl011 = 3,
+  Pl031 = 4,
+  GoldfishRtc = 5,
+  Ramdisk = 6,
+  VirtioNet = 7,
+  VirtioConsole = 8,
+  VirtioRng = 9,
+}
+
+impl Driver {
+  fn from_u32(value: u32) -> Option<Driver> {
+    Some(match value {
+      0 => Driver::Nil,
+      1 => Driver::VirtioBlk,
+      2 => Driver::Ns16550,
+      3 => Driver::Pl011,
+      4 => Driver::Pl031,
+      5 => Driver::GoldfishRtc,
+      6 => Driver::Ramdisk,
+      7 => Driver::VirtioNet,
+      8 => Driver::VirtioConsole,
+      9 => Driver::VirtioRng,
+      _ => return None,
+    })
+  }
+}
+
+/// A function found by PCI enumeration
+///
+/// The first register range of such device covers the configuration space of the function.
+/// Memory BARs hold physical addresses, I/O BARs are not recorded.
+#[repr(C)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PciFunction {
+  /// base class, sub class and programming interface, from high to low byte
+  pub class: u32,
+  pub segment: u16,
+  pub vendor_id: u16,
+  pub device_id: u16,
+  pub bus: u8,
+  pub device: u8,
+  pub function: u8,
+  /// `[start, end)` of each memory BAR, empty if not recorded
+  pub bars: [[u64; 2]; PCI_BAR_NUM],
+}
+
+impl PciFunction {
+  pub fn bar(&self, index: usize) -> Option<Range<usize>> {
+    let [start, end] = *self.bars.get(index)?;
+    if start < end {
+      Some(start as usize..end as usize)
+    } else {
+      None
+    }
+  }
+
+  pub fn set_bar(&mut self, index: usize, range: Option<Range<usize>>) {
+    self.bars[index] = range.map_or([0, 0], |r| [r.start as u64, r.end as u64]);
+  }
+
+  /// Recorded memory BARs
+  pub fn bar_ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+    (0..PCI_BAR_NUM).filter_map(|i| self.bar(i))
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Device<'a> {
+  pub name: &'a str,
+  /// `compatible` strings separated by NUL, as stored in FDT
+  pub compatible: &'a str,
+  pub registers: &'a [Range<usize>],
+  /// I/O port ranges, x86_64 only
+  pub ports: &'a [Range<usize>],
+  pub interrupts: &'a [usize],
+  pub driver: Option<Driver>,
+  pub pci: Option<PciFunction>,
+}
+
+impl<'a> Device<'a> {
+  /// First register range, which is the only one for most devices
+  pub fn register(&self) -> Option<Range<usize>> {
+    self.registers.first().cloned()
+  }
+
+  /// First interrupt, which is the only one for most devices
+  pub fn interrupt(&self) -> Option<usize> {
+    self.interrupts.first().copied()
+  }
+
+  pub fn compatibles(&self) -> impl Iterator<Item = &'a str> {
+    self.compatible.split('\0').filter(|s| !s.is_empty())
+  }
+
+  pub fn is_compatible(&self, compatible: &str) -> bool {
+    self.compatibles().any(|c| c == compatible)
+  }
+}
+
+#[repr(C)]
+struct Header {
+  magic: u32,
+  version: u32,
+  size: u32,
+  device_num: u32,
+  device_tree_offset: u32,
+  device_tree_size: u32,
+  acpi_rsdp: u64,
+}
+
+// Note: ranges and interrupts following the record head are in the native layout of the target
+#[repr(C)]
+struct Record {
+  // bytes of the record including the head, a multiple of `RECORD_ALIGN`
+  size: u32,
+  register_num: u32,
+  port_num: u32,
+  interrupt_num: u32,
+  name_len: u32,
+  compatible_len: u32,
+  // `Driver` plus one, zero for none
+  driver: u32,
+  // nonzero if `pci` is valid
+  has_pci: u32,
+  pci: PciFunction,
+}
+
+const RECORD_ALIGN: usize = align_of::<Record>();
+
+#[repr(C, align(4096))]
+pub struct PlatformInfo {
+  data: [u8; PLATFORM_INFO_SIZE],
+}
+
+impl PlatformInfo {
+  pub const fn new() -> Self {
+    let mut data = [0u8; PLATFORM_INFO_SIZE];
+    let header = [
+      PLATFORM_INFO_MAGIC.to_ne_bytes(),
+      PLATFORM_INFO_VERSION.to_ne_bytes(),
+      (size_of::<Header>() as u32).to_ne_bytes(),
+      0u32.to_ne_bytes(),
+      0u32.to_ne_bytes(),
+      0u32.to_ne_bytes(),
+    ];
+    let mut i = 0;
+    while i < header.len() * 4 {
+      data[i] = header[i / 4][i % 4];
+      i += 1;
+    }
+    PlatformInfo { data }
+  }
+
+  fn header(&self) -> &Header {
+    unsafe { &*(self.data.as_ptr() as *const Header) }
+  }
+
+  fn header_mut(&mut self) -> &mut Header {
+    unsafe { &mut *(self.data.as_mut_ptr() as *mut Header) }
+  }
+
+  /// Check the table is written by a kernel speaking the same layout
+  pub fn is_valid(&self) -> bool {
+    let header = self.header();
+    header.magic == PLATFORM_INFO_MAGIC
+      && header.version == PLATFORM_INFO_VERSION
+      && header.size as usize <= PLATFORM_INFO_SIZE
+  }
+
+  pub fn len(&self) -> usize {
+    self.header().device_num as usize
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Virtual address range of the device tree blob in the trusted address space, if any
+  pub fn device_tree(&self) -> Option<Range<usize>> {
+    let header = self.header();
+    if !self.is_valid() || header.device_tree_size == 0 {
+      return None;
+    }
+    let start = crate::CONFIG_TRUSTED_DEVICE_TREE + header.device_tree_offset as usize;
+    Some(start..(start + header.device_tree_size as usize))
+  }
+
+  /// Record a device tree blob mapped at `CONFIG_TRUSTED_DEVICE_TREE + offset`
+  pub fn set_device_tree(&mut self, offset: usize, size: usize) {
+    let header = self.header_mut();
+    header.device_tree_offset = offset as u32;
+    header.device_tree_size = size as u32;
+  }
+
+  /// Physical address of the ACPI RSDP, if any
+  pub fn acpi_rsdp(&self) -> Option<usize> {
+    match self.header().acpi_rsdp {
+      0 => None,
+      pa => Some(pa as usize),
+    }
+  }
+
+  pub fn set_acpi_rsdp(&mut self, pa: usize) {
+    self.header_mut().acpi_rsdp = pa as u64;
+  }
+
+  pub fn devices(&self) -> Devices<'_> {
+    Devices {
+      info: self,
+      offset: size_of::<Header>(),
+      remaining: if self.is_valid() { self.len() } else { 0 },
+    }
+  }
+
+  /// Append a device, fails with `ERROR_OOM` if the table is full
+  pub fn push(&mut self, device: &Device) -> Result<(), usize> {
+    let offset = self.header().size as usize;
+    let register_offset = offset + size_of::<Record>();
+    let port_offset = register_offset + size_of_val(device.registers);
+    let interrupt_offset = port_offset + size_of_val(device.ports);
+    let name_offset = interrupt_offset + size_of_val(device.interrupts);
+    let compatible_offset = name_offset + device.name.len();
+    let end = (compatible_offset + device.compatible.len() + RECORD_ALIGN - 1) & !(RECORD_ALIGN - 1);
+    if end > PLATFORM_INFO_SIZE {
+      return Err(crate::syscall::error::ERROR_OOM);
+    }
+    let base = self.data.as_mut_ptr();
+    unsafe {
+      (base.add(offset) as *mut Record).write(Record {
+        size: (end - offset) as u32,
+        register_num: device.registers.len() as u32,
+        port_num: device.ports.len() as u32,
+        interrupt_num: device.interrupts.len() as u32,
+        name_len: device.name.len() as u32,
+        compatible_len: device.compatible.len() as u32,
+        driver: device.driver.map_or(0, |d| d as u32 + 1),
+        has_pci: device.pci.is_some() as u32,
+        pci: device.pci.clone().unwrap_or_default(),
+      });
+      let registers = base.add(register_offset) as *mut Range<usize>;
+      for (i, r) in device.registers.iter().enumerate() {
+        registers.add(i).write(r.clone());
+      }
+      let ports = base.add(port_offset) as *mut Range<usize>;
+      for (i, r) in device.ports.iter().enumerate() {
+        ports.add(i).write(r.clone());
+      }
+      core::ptr::copy_nonoverlapping(
+        device.interrupts.as_ptr(),
+        base.add(interrupt_offset) as *mut usize,
+        device.interrupts.len(),
+      );
+      core::ptr::copy_nonoverlapping(device.name.as_ptr(), base.add(name_offset), device.name.len());
+      core::ptr::copy_nonoverlapping(
+        device.compatible.as_ptr(),
+        base.add(compatible_offset),
+        device.compatible.len(),
+      );
+    }
+    let header = self.header_mut();
+    header.size = end as u32;
+    header.device_num += 1;
+    Ok(())
+  }
+}
+
+impl Default for PlatformInfo {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl core::fmt::Debug for PlatformInfo {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    f.debug_list().entries(self.devices()).finish()
+  }
+}
+
+pub struct Devices<'a> {
+  info: &'a PlatformInfo,
+  offset: usize,
+  remaining: usize,
+}
+
+impl<'a> Iterator for Devices<'a> {
+  type Item = Device<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let used = (self.info.header().size as usize).min(PLATFORM_INFO_SIZE);
+    if self.remaining == 0 || self.offset & (RECORD_ALIGN - 1) != 0 || self.offset + size_of::<Record>() > used {
+      return None;
+    }
+    let base = self.info.data.as_ptr();
+    let record = unsafe { &*(base.add(self.offset) as *const Record) };
+    let size = record.size as usize;
+    let end = self.offset + size;
+    if size < size_of::<Record>() || size & (RECORD_ALIGN - 1) != 0 || end > used {
+      return None;
+    }
+    // Note: counts are 32-bit, none of the sums below overflows
+    let register_offset = self.offset + size_of::<Record>();
+    let port_offset = register_offset + record.register_num as usize * size_of::<Range<usize>>();
+    let interrupt_offset = port_offset + record.port_num as usize * size_of::<Range<usize>>();
+    let name_offset = interrupt_offset + record.interrupt_num as usize * size_of::<usize>();
+    let compatible_offset = name_offset + record.name_len as usize;
+    let compatible_end = compatible_offset + record.compatible_len as usize;
+    if compatible_end > end {
+      return None;
+    }
+    let data = &self.info.data;
+    let device = unsafe {
+      Device {
+        name: core::str::from_utf8(&data[name_offset..compatible_offset]).unwrap_or(""),
+        compatible: core::str::from_utf8(&data[compatible_offset..compatible_end]).unwrap_or(""),
+        registers: core::slice::from_raw_parts(
+          base.add(register_offset) as *const Range<usize>,
+          record.register_num as usize,
+        ),
+        ports: core::slice::from_raw_parts(base.add(port_offset) as *const Range<usize>, record.port_num as usize),
+        interrupts: core::slice::from_raw_parts(
+          base.add(interrupt_offset) as *const usize,
+          record.interrupt_num as usize,
+        ),
+        driver: record.driver.checked_sub(1).and_then(Driver::from_u32),
+        pci: if record.has_pci != 0 { Some(record.pci.clone()) } else { None },
+      }
+    };
+    self.offset = end;
+    self.remaining -= 1;
+    Some(device)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+
+  use std::vec::Vec;
+
+  use super::*;
+
+  #[test]
+  fn push_then_iterate() {
+    let mut pci = PciFunction {
+      class: 0x01_00_00,
+      segment: 0,
+      vendor_id: 0x1af4,
+      device_id: 0x1042,
+      bus: 0,
+      device: 3,
+      function: 0,
+      bars: Default::default(),
+    };
+    pci.set_bar(4, Some(0x1000_0000..0x1000_4000));
+    let registers = [0x3000_0000..0x3000_1000, 0x4000_0000..0x4000_0010];
+    let ports = 0x3f8..0x400;
+    let interrupts = [33, 34, 35];
+    let devices = [
+      Device {
+        name: "virtio_blk",
+        compatible: "",
+        registers: &registers[..1],
+        ports: &[],
+        interrupts: &interrupts[..1],
+        driver: Some(Driver::VirtioBlk),
+        pci: Some(pci.clone()),
+      },
+      Device {
+        name: "serial",
+        compatible: "ns16550a\0ns16550\0",
+        registers: &registers,
+        ports: core::slice::from_ref(&ports),
+        interrupts: &interrupts,
+        driver: Some(Driver::Ns16550),
+        pci: None,
+      },
+      Device {
+        name: "x",
+        compatible: "",
+        registers: &[],
+        ports: &[],
+        interrupts: &[],
+        driver: None,
+        pci: None,
+      },
+    ];
+    let mut info = PlatformInfo::new();
+    for device in devices.iter() {
+      info.push(device).unwrap();
+    }
+    assert!(info.is_valid());
+    assert_eq!(info.len(), devices.len());
+    let mut n = 0;
+    for (got, expected) in info.devices().zip(devices.iter()) {
+      assert_eq!(got.name, expected.name);
+      assert_eq!(got.compatible, expected.compatible);
+      assert_eq!(got.registers, expected.registers);
+      assert_eq!(got.ports, expected.ports);
+      assert_eq!(got.interrupts, expected.interrupts);
+      assert_eq!(got.driver, expected.driver);
+      assert_eq!(got.pci, expected.pci);
+      n += 1;
+    }
+    assert_eq!(n, devices.len());
+    let pci = info.devices().next().unwrap().pci.unwrap();
+    assert_eq!(pci.bar(4), Some(0x1000_0000..0x1000_4000));
+    assert_eq!(pci.bar_ranges().count(), 1);
+    assert_eq!(info.devices().nth(1).unwrap().compatibles().collect::<Vec<_>>(), ["ns16550a", "ns16550"]);
+  }
+
+  #[test]
+  fn truncated_record_is_rejected() {
+    let mut info = PlatformInfo::new();
+    let device = Device {
+      name: "serial",
+      compatible: "ns16550a",
+      registers: &[],
+      ports: &[],
+      interrupts: &[],
+      driver: None,
+      pci: None,
+    };
+    info.push(&device).unwrap();
+    // claim a name running past the record
+    let offset = size_of::<Header>() + 16;
+    info.data[offset..offset + 4].copy_from_slice(&4096u32.to_ne_bytes());
+    assert!(info.devices().next().is_none());
+  }
+}
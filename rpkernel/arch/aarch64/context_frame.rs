@@ -8,6 +8,10 @@ use unwind::registers::Registers;
 use crate::ContextFrameTrait;
 use crate::syscall::SyscallOutRegisters;
 
+const SPSR_NZCV_MASK: u64 = 0xf << 28;
+// software step bit, see `Aarch64Arch::set_user_single_step`
+const SPSR_SS: u64 = 1 << 21;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct Aarch64ContextFrame {
@@ -154,4 +158,41 @@ impl ContextFrameTrait for Aarch64ContextFrame {
   fn gpr(&self, index: usize) -> usize {
     self.gpr[index] as usize
   }
+
+  fn register(&self, index: usize) -> Option<usize> {
+    use rpabi::debug::*;
+    match index {
+      0..=30 => Some(self.gpr[index] as usize),
+      REGISTER_SP => Some(self.sp as usize),
+      REGISTER_PC => Some(self.elr as usize),
+      REGISTER_STATUS => Some(self.spsr as usize),
+      _ => None,
+    }
+  }
+
+  fn set_register(&mut self, index: usize, value: usize) -> Option<()> {
+    use rpabi::debug::*;
+    match index {
+      0..=30 => self.gpr[index] = value as u64,
+      REGISTER_SP => self.sp = value as u64,
+      REGISTER_PC => self.elr = value as u64,
+      // only condition flags (NZCV) are writable
+      REGISTER_STATUS => self.spsr = (self.spsr & !SPSR_NZCV_MASK) | (value as u64 & SPSR_NZCV_MASK),
+      _ => return None,
+    }
+    Some(())
+  }
+
+  fn single_step(&self) -> bool {
+    self.spsr & SPSR_SS != 0
+  }
+
+  fn set_single_step(&mut self, enable: bool) -> Option<()> {
+    if enable {
+      self.spsr |= SPSR_SS;
+    } else {
+      self.spsr &= !SPSR_SS;
+    }
+    Some(())
+  }
 }
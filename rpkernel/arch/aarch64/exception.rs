@@ -47,6 +47,8 @@ unsafe extern "C" fn lower_aarch64_synchronous(ctx: *mut ContextFrame) {
     crate::kernel::syscall::syscall();
   } else if ESR_EL1.matches_all(ESR_EL1::EC::InstrAbortLowerEL) | ESR_EL1.matches_all(ESR_EL1::EC::DataAbortLowerEL) {
     crate::mm::page_fault::handle();
+  } else if ESR_EL1.matches_all(ESR_EL1::EC::SoftwareStepLowerEL) {
+    crate::kernel::exception::handle_single_step();
   } else {
     let ec = ESR_EL1.read(ESR_EL1::EC);
     error!("lower_aarch64_synchronous: ec {:06b} \n{}", ec, ctx.read());
@@ -99,6 +101,8 @@ pub fn init() {
   }
   let addr: u64 = vectors as usize as u64;
   VBAR_EL1.set(addr);
+  // unlock OS lock so that software step exceptions can be taken from EL0
+  unsafe { core::arch::asm!("msr oslar_el1, xzr"); }
   use aarch64_cpu::asm::barrier::*;
   isb(SY);
 }
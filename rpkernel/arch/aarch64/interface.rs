@@ -59,6 +59,19 @@ impl ArchTrait for Aarch64Arch {
     FAR_EL1.get() as usize
   }
 
+  fn fault_access() -> usize {
+    use rpabi::pager::*;
+    // ISS.WnR of data abort
+    const ESR_ISS_WNR: u64 = 1 << 6;
+    if ESR_EL1.matches_all(ESR_EL1::EC::InstrAbortLowerEL) {
+      ACCESS_EXECUTE
+    } else if ESR_EL1.matches_all(ESR_EL1::EC::DataAbortLowerEL) {
+      if ESR_EL1.get() & ESR_ISS_WNR != 0 { ACCESS_WRITE } else { ACCESS_READ }
+    } else {
+      ACCESS_UNKNOWN
+    }
+  }
+
   fn raw_arch_id() -> usize {
     MPIDR_EL1.get() as usize
   }
@@ -68,4 +81,21 @@ impl ArchTrait for Aarch64Arch {
     TTBR0_EL1.write(TTBR0_EL1::BADDR.val((base >> 1) as u64));
     Self::invalidate_tlb();
   }
+
+  fn set_user_single_step(enable: bool) {
+    // MDSCR_EL1.SS enables software step; PSTATE.SS (restored from SPSR) makes it step one instruction
+    const MDSCR_SS: u64 = 1 << 0;
+    unsafe {
+      let mut mdscr: u64;
+      core::arch::asm!("mrs {}, mdscr_el1", out(reg) mdscr);
+      if enable {
+        mdscr |= MDSCR_SS;
+      } else {
+        mdscr &= !MDSCR_SS;
+      }
+      core::arch::asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr);
+    }
+  }
+
+  fn install_io_port_bitmap(_bitmap: Option<&crate::kernel::address_space::IoPortBitmap>) {}
 }
\ No newline at end of file
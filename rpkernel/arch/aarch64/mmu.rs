@@ -44,6 +44,9 @@ pub unsafe extern "C" fn populate_page_table(pt: &mut PageDirectory) {
   for i in BOARD_DEVICE_MEMORY_RANGE.step_by(ONE_GIGABYTE) {
     pt.0[i / ONE_GIGABYTE] = block_entry(i, true);
   }
+  for i in BOARD_HIGH_DEVICE_MEMORY_RANGE.step_by(ONE_GIGABYTE) {
+    pt.0[i / ONE_GIGABYTE] = block_entry(i, true);
+  }
   for i in BOARD_NORMAL_MEMORY_RANGE.step_by(ONE_GIGABYTE) {
     pt.0[i / ONE_GIGABYTE] = block_entry(i, false);
   }
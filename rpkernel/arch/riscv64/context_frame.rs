@@ -184,5 +184,40 @@ impl crate::kernel::traits::ContextFrameTrait for Riscv64ContextFrame {
   fn gpr(&self, index: usize) -> usize {
     self.gpr[index] as usize
   }
+
+  fn register(&self, index: usize) -> Option<usize> {
+    use rpabi::debug::*;
+    match index {
+      0 => Some(0),
+      1..=31 => Some(self.gpr[index] as usize),
+      REGISTER_PC => Some(self.sepc as usize),
+      _ => None,
+    }
+  }
+
+  fn set_register(&mut self, index: usize, value: usize) -> Option<()> {
+    use rpabi::debug::*;
+    match index {
+      // x0 is hard-wired zero
+      0 => {}
+      1..=31 => self.gpr[index] = value as u64,
+      REGISTER_PC => self.sepc = value as u64,
+      _ => return None,
+    }
+    Some(())
+  }
+
+  fn single_step(&self) -> bool {
+    false
+  }
+
+  fn set_single_step(&mut self, enable: bool) -> Option<()> {
+    // Note: riscv has no supervisor-controlled single step
+    if enable {
+      None
+    } else {
+      Some(())
+    }
+  }
 }
 
@@ -68,8 +68,9 @@ unsafe extern "C" fn exception_entry(ctx: *mut ContextFrame) {
       INTERRUPT_SUPERVISOR_EXTERNAL => {
         let rvic = &crate::driver::INTERRUPT_CONTROLLER;
         if let Some(int) = rvic.fetch() {
-          crate::kernel::interrupt::interrupt(int.0);
+          // Note: PLIC ignores completion of a disabled source, complete before kernel masks the line
           rvic.finish(int.0);
+          crate::kernel::interrupt::interrupt(int.0);
         } else {
           warn!("PLIC report no irq");
         }
@@ -53,6 +53,19 @@ impl ArchTrait for Riscv64Arch {
     STVAL.get() as usize
   }
 
+  fn fault_access() -> usize {
+    use rpabi::pager::*;
+    match SCAUSE.get() {
+      // instruction page fault
+      12 => ACCESS_EXECUTE,
+      // load access/page fault
+      5 | 13 => ACCESS_READ,
+      // store access/page fault
+      7 | 15 => ACCESS_WRITE,
+      _ => ACCESS_UNKNOWN,
+    }
+  }
+
   fn raw_arch_id() -> usize {
     // hartid is m-mode only
     panic!()
@@ -64,4 +77,8 @@ impl ArchTrait for Riscv64Arch {
     );
     riscv::barrier::sfence_vma_all();
   }
+
+  fn set_user_single_step(_enable: bool) {}
+
+  fn install_io_port_bitmap(_bitmap: Option<&crate::kernel::address_space::IoPortBitmap>) {}
 }
\ No newline at end of file
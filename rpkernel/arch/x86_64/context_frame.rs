@@ -4,6 +4,10 @@ use core::panic;
 use crate::ContextFrameTrait;
 use crate::syscall::SyscallOutRegisters;
 
+// CF PF AF ZF SF DF OF
+const RFLAGS_ARITHMETIC_MASK: u64 = 0xcd5;
+const RFLAGS_TF: u64 = 1 << 8;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct X64ContextFrame {
@@ -175,6 +179,73 @@ impl ContextFrameTrait for X64ContextFrame {
   fn gpr(&self, _index: usize) -> usize {
     panic!()
   }
+
+  fn register(&self, index: usize) -> Option<usize> {
+    Some(match index {
+      0 => self.rax,
+      1 => self.rbx,
+      2 => self.rcx,
+      3 => self.rdx,
+      4 => self.rsi,
+      5 => self.rdi,
+      6 => self.rbp,
+      7 => self.rsp,
+      8 => self.r8,
+      9 => self.r9,
+      10 => self.r10,
+      11 => self.r11,
+      12 => self.r12,
+      13 => self.r13,
+      14 => self.r14,
+      15 => self.r15,
+      16 => self.rip,
+      17 => self.rflags,
+      18 => self.cs,
+      19 => self.ss,
+      _ => return None,
+    } as usize)
+  }
+
+  fn set_register(&mut self, index: usize, value: usize) -> Option<()> {
+    let value = value as u64;
+    match index {
+      0 => self.rax = value,
+      1 => self.rbx = value,
+      2 => self.rcx = value,
+      3 => self.rdx = value,
+      4 => self.rsi = value,
+      5 => self.rdi = value,
+      6 => self.rbp = value,
+      7 => self.rsp = value,
+      8 => self.r8 = value,
+      9 => self.r9 = value,
+      10 => self.r10 = value,
+      11 => self.r11 = value,
+      12 => self.r12 = value,
+      13 => self.r13 = value,
+      14 => self.r14 = value,
+      15 => self.r15 = value,
+      16 => self.rip = value,
+      // only arithmetic flags are writable
+      17 => self.rflags = (self.rflags & !RFLAGS_ARITHMETIC_MASK) | (value & RFLAGS_ARITHMETIC_MASK),
+      // cs and ss are read-only
+      _ => return None,
+    }
+    Some(())
+  }
+
+  fn single_step(&self) -> bool {
+    self.rflags & RFLAGS_TF != 0
+  }
+
+  fn set_single_step(&mut self, enable: bool) -> Option<()> {
+    if enable {
+      self.rflags |= RFLAGS_TF;
+    } else {
+      self.rflags &= !RFLAGS_TF;
+    }
+    Some(())
+  }
 }
 
 impl X64ContextFrame {
@@ -1,3 +1,6 @@
+use core::mem::size_of;
+use core::ptr::addr_of_mut;
+
 use spin::Once;
 use x86_64::registers::control::{Cr4, Cr4Flags};
 use x86_64::registers::model_specific::{
@@ -12,6 +15,7 @@ use x86_64::{set_general_handler, VirtAddr};
 
 use super::ContextFrame;
 use crate::driver::{apic, INTERRUPT_CONTROLLER};
+use crate::kernel::address_space::{IoPortBitmap, IO_PORT_NUM};
 use crate::kernel::interrupt::*;
 use crate::MAX_CPU_NUMBER;
 
@@ -35,8 +39,34 @@ impl PerCpu {
   }
 }
 
+// TSS followed by its I/O permission bitmap, a set bit denies user access to the port
+#[repr(C)]
+struct Tss {
+  tss: TaskStateSegment,
+  io_bitmap: IoPortBitmap,
+  // the byte after the bitmap must be all ones
+  io_bitmap_end: u8,
+  // out of the segment limit, whether any port is currently allowed
+  io_bitmap_open: bool,
+}
+
+impl Tss {
+  const fn new() -> Self {
+    Tss {
+      tss: TaskStateSegment::new(),
+      io_bitmap: [u64::MAX; IO_PORT_NUM / 64],
+      io_bitmap_end: u8::MAX,
+      io_bitmap_open: false,
+    }
+  }
+}
+
 static PER_CPU: Once<[PerCpu; MAX_CPU_NUMBER]> = Once::new();
-static PER_CPU_TSS: Once<[TaskStateSegment; MAX_CPU_NUMBER]> = Once::new();
+// Note: each core only touches its own TSS, through `addr_of_mut!`
+static mut PER_CPU_TSS: [Tss; MAX_CPU_NUMBER] = {
+  const TSS_DEFAULT: Tss = Tss::new();
+  [TSS_DEFAULT; MAX_CPU_NUMBER]
+};
 static PER_CPU_TSS_SEL: Once<[SegmentSelector; MAX_CPU_NUMBER]> = Once::new();
 
 const KERNEL_CODE_SEGMENT_SEL: u16 = 0x8;
@@ -45,21 +75,35 @@ const USER_CODE_SEGMENT_SEL: u16 = 0x1B;
 const USER_STACK_SEGMENT_SEL: u16 = 0x23;
 const SYSRET_CODE_SEGMENT_SEL: u16 = 0x2B;
 
+// Note: `Descriptor::tss_segment` limits the segment to the TSS itself, leaving out the I/O permission bitmap
+fn tss_descriptor(tss: &'static Tss) -> Descriptor {
+  let base = tss as *const Tss as u64;
+  // inclusive limit, covering the trailing all-ones byte
+  let limit = (size_of::<TaskStateSegment>() + size_of::<IoPortBitmap>()) as u64;
+  let low = (limit & 0xffff)
+    | ((base & 0xff_ffff) << 16)
+    | (0b1001 << 40) // available 64-bit TSS
+    | (1 << 47) // present
+    | (((base >> 24) & 0xff) << 56);
+  Descriptor::SystemSegment(low, base >> 32)
+}
+
 fn per_cpu_init() -> &'static [PerCpu; MAX_CPU_NUMBER] {
   const PER_CPU_DEFAULT: PerCpu = PerCpu::new();
   let mut per_cpu = [PER_CPU_DEFAULT; MAX_CPU_NUMBER];
-  let mut per_cpu_tss = [TaskStateSegment::new(); MAX_CPU_NUMBER];
   let mut per_cpu_tss_sel = [SegmentSelector::NULL; MAX_CPU_NUMBER];
 
+  let per_cpu_tss = unsafe { &mut *addr_of_mut!(PER_CPU_TSS) };
   for i in 0..MAX_CPU_NUMBER {
     // rustpi uses per-CPU kernel stack (not differentiate for threads)
     // the same stack is use for interrupt, syscall
     let kernel_stack = crate::kernel::stack::stack_of_core(i) as u64;
-    per_cpu_tss[i].privilege_stack_table[0] = VirtAddr::new(kernel_stack); // interrupt kernel stack
-    per_cpu_tss[i].interrupt_stack_table[0] = VirtAddr::new(kernel_stack); // interrupt kernel stack (when IST(0) is set in IDT)
+    per_cpu_tss[i].tss.privilege_stack_table[0] = VirtAddr::new(kernel_stack); // interrupt kernel stack
+    per_cpu_tss[i].tss.interrupt_stack_table[0] = VirtAddr::new(kernel_stack); // interrupt kernel stack (when IST(0) is set in IDT)
+    per_cpu_tss[i].tss.iomap_base = size_of::<TaskStateSegment>() as u16;
   }
 
-  let per_cpu_tss = PER_CPU_TSS.call_once(|| per_cpu_tss);
+  let per_cpu_tss: &'static [Tss; MAX_CPU_NUMBER] = per_cpu_tss;
   for i in 0..MAX_CPU_NUMBER {
     let gdt = &mut per_cpu[i].gdt;
     let kernel_cs = gdt.add_entry(Descriptor::kernel_code_segment());
@@ -76,19 +120,37 @@ fn per_cpu_init() -> &'static [PerCpu; MAX_CPU_NUMBER] {
     let kernel_stack = crate::kernel::stack::stack_of_core(i) as u64;
     per_cpu[i].kernel_rsp = kernel_stack as usize; // syscall kernel stack (retrieved after SWAPGS indexed by GS)
 
-    let tss_selector = gdt.add_entry(Descriptor::tss_segment(&per_cpu_tss[i]));
+    let tss_selector = gdt.add_entry(tss_descriptor(&per_cpu_tss[i]));
     per_cpu_tss_sel[i] = tss_selector;
   }
   PER_CPU_TSS_SEL.call_once(|| per_cpu_tss_sel);
   PER_CPU.call_once(|| per_cpu)
 }
 
+// Load the I/O permission bitmap of the address space being switched to, `None` denies all ports
+pub fn install_io_port_bitmap(bitmap: Option<&IoPortBitmap>) {
+  let tss = unsafe { &mut *addr_of_mut!(PER_CPU_TSS[crate::core_id()]) };
+  match bitmap {
+    Some(bitmap) => {
+      tss.io_bitmap.copy_from_slice(bitmap);
+      tss.io_bitmap_open = true;
+    }
+    None if tss.io_bitmap_open => {
+      tss.io_bitmap.fill(u64::MAX);
+      tss.io_bitmap_open = false;
+    }
+    None => {}
+  }
+}
+
 static IDT: Once<InterruptDescriptorTable> = Once::new();
 
 fn idt_init() -> InterruptDescriptorTable {
   extern "C" {
     // see interrupt.S
     fn timer_interrupt_handler();
+    fn debug_exception_handler();
+    static device_interrupt_handlers: [u64; apic::IO_APIC_INTERRUPT_NUM];
   }
   let mut idt = InterruptDescriptorTable::new();
   set_general_handler!(&mut idt, abort, 0..32);
@@ -108,10 +170,19 @@ fn idt_init() -> InterruptDescriptorTable {
       .page_fault
       .set_handler_fn(page_fault_handler)
       .set_stack_index(0);
+    idt
+      .debug
+      .set_handler_addr(VirtAddr::new(debug_exception_handler as u64))
+      .set_stack_index(0);
     // Set timer handler.
     idt[apic::INT_TIMER]
       .set_handler_addr(VirtAddr::new(timer_interrupt_handler as u64))
       .set_stack_index(0);
+    for (i, handler) in device_interrupt_handlers.iter().enumerate() {
+      idt[apic::IO_APIC_VECTOR_BASE as usize + i]
+        .set_handler_addr(VirtAddr::new(*handler))
+        .set_stack_index(0);
+    }
     idt[apic::ERROR_INTERRUPT_NUMBER as usize]
       .set_handler_fn(error_interrupt_handler)
       .set_stack_index(0);
@@ -176,7 +247,8 @@ pub fn init() {
     flag_mask.insert(RFlags::INTERRUPT_FLAG);
     flag_mask.insert(RFlags::IOPL_HIGH);
     flag_mask.insert(RFlags::IOPL_LOW);
-    // clear RFLAGS:IF and set IOPL=0 when entering kernel by syscall
+    flag_mask.insert(RFlags::TRAP_FLAG);
+    // clear RFLAGS:IF/TF and set IOPL=0 when entering kernel by syscall
     SFMask::write(flag_mask);
   }
 }
@@ -246,6 +318,23 @@ extern "C" fn timer_rust_entry(ctx: *mut ContextFrame) {
   core.clear_context();
 }
 
+#[no_mangle]
+extern "C" fn device_rust_entry(ctx: *mut ContextFrame, int: usize) {
+  let core = crate::kernel::cpu::cpu();
+  core.set_context(ctx);
+  crate::kernel::interrupt::interrupt(int);
+  INTERRUPT_CONTROLLER.finish(int);
+  core.clear_context();
+}
+
+#[no_mangle]
+extern "C" fn debug_rust_entry(ctx: *mut ContextFrame) {
+  let core = crate::kernel::cpu::cpu();
+  core.set_context(ctx);
+  crate::kernel::exception::handle_single_step();
+  core.clear_context();
+}
+
 extern "x86-interrupt" fn error_interrupt_handler(stack_frame: InterruptStackFrame) {
   error!("APIC LVT Error Interrupt");
   error!("ESR: {:#?}", unsafe { apic::local_apic().error_flags() });
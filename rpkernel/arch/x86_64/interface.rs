@@ -54,6 +54,11 @@ impl ArchTrait for X64Arch {
     x86_64::registers::control::Cr2::read_raw() as usize
   }
 
+  fn fault_access() -> usize {
+    // Note: page fault error code is not kept after the fault
+    rpabi::pager::ACCESS_UNKNOWN
+  }
+
   fn raw_arch_id() -> usize {
     todo!()
   }
@@ -70,4 +75,11 @@ impl ArchTrait for X64Arch {
       );
     };
   }
+
+  // Note: single step is armed by RFLAGS.TF in the user context frame
+  fn set_user_single_step(_enable: bool) {}
+
+  fn install_io_port_bitmap(bitmap: Option<&crate::kernel::address_space::IoPortBitmap>) {
+    super::exception::install_io_port_bitmap(bitmap)
+  }
 }
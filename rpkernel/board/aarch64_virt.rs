@@ -1,10 +1,10 @@
 use core::ops::Range;
 use hardware::pl011::Pl011Mmio;
-use spin::Once;
+use spin::{Mutex, Once};
 use tock_registers::interfaces::{Readable, Writeable};
 
 use crate::driver::gic::INT_TIMER;
-use crate::kernel::device::{device_from_fdt_node, PlatformInfo};
+use crate::kernel::device::{device_add, device_add_fdt_node, virtio_mmio_driver, PlatformInfo};
 use crate::kernel::interrupt::InterruptController;
 use crate::kernel::print::DebugUart;
 use crate::kernel::traits::ArchTrait;
@@ -12,6 +12,8 @@ use crate::Address;
 
 pub const BOARD_DEVICE_MEMORY_RANGE: Range<usize> = 0x0000_0000..0x4000_0000;
 pub const BOARD_NORMAL_MEMORY_RANGE: Range<usize> = 0x4000_0000..0x1_0000_0000;
+// Note: covers the high PCIe ECAM window at 0x40_1000_0000
+pub const BOARD_HIGH_DEVICE_MEMORY_RANGE: Range<usize> = 0x40_0000_0000..0x40_4000_0000;
 
 static CPU_NUMBER: Once<usize> = Once::new();
 pub fn cpu_number() -> usize {
@@ -20,6 +22,7 @@ pub fn cpu_number() -> usize {
 
 pub fn init(fdt: usize) -> (Range<usize>, Range<usize>) {
   // println!("FDT phyaddr {:x}", fdt);
+  let fdt_pa = fdt;
   let fdt = unsafe { core::slice::from_raw_parts(fdt.pa2kva() as *const u8, 1048576) };
   let fdt = fdt::Fdt::new(fdt).unwrap();
   // println!("FDT model {}", fdt.root().model());
@@ -72,36 +75,31 @@ pub fn init(fdt: usize) -> (Range<usize>, Range<usize>) {
       .call_once(|| Pl011Mmio::new(start_addr.pa2kva()))
       .init();
   }
-  PLATFORM_INFO.call_once(|| {
-    let mut r = PlatformInfo::default();
-    if let Some(x) = fdt.find_compatible(&["virtio,mmio"]) {
-      // add first virtio,mmio
-      r.devices[0] = Some(device_from_fdt_node(
-        &fdt,
-        &x,
-        Some(rpabi::platform::Driver::VirtioBlk),
-      ));
+  {
+    let mut info = PLATFORM_INFO.lock();
+    for node in fdt.all_nodes() {
+      let compatible = match node.compatible() {
+        Some(compatible) => compatible,
+        None => continue,
+      };
+      let driver = if compatible.all().any(|c| c == "virtio,mmio") {
+        virtio_mmio_driver(&node)
+      } else if compatible.all().any(|c| c == "arm,pl031") {
+        Some(rpabi::platform::Driver::Pl031)
+      } else if compatible.all().any(|c| c == "arm,pl011") {
+        Some(rpabi::platform::Driver::Pl011)
+      } else {
+        continue;
+      };
+      device_add_fdt_node(&mut info, &fdt, &node, driver);
     }
-    if let Some(x) = fdt.find_compatible(&["arm,pl031"]) {
-      r.devices[1] = Some(device_from_fdt_node(
-        &fdt,
-        &x,
-        Some(rpabi::platform::Driver::Pl031),
-      ));
-    }
-    if let Some(x) = fdt.find_compatible(&["arm,pl011"]) {
-      r.devices[2] = Some(device_from_fdt_node(
-        &fdt,
-        &x,
-        Some(rpabi::platform::Driver::Pl011),
-      ));
-    }
-    r
-  });
+    crate::kernel::pci::enumerate_fdt(&fdt, |dev| device_add(&mut info, dev));
+  }
   match fdt.find_node("/").unwrap().interrupt_parent() {
     Some(node) => {
       let comp = node.compatible().unwrap().first();
       match comp {
+        #[cfg(not(feature = "gicv3"))]
         "arm,cortex-a15-gic" => {
           let mut reg_iter = node.reg().unwrap();
           let gicd_region = reg_iter.next().unwrap();
@@ -112,6 +110,7 @@ pub fn init(fdt: usize) -> (Range<usize>, Range<usize>) {
             crate::driver::gic::Gic::new(gicd_base, gicc_base)
           });
         },
+        #[cfg(feature = "gicv3")]
         "arm,gic-v3" => {
           assert_eq!(node.property("#redistributor-regions").unwrap().as_usize().unwrap(), 1);
           let mut reg_iter = node.reg().unwrap();
@@ -119,8 +118,12 @@ pub fn init(fdt: usize) -> (Range<usize>, Range<usize>) {
           let gicd_base = gicd_region.starting_address as usize;
           let gicr_region = reg_iter.next().unwrap();
           let gicr_base = gicr_region.starting_address as usize;
+          let its_base = fdt.find_compatible(&["arm,gic-v3-its"])
+            .and_then(|its| its.reg())
+            .and_then(|mut reg_iter| reg_iter.next())
+            .map(|its_region| its_region.starting_address as usize);
           crate::driver::INTERRUPT_CONTROLLER.call_once(|| {
-            crate::driver::gic::Gic::new(gicd_base, gicr_base)
+            crate::driver::gic::Gic::new(gicd_base, gicr_base, its_base)
           });
         }
         _ => panic!(),
@@ -128,7 +131,12 @@ pub fn init(fdt: usize) -> (Range<usize>, Range<usize>) {
     },
     None => panic!(),
   }
-  (heap_start..heap_end, paged_start..paged_end)
+  let paged = crate::kernel::device::device_tree_reserve(
+    fdt_pa..(fdt_pa + fdt.total_size()),
+    &(heap_start..heap_end),
+    paged_start..paged_end,
+  );
+  (heap_start..heap_end, paged)
 }
 
 pub fn init_post_heap_setup(fdt: usize) {}
@@ -201,7 +209,7 @@ pub fn launch_other_cores() {
 //   compatible = "arm,pl011\0arm,primecell";
 // };
 
-pub static PLATFORM_INFO: Once<PlatformInfo> = Once::new();
+pub static PLATFORM_INFO: Mutex<PlatformInfo> = Mutex::new(PlatformInfo::new());
 
 pub static DEBUG_UART: Once<Pl011Mmio> = Once::new();
 
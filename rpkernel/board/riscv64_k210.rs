@@ -1,8 +1,8 @@
 use core::ops::Range;
-use spin::Once;
+use spin::{Mutex, Once};
 use tock_registers::interfaces::{Readable, Writeable};
 
-use crate::kernel::device::{device_new, PlatformInfo};
+use crate::kernel::device::{device_add, Device, PlatformInfo};
 use crate::kernel::interrupt::InterruptController;
 use crate::kernel::print::DebugUart;
 use crate::kernel::traits::Address;
@@ -35,18 +35,26 @@ pub fn init(_fdt: usize) -> (Range<usize>, Range<usize>) {
 
   DEBUG_UART.call_once(|| K210Uart).init();
 
-  PLATFORM_INFO.call_once(|| PlatformInfo {
-    devices: [
-      Some(device_new("GPIOHS", 0x3800_1000..0x3800_2000, None, None)),
-      Some(device_new("SPI0", 0x5200_0000..0x5200_1000, None, None)),
-      Some(device_new("DMAC", 0x5000_0000..0x5000_1000, None, None)),
-      Some(device_new("SYSCTL", 0x5044_0000..0x5044_1000, None, None)),
-      Some(device_new("FPIOA", 0x502B_0000..0x502B_1000, None, None)),
-      None,
-      None,
-      None,
-    ],
-  });
+  {
+    let mut info = PLATFORM_INFO.lock();
+    for (name, register) in [
+      ("GPIOHS", 0x3800_1000..0x3800_2000),
+      ("SPI0", 0x5200_0000..0x5200_1000),
+      ("DMAC", 0x5000_0000..0x5000_1000),
+      ("SYSCTL", 0x5044_0000..0x5044_1000),
+      ("FPIOA", 0x502B_0000..0x502B_1000),
+    ] {
+      device_add(&mut info, &Device {
+        name,
+        compatible: "",
+        registers: &[register],
+        ports: &[],
+        interrupts: &[],
+        driver: None,
+        pci: None,
+      });
+    }
+  }
   (heap_start..heap_end, paged_start..paged_end)
 }
 
@@ -82,7 +90,7 @@ pub unsafe extern "C" fn hart_spin(core_id: usize) {
   crate::main(core_id, 0);
 }
 
-pub static PLATFORM_INFO: Once<PlatformInfo> = Once::new();
+pub static PLATFORM_INFO: Mutex<PlatformInfo> = Mutex::new(PlatformInfo::new());
 
 pub static DEBUG_UART: Once<K210Uart> = Once::new();
 
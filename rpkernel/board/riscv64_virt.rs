@@ -2,10 +2,10 @@ use core::ops::Range;
 use core::sync::atomic::{AtomicBool, Ordering};
 use hardware::ns16550::*;
 use riscv::regs::SSCRATCH;
-use spin::Once;
+use spin::{Mutex, Once};
 use tock_registers::interfaces::{Readable, Writeable};
 
-use crate::kernel::device::{device_from_fdt_node, PlatformInfo};
+use crate::kernel::device::{device_add, device_add_fdt_node, virtio_mmio_driver, PlatformInfo};
 use crate::kernel::interrupt::InterruptController;
 use crate::kernel::print::DebugUart;
 use crate::kernel::traits::*;
@@ -18,6 +18,7 @@ pub fn cpu_number() -> usize {
 
 pub fn init(fdt: usize) -> (Range<usize>, Range<usize>) {
   // println!("FDT phyaddr {:x}", fdt);
+  let fdt_pa = fdt;
   let fdt = unsafe { core::slice::from_raw_parts(fdt.pa2kva() as *const u8, 8192) };
   let fdt = fdt::Fdt::new(fdt).unwrap();
   // println!("FDT model {}", fdt.root().model());
@@ -61,33 +62,32 @@ pub fn init(fdt: usize) -> (Range<usize>, Range<usize>) {
       .init();
   }
 
-  PLATFORM_INFO.call_once(|| {
-    let mut r = PlatformInfo::default();
-    if let Some(x) = fdt.find_node("/soc/virtio_mmio@10001000") {
-      // add first virtio,mmio
-      r.devices[0] = Some(device_from_fdt_node(
-        &fdt,
-        &x,
-        Some(rpabi::platform::Driver::VirtioBlk),
-      ));
+  {
+    let mut info = PLATFORM_INFO.lock();
+    for node in fdt.all_nodes() {
+      let compatible = match node.compatible() {
+        Some(compatible) => compatible,
+        None => continue,
+      };
+      let driver = if compatible.all().any(|c| c == "virtio,mmio") {
+        virtio_mmio_driver(&node)
+      } else if compatible.all().any(|c| c == "google,goldfish-rtc") {
+        Some(rpabi::platform::Driver::GoldfishRtc)
+      } else if compatible.all().any(|c| c == "ns16550a") {
+        Some(rpabi::platform::Driver::Ns16550)
+      } else {
+        continue;
+      };
+      device_add_fdt_node(&mut info, &fdt, &node, driver);
     }
-    if let Some(x) = fdt.find_compatible(&["google,goldfish-rtc"]) {
-      r.devices[1] = Some(device_from_fdt_node(
-        &fdt,
-        &x,
-        Some(rpabi::platform::Driver::GoldfishRtc),
-      ));
-    }
-    if let Some(x) = fdt.find_compatible(&["ns16550a"]) {
-      r.devices[2] = Some(device_from_fdt_node(
-        &fdt,
-        &x,
-        Some(rpabi::platform::Driver::Ns16550),
-      ));
-    }
-    r
-  });
-  (heap_start..heap_end, paged_start..paged_end)
+    crate::kernel::pci::enumerate_fdt(&fdt, |dev| device_add(&mut info, dev));
+  }
+  let paged = crate::kernel::device::device_tree_reserve(
+    fdt_pa..(fdt_pa + fdt.total_size()),
+    &(heap_start..heap_end),
+    paged_start..paged_end,
+  );
+  (heap_start..heap_end, paged)
 }
 
 pub fn init_post_heap_setup(fdt: usize) {}
@@ -161,7 +161,7 @@ pub unsafe extern "C" fn hart_spin(core_id: usize, fdt: usize) {
 //   compatible = "ns16550a";
 // };
 
-pub static PLATFORM_INFO: Once<PlatformInfo> = Once::new();
+pub static PLATFORM_INFO: Mutex<PlatformInfo> = Mutex::new(PlatformInfo::new());
 
 pub static DEBUG_UART: Once<Ns16550Mmio> = Once::new();
 
@@ -1,19 +1,20 @@
 use core::ptr::NonNull;
 
 use acpi::{
+  fadt::Fadt,
+  hpet::HpetTable,
   madt::{Madt, MadtEntry},
-  AcpiHandler, AcpiTables, PhysicalMapping,
+  mcfg::{Mcfg, PciConfigRegions},
+  AcpiHandler, AcpiTable, AcpiTables, HpetInfo, PhysicalMapping,
 };
-use rpabi::{
-  platform::{Device, PlatformInfo},
-  X64BootData,
-};
-use spin::Once;
+use rpabi::X64BootData;
+use spin::{Mutex, Once};
 use x86_64::instructions::port::Port;
 
 use crate::{
   arch::PAGE_SIZE,
   kernel::{
+    device::{device_add, Device, PlatformInfo},
     interrupt::InterruptController,
     traits::Address,
   },
@@ -68,7 +69,14 @@ pub fn launch_other_cores() {
   // }
 }
 
-pub static PLATFORM_INFO: Once<PlatformInfo> = Once::new();
+pub static PLATFORM_INFO: Mutex<PlatformInfo> = Mutex::new(PlatformInfo::new());
+
+const COM1: u16 = 0x3f8;
+const COM1_ISA_INTERRUPT: u8 = 4;
+const HPET_REGISTER_SIZE: usize = 0x400;
+const ACPI_RSDP_V2_SIZE: usize = 36;
+const ACPI_RSDP_XSDT_OFFSET: usize = 24;
+const ACPI_SDT_LENGTH_OFFSET: usize = 4;
 
 pub struct I8250 {}
 
@@ -78,7 +86,6 @@ impl crate::kernel::print::DebugUart for I8250 {
   fn init(&self) {}
 
   fn putc(&self, c: u8) {
-    const COM1: u16 = 0x3f8;
     unsafe {
       let mut thr_port = Port::new(COM1 + 0);
       let mut lsr_port = Port::new(COM1 + 5);
@@ -94,7 +101,6 @@ impl crate::kernel::print::DebugUart for I8250 {
   }
 
   fn getc(&self) -> Option<u8> {
-    const COM1: u16 = 0x3f8;
     unsafe {
       let mut rhr_port = Port::new(COM1 + 0);
       let mut lsr_port = Port::new(COM1 + 5);
@@ -127,16 +133,6 @@ pub fn init(boot_data: usize) -> (core::ops::Range<usize>, core::ops::Range<usiz
   let paged_start = heap_end;
   let paged_end = boot_data.free_mem_start + boot_data.free_mem_count * PAGE_SIZE;
 
-  PLATFORM_INFO.call_once(|| {
-    let mut r = PlatformInfo::default();
-    r.devices[0] = Some(Device {
-      name: [0; rpabi::platform::DEVICE_NAME_LEN],
-      register: 0..0,
-      interrupt: None,
-      driver: Some(rpabi::platform::Driver::Ramdisk),
-    });
-    r
-  });
   // TODO: we are not ready to boot APs
   CPU_NUMBER.call_once(|| 1);
   (heap_start..heap_end, paged_start..paged_end)
@@ -166,7 +162,7 @@ pub fn init_post_heap_setup(boot_data: usize) {
     }
     let platform_info = acpi_table.platform_info().unwrap();
 
-    if let acpi::InterruptModel::Apic(apic) = platform_info.interrupt_model {
+    if let acpi::InterruptModel::Apic(apic) = &platform_info.interrupt_model {
       let local_apic_pa = apic.local_apic_address;
       info!("local apic pa {:X}", local_apic_pa);
       for io_apic in apic.io_apics.iter() {
@@ -175,6 +171,86 @@ pub fn init_post_heap_setup(boot_data: usize) {
     } else {
       panic!();
     }
+    // Note: ISA interrupts are identity mapped to GSIs unless overridden in MADT
+    let isa_gsi = |isa: u8| match &platform_info.interrupt_model {
+      acpi::InterruptModel::Apic(apic) => apic
+        .interrupt_source_overrides
+        .iter()
+        .find(|o| o.isa_source == isa)
+        .map_or(isa as usize, |o| o.global_system_interrupt as usize),
+      _ => isa as usize,
+    };
+    // Note: PCI enumeration needs ECAM mapped by `map_non_cache_region_boot` and MCFG parsed on heap
+    {
+      let mut info = PLATFORM_INFO.lock();
+      device_add(&mut info, &Device {
+        name: "ramdisk",
+        compatible: "",
+        registers: &[],
+        ports: &[],
+        interrupts: &[],
+        driver: Some(rpabi::platform::Driver::Ramdisk),
+        pci: None,
+      });
+      // Note: legacy devices sit at fixed ISA addresses, AML (_HID, _CRS) is not interpreted
+      if com_present(COM1) {
+        device_add(&mut info, &Device {
+          name: "serial@3f8",
+          compatible: "PNP0501",
+          registers: &[],
+          ports: &[(COM1 as usize)..(COM1 as usize + 8)],
+          interrupts: &[isa_gsi(COM1_ISA_INTERRUPT)],
+          // Note: kernel keeps printing to COM1, the user-space driver owns its receiver and interrupt
+          driver: Some(rpabi::platform::Driver::Ns16550),
+          pci: None,
+        });
+      }
+      match HpetInfo::new(&acpi_table) {
+        Ok(hpet) => device_add(&mut info, &Device {
+          name: "hpet",
+          compatible: "PNP0103",
+          registers: &[hpet.base_address..(hpet.base_address + HPET_REGISTER_SIZE)],
+          ports: &[],
+          interrupts: &[],
+          driver: None,
+          pci: None,
+        }),
+        Err(e) => warn!("no hpet {:?}", e),
+      }
+      match PciConfigRegions::new(&acpi_table) {
+        Ok(regions) => {
+          // Note: legacy INTx routing is described by AML (_PRT), which is not interpreted.
+          //   Drivers of these devices are expected to poll or use MSI.
+          crate::kernel::pci::enumerate(
+            0,
+            |bus, device, function| {
+              regions
+                .physical_address(0, bus, device, function)
+                .map(|pa| pa as usize)
+            },
+            None,
+            |_, _, _, _| None,
+            |dev| device_add(&mut info, dev),
+          );
+        }
+        Err(e) => warn!("no pci config regions {:?}", e),
+      }
+    }
+
+    // Note: RSDP, XSDT and tables user space drivers may need are passed to trusted
+    let rsdp = boot_data.acpi2_table_pa;
+    let mut tables = vec![rsdp..(rsdp + ACPI_RSDP_V2_SIZE)];
+    let xsdt = ((rsdp + ACPI_RSDP_XSDT_OFFSET).pa2kva() as *const u64).read_unaligned() as usize;
+    if xsdt != 0 {
+      let length = ((xsdt + ACPI_SDT_LENGTH_OFFSET).pa2kva() as *const u32).read_unaligned() as usize;
+      tables.push(xsdt..(xsdt + length));
+    }
+    tables.extend(table_region::<Fadt>(&acpi_table));
+    tables.extend(table_region::<Madt>(&acpi_table));
+    tables.extend(table_region::<Mcfg>(&acpi_table));
+    tables.extend(table_region::<HpetTable>(&acpi_table));
+    crate::kernel::device::acpi_tables_register(rsdp, tables);
+
     let processor_info = platform_info.processor_info.unwrap();
     let ap_number = processor_info.application_processors.len();
     info!(
@@ -197,6 +273,20 @@ pub fn init_post_heap_setup(boot_data: usize) {
   };
 }
 
+fn table_region<T: AcpiTable>(tables: &AcpiTables<AcpiHandlerImpl>) -> Option<core::ops::Range<usize>> {
+  let mapping = tables.find_table::<T>().ok()?;
+  Some(mapping.physical_start()..(mapping.physical_start() + mapping.region_length()))
+}
+
+// Probe a 16550 by its scratch register
+fn com_present(base: u16) -> bool {
+  unsafe {
+    let mut scratch = Port::<u8>::new(base + 7);
+    scratch.write(0x5a);
+    scratch.read() == 0x5a
+  }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct AcpiHandlerImpl;
 
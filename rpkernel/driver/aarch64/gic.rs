@@ -4,7 +4,10 @@ use tock_registers::registers::*;
 use tock_registers::*;
 
 use crate::core_id;
-use crate::kernel::interrupt::{InterProcessorInterruptController, InterruptController};
+use crate::kernel::interrupt::{
+  InterProcessorInterruptController, InterruptController, InterruptPriority, TriggerMode,
+  INTERRUPT_PRIORITY_DEFAULT, INTERRUPT_PRIORITY_MAX,
+};
 
 const GIC_INTERRUPT_NUM: usize = 1024;
 const GIC_SGI_NUM: usize = 16;
@@ -12,6 +15,18 @@ const GIC_1_BIT_NUM: usize = GIC_INTERRUPT_NUM / 32;
 const GIC_8_BIT_NUM: usize = GIC_INTERRUPT_NUM * 8 / 32;
 const GIC_2_BIT_NUM: usize = GIC_INTERRUPT_NUM * 2 / 32;
 
+// upper bound of interrupt numbers
+pub const INTERRUPT_NUMBER_MAX: usize = GIC_INTERRUPT_NUM;
+
+// GIC priority: lower value is more urgent, only the upper bits are guaranteed to be implemented
+fn gic_priority(priority: InterruptPriority) -> u8 {
+  ((INTERRUPT_PRIORITY_MAX - priority.min(INTERRUPT_PRIORITY_MAX)) << 5) as u8
+}
+
+fn gic_priority_word(priority: InterruptPriority) -> u32 {
+  u32::from_ne_bytes([gic_priority(priority); 4])
+}
+
 register_bitfields! {
   u32,
   pub GICD_SGIR [
@@ -143,9 +158,14 @@ impl GicDistributor {
       self.ICPENDR[i].set(u32::MAX);
       self.ICACTIVER[i].set(u32::MAX);
     }
+    // Note: SPIs are routed to the initializing core (core 0) until `set_affinity`
+    let cpu_if_id = self.ITARGETSR[0].get() & 0xff;
     for i in 8usize..(max_spi as usize * 8 / 32) {
-      self.IPRIORITYR[i].set(u32::MAX);
-      self.ITARGETSR[i].set(u32::MAX);
+      self.IPRIORITYR[i].set(gic_priority_word(INTERRUPT_PRIORITY_DEFAULT));
+      self.ITARGETSR[i].set(u32::from_ne_bytes([cpu_if_id as u8; 4]));
+    }
+    for i in 2usize..(max_spi as usize * 2 / 32) {
+      self.ICFGR[i].set(0xaaaaaaaa); // All set to edge
     }
     self.CTLR.set(1);
   }
@@ -158,7 +178,7 @@ impl GicDistributor {
       self.CPENDSGIR[i].set(u32::MAX);
     }
     for i in 0..8 {
-      self.IPRIORITYR[i].set(u32::MAX);
+      self.IPRIORITYR[i].set(gic_priority_word(INTERRUPT_PRIORITY_DEFAULT));
     }
     let cpu_if_id = (self.ITARGETSR[0].get() & 0xff) as u8;
     info!("cpu_if_id {}", cpu_if_id);
@@ -236,18 +256,8 @@ impl InterruptController for Once<Gic> {
   }
 
   fn enable(&self, int: Interrupt) {
-    let core_id = core_id();
     let gicd = &self.get().unwrap().d;
     gicd.set_enable(int);
-    gicd.set_priority(int, 0x7f);
-    if int < 16 {
-      return;
-    }
-    if int >= 32 {
-      // when enabling SPIs, set it to be edge triggered and targeting current CPU
-      gicd.set_config(int, true);
-      gicd.set_target(int, (1 << core_id) as u8);
-    }
   }
 
   fn disable(&self, int: Interrupt) {
@@ -255,6 +265,27 @@ impl InterruptController for Once<Gic> {
     gicd.clear_enable(int);
   }
 
+  fn set_priority(&self, int: Interrupt, priority: InterruptPriority) {
+    // Note: banked registers of SGIs and PPIs only affect current core
+    let gicd = &self.get().unwrap().d;
+    gicd.set_priority(int, gic_priority(priority));
+  }
+
+  fn set_trigger(&self, int: Interrupt, trigger: TriggerMode) {
+    if int < 32 {
+      // trigger mode of SGIs and PPIs is fixed
+      return;
+    }
+    let gicd = &self.get().unwrap().d;
+    gicd.set_config(int, trigger == TriggerMode::Edge);
+  }
+
+  fn set_affinity(&self, int: Interrupt, core_id: usize) {
+    let gicd = &self.get().unwrap().d;
+    let cpu_if_id = gicd.cpu_if_id.lock()[core_id];
+    gicd.set_target(int, cpu_if_id);
+  }
+
   fn fetch(&self) -> Option<(Interrupt, usize)> {
     let gicc = &self.get().unwrap().c;
     let iar = gicc.IAR.extract();
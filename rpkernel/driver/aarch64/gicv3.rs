@@ -6,7 +6,12 @@ use tock_registers::registers::*;
 use tock_registers::*;
 
 use crate::core_id;
-use crate::kernel::interrupt::{InterProcessorInterruptController, InterruptController};
+use super::its::{GicIts, LPI_BASE, LPI_NUM};
+use crate::kernel::interrupt::{
+  Error, InterProcessorInterruptController, InterruptController, InterruptPriority, TriggerMode,
+  INTERRUPT_PRIORITY_DEFAULT, INTERRUPT_PRIORITY_MAX,
+};
+use rpabi::syscall::error::ERROR_INVARG;
 
 const GIC_INTERRUPT_NUM: usize = 1024;
 const GIC_SGI_NUM: usize = 16;
@@ -15,6 +20,31 @@ const GIC_1_BIT_NUM: usize = GIC_INTERRUPT_NUM / 32;
 const GIC_8_BIT_NUM: usize = GIC_INTERRUPT_NUM * 8 / 32;
 const GIC_2_BIT_NUM: usize = GIC_INTERRUPT_NUM * 2 / 32;
 
+// upper bound of interrupt numbers, including LPIs
+pub const INTERRUPT_NUMBER_MAX: usize = LPI_BASE + LPI_NUM;
+
+// GIC priority: lower value is more urgent, kept below the priority mask (0xf0) of the cpu interface
+fn gic_priority(priority: InterruptPriority) -> u8 {
+  ((INTERRUPT_PRIORITY_MAX - priority.min(INTERRUPT_PRIORITY_MAX)) << 5) as u8
+}
+
+fn gic_priority_word(priority: InterruptPriority) -> u32 {
+  u32::from_ne_bytes([gic_priority(priority); 4])
+}
+
+fn set_byte(reg: &ReadWrite<u32>, int: usize, value: u8) {
+  let offset = (int % 4) * 8;
+  let mask: u32 = 0xff << offset;
+  reg.set((reg.get() & !mask) | ((value as u32) << offset));
+}
+
+fn set_trigger_bits(reg: &ReadWrite<u32>, int: usize, trigger: TriggerMode) {
+  let offset = (int % 16) * 2;
+  let mask: u32 = 0b11 << offset;
+  let bits: u32 = if trigger == TriggerMode::Edge { 0b10 } else { 0b00 };
+  reg.set((reg.get() & !mask) | (bits << offset));
+}
+
 register_bitfields! {
   u32,
   pub GICD_TYPE [
@@ -140,7 +170,7 @@ impl GicDistributor {
       self.IntClearActive[i].set(u32::MAX);
     }
     for i in 8..8 * (itlines_num + 1) {
-      self.IntPriority[i].set(0xa0_a0_a0_a0);
+      self.IntPriority[i].set(gic_priority_word(INTERRUPT_PRIORITY_DEFAULT));
     }
     for i in 2..2 * (itlines_num + 1) {
       self.IntConfig[i].set(0xaaaaaaaa); // All set to edge
@@ -152,8 +182,9 @@ impl GicDistributor {
     debug!("gicd_ctrl {:b}", self.Control.get());
 
     let def_affinity = 0; // MPIDR -> aff
-    for i in 32..line_num {
-      self.IntRoute[i].set(def_affinity);
+    // Note: `IntRoute` starts from the first SPI
+    for i in 32..line_num.min(GIC_INTERRUPT_NUM) {
+      self.IntRoute[i - 32].set(def_affinity);
     }
   }
 
@@ -172,6 +203,19 @@ impl GicDistributor {
     self.wait_for_rwp();
   }
 
+  fn set_priority(&self, int: usize, priority: u8) {
+    set_byte(&self.IntPriority[int / 4], int, priority);
+  }
+
+  fn set_trigger(&self, int: usize, trigger: TriggerMode) {
+    set_trigger_bits(&self.IntConfig[int / 16], int, trigger);
+  }
+
+  fn set_route(&self, int: usize, affinity: u64) {
+    assert!(int >= 32 && int < GIC_INTERRUPT_NUM);
+    self.IntRoute[int - 32].set(affinity);
+  }
+
   fn wait_for_rwp(&self) {
     loop {
       if (self.Control.get() & (1 << 3/* RWP */)) == 0 {
@@ -182,6 +226,7 @@ impl GicDistributor {
 }
 
 struct GicRedistributor {
+  rd_pa: usize,
   rd_base: usize,
   sgi_base: usize,
 }
@@ -223,7 +268,7 @@ impl GicRedistributor {
     self.sgi().IntClearActive0.set(u32::MAX);
     self.sgi().IntClearEnable0.set(u32::MAX);
     for i in 0..8 {
-      self.sgi().IntPriority[i].set(0xa0_a0_a0_a0);
+      self.sgi().IntPriority[i].set(gic_priority_word(INTERRUPT_PRIORITY_DEFAULT));
     }
     self.wait_for_rwp();
     // info!("cfg 0 {:x} cfg 1 {:x}", self.sgi().IntConfig0.get(), self.sgi().IntConfig1.get());
@@ -243,14 +288,32 @@ impl GicRedistributor {
     self.wait_for_rwp();
   }
 
-  // fn set_priority(&self, int: usize, priority: u8) {
-  //   assert!(int < 32);
-  //   let reg_idx = int / 8;
-  //   let offset = (int % 4) * 8;
-  //   let mask: u32 = 0xff << offset;
-  //   let prev = self.sgi().IntPriority[reg_idx].get();
-  //   self.sgi().IntPriority[reg_idx].set((prev & (!mask)) | (((priority as u32) << offset) & mask))
-  // }
+  fn set_priority(&self, int: usize, priority: u8) {
+    assert!(int < 32);
+    set_byte(&self.sgi().IntPriority[int / 4], int, priority);
+  }
+
+  fn set_trigger(&self, int: usize, trigger: TriggerMode) {
+    assert!(int >= 16 && int < 32);
+    set_trigger_bits(&self.sgi().IntConfig1, int, trigger);
+  }
+
+  // `GICR_TYPER.Processor_Number`
+  fn processor_number(&self) -> usize {
+    ((self.rd().Type.get() >> 8) & 0xffff) as usize
+  }
+
+  fn enable_lpi(&self, config_table: u64, pending_table: u64) {
+    self.rd().PropertiesBaseAddr.set(config_table);
+    self.rd().LpiPendingTableBaseAddr.set(pending_table);
+    self.rd().Control.set(self.rd().Control.get() | 1 /* EnableLPIs */);
+  }
+
+  // affinity value in the layout of `GICD_IROUTER`
+  fn affinity(&self) -> u64 {
+    let aff = (self.rd().Type.get() >> 32) as u64;
+    (aff & 0xff_ffff) | ((aff >> 24) << 32)
+  }
 
   fn wait_for_rwp(&self) {
     loop {
@@ -338,24 +401,26 @@ pub struct Gic {
   d: GicDistributor,
   r: [Option<GicRedistributor>; crate::MAX_CPU_NUMBER],
   c: GicCpuInterface,
+  its: Option<GicIts>,
 }
 
 const NONE_GICR: Option<GicRedistributor> = None;
 
 impl Gic {
-  pub fn new(gicd_base: usize, gicr_base: usize) -> Self {
+  pub fn new(gicd_base: usize, gicr_base: usize, its_base: Option<usize>) -> Self {
     let mut r = Gic {
       d: GicDistributor {
         base_addr: gicd_base.pa2kva(),
       },
       r: [NONE_GICR; crate::MAX_CPU_NUMBER],
       c: GicCpuInterface {},
+      its: its_base.map(|pa| GicIts::new(pa, pa.pa2kva())),
     };
-    let gicr_base = gicr_base.pa2kva();
     for i in 0..crate::cpu_number() {
       r.r[i] = Some(GicRedistributor {
-        rd_base: gicr_base + i * 0x20000,
-        sgi_base: gicr_base + i * 0x20000 + 0x10000,
+        rd_pa: gicr_base + i * 0x20000,
+        rd_base: gicr_base.pa2kva() + i * 0x20000,
+        sgi_base: gicr_base.pa2kva() + i * 0x20000 + 0x10000,
       })
     }
     r
@@ -368,9 +433,17 @@ impl InterruptController for Once<Gic> {
     let core_id = core_id();
     if core_id == 0 {
       gic.d.init();
+      if let Some(its) = &gic.its {
+        its.init(gic_priority(INTERRUPT_PRIORITY_DEFAULT));
+      }
     }
     crate::util::barrier();
-    gic.r[core_id].as_ref().unwrap().init();
+    let gicr = gic.r[core_id].as_ref().unwrap();
+    gicr.init();
+    if let Some(its) = &gic.its {
+      gicr.enable_lpi(its.config_table_pa(), its.pending_table_pa(core_id));
+      its.map_collection(core_id, gicr.rd_pa, gicr.processor_number());
+    }
     gic.c.init();
   }
 
@@ -388,10 +461,16 @@ impl InterruptController for Once<Gic> {
         // PPI
         gicr.set_enable(int);
       }
-      _ => {
+      32..=1019 => {
         // SPI
         gicd.set_enable(int);
       }
+      LPI_BASE.. => {
+        if let Some(its) = &gic.its {
+          its.enable(int);
+        }
+      }
+      _ => {}
     }
   }
 
@@ -409,16 +488,75 @@ impl InterruptController for Once<Gic> {
         // PPI
         gicr.clear_enable(int);
       }
-      _ => {
+      32..=1019 => {
         // SPI
         gicd.clear_enable(int);
       }
+      LPI_BASE.. => {
+        if let Some(its) = &gic.its {
+          its.disable(int);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn set_priority(&self, int: super::Interrupt, priority: InterruptPriority) {
+    let gic = self.get().unwrap();
+    match int {
+      0..=31 => gic.r[core_id()].as_ref().unwrap().set_priority(int, gic_priority(priority)),
+      32..=1019 => gic.d.set_priority(int, gic_priority(priority)),
+      LPI_BASE.. => {
+        if let Some(its) = &gic.its {
+          its.set_priority(int, gic_priority(priority));
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn set_trigger(&self, int: super::Interrupt, trigger: TriggerMode) {
+    let gic = self.get().unwrap();
+    match int {
+      16..=31 => gic.r[core_id()].as_ref().unwrap().set_trigger(int, trigger),
+      32..=1019 => gic.d.set_trigger(int, trigger),
+      // trigger mode of SGIs is fixed, LPIs are always edge triggered
+      _ => {}
+    }
+  }
+
+  fn set_affinity(&self, int: super::Interrupt, core_id: usize) {
+    let gic = self.get().unwrap();
+    match int {
+      32..=1019 => gic.d.set_route(int, gic.r[core_id].as_ref().unwrap().affinity()),
+      LPI_BASE.. => {
+        if let Some(its) = &gic.its {
+          its.set_affinity(int, core_id);
+        }
+      }
+      // SGIs and PPIs are private to each core
+      _ => {}
+    }
+  }
+
+  fn msi_alloc(&self, device_id: usize) -> Result<(super::Interrupt, usize, usize), Error> {
+    match &self.get().unwrap().its {
+      Some(its) => its.msi_alloc(device_id),
+      None => Err(ERROR_INVARG),
+    }
+  }
+
+  fn msi_free(&self, int: super::Interrupt) -> Result<(), Error> {
+    match &self.get().unwrap().its {
+      Some(its) => its.msi_free(int),
+      None => Err(ERROR_INVARG),
     }
   }
 
   fn fetch(&self) -> Option<(super::Interrupt, usize)> {
     let int_id = self.get().unwrap().c.int_ack();
-    if int_id >= 1020 {
+    // Note: 1020..=1023 are special INTIDs, LPIs start from 8192
+    if (1020..LPI_BASE as u32).contains(&int_id) {
       None
     } else {
       Some((int_id as Interrupt, 0 as usize)) // TODO: source!
@@ -0,0 +1,331 @@
+use alloc::alloc::{alloc_zeroed, Layout};
+use alloc::collections::BTreeMap;
+use core::arch::asm;
+
+use rpabi::syscall::error::{ERROR_INVARG, ERROR_OOM};
+use spin::Mutex;
+use tock_registers::interfaces::{Readable, Writeable};
+use tock_registers::registers::*;
+use tock_registers::*;
+
+use crate::kernel::traits::Address;
+
+use super::gic::Interrupt;
+
+// Interrupt Translation Service: turns MSI writes (DeviceID, EventID) into LPIs
+// https://developer.arm.com/documentation/ihi0069/latest
+
+// LPI INTIDs start from 8192
+pub const LPI_BASE: usize = 8192;
+// LPIs supported by the configuration table, i.e. 14 bits of INTID
+pub const LPI_NUM: usize = 8192;
+const LPI_ID_BITS: u64 = 14;
+
+// each mapped device raises up to this many MSIs
+const ITS_EVENT_BITS: usize = 5;
+const ITS_EVENT_NUM: usize = 1 << ITS_EVENT_BITS;
+
+const ITS_COMMAND_SIZE: usize = 32;
+const ITS_COMMAND_QUEUE_SIZE: usize = 0x1000;
+const ITS_DEVICE_TABLE_SIZE: usize = 0x10000;
+const ITS_COLLECTION_TABLE_SIZE: usize = 0x1000;
+
+// MSI doorbell (GITS_TRANSLATER) offset from ITS base
+const ITS_TRANSLATER_OFFSET: usize = 0x10040;
+
+const GITS_BASER_TYPE_DEVICE: u64 = 1;
+const GITS_BASER_TYPE_COLLECTION: u64 = 4;
+const GITS_BASER_VALID: u64 = 1 << 63;
+// Normal Inner Write-back Read-allocate Write-allocate, Inner Shareable
+const GITS_TABLE_ATTR: u64 = (0b111 << 59) | (0b01 << 10);
+const GICR_TABLE_ATTR: u64 = (0b111 << 7) | (0b01 << 10);
+
+const ITS_CMD_MOVI: u64 = 0x01;
+const ITS_CMD_SYNC: u64 = 0x05;
+const ITS_CMD_MAPD: u64 = 0x08;
+const ITS_CMD_MAPC: u64 = 0x09;
+const ITS_CMD_MAPTI: u64 = 0x0a;
+const ITS_CMD_INV: u64 = 0x0c;
+const ITS_CMD_DISCARD: u64 = 0x0f;
+
+register_structs! {
+  #[allow(non_snake_case)]
+  GicItsMmio {
+    (0x0000 => Control: ReadWrite<u32>),
+    (0x0004 => ImplementerId: ReadOnly<u32>),
+    (0x0008 => Type: ReadOnly<u64>),
+    (0x0010 => _reserved_0),
+    (0x0080 => CmdBase: ReadWrite<u64>),
+    (0x0088 => CmdWrite: ReadWrite<u64>),
+    (0x0090 => CmdRead: ReadOnly<u64>),
+    (0x0098 => _reserved_1),
+    (0x0100 => TableBase: [ReadWrite<u64>; 8]),
+    (0x0140 => _reserved_2),
+    (0x10000 => @END),
+  }
+}
+
+// physically contiguous, zeroed kernel memory which is never freed
+fn table_alloc(size: usize, align: usize) -> Result<usize, usize> {
+  let layout = Layout::from_size_align(size, align).map_err(|_| ERROR_INVARG)?;
+  let ptr = unsafe { alloc_zeroed(layout) };
+  if ptr.is_null() {
+    Err(ERROR_OOM)
+  } else {
+    Ok(ptr as usize)
+  }
+}
+
+fn dsb() {
+  unsafe {
+    asm!("dsb sy");
+  }
+}
+
+// LPI configuration byte: priority in bits [7:2], enable in bit 0
+fn lpi_config(priority: u8, enable: bool) -> u8 {
+  (priority & 0xfc) | (enable as u8)
+}
+
+struct Mapping {
+  device_id: usize,
+  event_id: usize,
+  core_id: usize,
+}
+
+struct ItsState {
+  cmd_queue: usize,
+  cmd_write: usize,
+  // DeviceIDs backed by the device table
+  device_num: usize,
+  itt_entry_size: usize,
+  // physical target of each collection, in the format of command `RDbase` field
+  rdbase: [u64; crate::MAX_CPU_NUMBER],
+  // bitmap of allocated EventIDs of each mapped device
+  devices: BTreeMap<usize, u32>,
+  lpis: BTreeMap<Interrupt, Mapping>,
+}
+
+pub struct GicIts {
+  base_addr: usize,
+  base_pa: usize,
+  // LPI configuration table shared by all redistributors
+  config_table: Mutex<usize>,
+  // LPI pending table of each core, kept across re-initialization of its redistributor
+  pending_tables: Mutex<[usize; crate::MAX_CPU_NUMBER]>,
+  state: Mutex<ItsState>,
+}
+
+impl core::ops::Deref for GicIts {
+  type Target = GicItsMmio;
+
+  fn deref(&self) -> &Self::Target {
+    unsafe { &*(self.base_addr as *const GicItsMmio) }
+  }
+}
+
+impl GicIts {
+  pub const fn new(base_pa: usize, base_addr: usize) -> Self {
+    GicIts {
+      base_addr,
+      base_pa,
+      config_table: Mutex::new(0),
+      pending_tables: Mutex::new([0; crate::MAX_CPU_NUMBER]),
+      state: Mutex::new(ItsState {
+        cmd_queue: 0,
+        cmd_write: 0,
+        device_num: 0,
+        itt_entry_size: 0,
+        rdbase: [0; crate::MAX_CPU_NUMBER],
+        devices: BTreeMap::new(),
+        lpis: BTreeMap::new(),
+      }),
+    }
+  }
+
+  // Called once by core 0 after kernel heap is ready
+  pub fn init(&self, default_priority: u8) {
+    let config_table = table_alloc(LPI_NUM, 0x10000).expect("its config table allocation failed");
+    for i in 0..LPI_NUM {
+      unsafe { ((config_table + i) as *mut u8).write_volatile(lpi_config(default_priority, false)); }
+    }
+    *self.config_table.lock() = config_table;
+
+    self.Control.set(0);
+    loop {
+      if self.Control.get() & (1 << 31/* Quiescent */) != 0 {
+        break;
+      }
+    }
+    let typer = self.Type.get();
+    let mut state = self.state.lock();
+    state.itt_entry_size = (((typer >> 4) & 0xf) + 1) as usize;
+    let device_bits = ((typer >> 13) & 0x1f) + 1;
+
+    state.cmd_queue = table_alloc(ITS_COMMAND_QUEUE_SIZE, 0x10000).expect("its command queue allocation failed");
+    self.CmdBase.set(
+      GITS_BASER_VALID | GITS_TABLE_ATTR | (state.cmd_queue.kva2pa() as u64)
+        | (ITS_COMMAND_QUEUE_SIZE / 0x1000 - 1) as u64,
+    );
+    self.CmdWrite.set(0);
+    state.cmd_write = 0;
+
+    for i in 0..8 {
+      let baser = self.TableBase[i].get();
+      let entry_size = (((baser >> 48) & 0x1f) + 1) as usize;
+      let size = match (baser >> 56) & 0b111 {
+        GITS_BASER_TYPE_DEVICE => {
+          state.device_num = (ITS_DEVICE_TABLE_SIZE / entry_size).min(1 << device_bits);
+          ITS_DEVICE_TABLE_SIZE
+        }
+        GITS_BASER_TYPE_COLLECTION => ITS_COLLECTION_TABLE_SIZE,
+        _ => continue,
+      };
+      let table = table_alloc(size, 0x10000).expect("its table allocation failed");
+      // Note: page size field 0b00 selects 4 KB pages
+      self.TableBase[i].set(
+        GITS_BASER_VALID | GITS_TABLE_ATTR | (baser & ((0b111 << 56) | (0x1f << 48)))
+          | (table.kva2pa() as u64) | (size / 0x1000 - 1) as u64,
+      );
+    }
+    drop(state);
+    self.Control.set(1);
+    info!("gic its at {:x} device ids {}", self.base_pa, self.state.lock().device_num);
+  }
+
+  pub fn config_table_pa(&self) -> u64 {
+    (*self.config_table.lock()).kva2pa() as u64 | GICR_TABLE_ATTR | (LPI_ID_BITS - 1)
+  }
+
+  // Pending table of core `core_id`, see `GICR_PENDBASER`
+  pub fn pending_table_pa(&self, core_id: usize) -> u64 {
+    let mut tables = self.pending_tables.lock();
+    // Note: only a freshly allocated table is known to be zeroed
+    let zeroed = tables[core_id] == 0;
+    if zeroed {
+      // one bit for every INTID below 2^LPI_ID_BITS
+      tables[core_id] = table_alloc((1 << LPI_ID_BITS) / 8, 0x10000).expect("its pending table allocation failed");
+    }
+    tables[core_id].kva2pa() as u64 | GICR_TABLE_ATTR | ((zeroed as u64) << 62/* PTZ */)
+  }
+
+  // Map the collection of core `core_id` to its redistributor
+  // `rd_pa` is the physical address of the redistributor, `processor` is `GICR_TYPER.Processor_Number`
+  pub fn map_collection(&self, core_id: usize, rd_pa: usize, processor: usize) {
+    let mut state = self.state.lock();
+    let pta = self.Type.get() & (1 << 19) != 0;
+    let rdbase = if pta { (rd_pa >> 16) as u64 } else { processor as u64 };
+    state.rdbase[core_id] = rdbase;
+    self.send(&mut state, [ITS_CMD_MAPC, 0, (rdbase << 16) | core_id as u64 | (1 << 63), 0]);
+    self.sync(&mut state, core_id);
+  }
+
+  fn send(&self, state: &mut ItsState, cmd: [u64; 4]) {
+    let slot = (state.cmd_queue + state.cmd_write) as *mut [u64; 4];
+    unsafe { slot.write_volatile(cmd); }
+    state.cmd_write = (state.cmd_write + ITS_COMMAND_SIZE) % ITS_COMMAND_QUEUE_SIZE;
+    dsb();
+    self.CmdWrite.set(state.cmd_write as u64);
+    // Note: commands are issued one by one, so the queue never fills
+    loop {
+      if self.CmdRead.get() as usize == state.cmd_write {
+        break;
+      }
+    }
+  }
+
+  fn sync(&self, state: &mut ItsState, core_id: usize) {
+    let rdbase = state.rdbase[core_id];
+    self.send(state, [ITS_CMD_SYNC, 0, rdbase << 16, 0]);
+  }
+
+  // Allocate an LPI for event of device `device_id` (e.g., PCI requester id)
+  // Returns the LPI, the doorbell physical address and the data a device writes to raise it
+  pub fn msi_alloc(&self, device_id: usize) -> Result<(Interrupt, usize, usize), usize> {
+    let mut state = self.state.lock();
+    if device_id >= state.device_num {
+      return Err(ERROR_INVARG);
+    }
+    if !state.devices.contains_key(&device_id) {
+      let itt = table_alloc(ITS_EVENT_NUM * state.itt_entry_size, 256)?;
+      self.send(&mut state, [
+        ITS_CMD_MAPD | ((device_id as u64) << 32),
+        (ITS_EVENT_BITS - 1) as u64,
+        (itt.kva2pa() as u64) | (1 << 63),
+        0,
+      ]);
+      state.devices.insert(device_id, 0);
+    }
+    let events = state.devices[&device_id];
+    let event_id = (0..ITS_EVENT_NUM).find(|e| events & (1 << e) == 0).ok_or(ERROR_OOM)?;
+    let lpi = (LPI_BASE..LPI_BASE + LPI_NUM).find(|i| !state.lpis.contains_key(i)).ok_or(ERROR_OOM)?;
+    // Note: collection of core 0 until `set_affinity`
+    self.send(&mut state, [
+      ITS_CMD_MAPTI | ((device_id as u64) << 32),
+      event_id as u64 | ((lpi as u64) << 32),
+      0,
+      0,
+    ]);
+    self.sync(&mut state, 0);
+    state.devices.insert(device_id, events | (1 << event_id));
+    state.lpis.insert(lpi, Mapping { device_id, event_id, core_id: 0 });
+    Ok((lpi, self.base_pa + ITS_TRANSLATER_OFFSET, event_id))
+  }
+
+  pub fn msi_free(&self, lpi: Interrupt) -> Result<(), usize> {
+    self.set_config(lpi, |c| c & !1);
+    let mut state = self.state.lock();
+    let m = state.lpis.remove(&lpi).ok_or(ERROR_INVARG)?;
+    self.send(&mut state, [ITS_CMD_DISCARD | ((m.device_id as u64) << 32), m.event_id as u64, 0, 0]);
+    self.sync(&mut state, m.core_id);
+    if let Some(events) = state.devices.get_mut(&m.device_id) {
+      *events &= !(1 << m.event_id);
+    }
+    Ok(())
+  }
+
+  // Update configuration byte of `lpi` and make redistributors reload it
+  fn set_config<F>(&self, lpi: Interrupt, f: F) where F: FnOnce(u8) -> u8 {
+    let mut state = self.state.lock();
+    let (device_id, event_id, core_id) = match state.lpis.get(&lpi) {
+      Some(m) => (m.device_id, m.event_id, m.core_id),
+      None => return,
+    };
+    let config_table = *self.config_table.lock();
+    let ptr = (config_table + lpi - LPI_BASE) as *mut u8;
+    unsafe { ptr.write_volatile(f(ptr.read_volatile())); }
+    dsb();
+    self.send(&mut state, [ITS_CMD_INV | ((device_id as u64) << 32), event_id as u64, 0, 0]);
+    self.sync(&mut state, core_id);
+  }
+
+  pub fn enable(&self, lpi: Interrupt) {
+    self.set_config(lpi, |c| c | 1);
+  }
+
+  pub fn disable(&self, lpi: Interrupt) {
+    self.set_config(lpi, |c| c & !1);
+  }
+
+  pub fn set_priority(&self, lpi: Interrupt, priority: u8) {
+    self.set_config(lpi, |c| lpi_config(priority, c & 1 != 0));
+  }
+
+  pub fn set_affinity(&self, lpi: Interrupt, core_id: usize) {
+    let mut state = self.state.lock();
+    let (device_id, event_id) = match state.lpis.get_mut(&lpi) {
+      Some(m) => {
+        m.core_id = core_id;
+        (m.device_id, m.event_id)
+      }
+      None => return,
+    };
+    self.send(&mut state, [
+      ITS_CMD_MOVI | ((device_id as u64) << 32),
+      event_id as u64,
+      core_id as u64,
+      0,
+    ]);
+    self.sync(&mut state, core_id);
+  }
+}
@@ -1,4 +1,4 @@
-pub use gic::{Interrupt, INTERRUPT_CONTROLLER};
+pub use gic::{Interrupt, INTERRUPT_CONTROLLER, INTERRUPT_NUMBER_MAX};
 
 #[cfg(not(feature = "gicv3"))]
 #[path ="gic.rs"]
@@ -8,6 +8,9 @@ pub mod gic;
 #[path ="gicv3.rs"]
 pub mod gic;
 
+#[cfg(feature = "gicv3")]
+mod its;
+
 mod smc;
 pub mod psci;
 pub mod timer;
@@ -1,4 +1,4 @@
-pub use plic::{Interrupt, INTERRUPT_CONTROLLER};
+pub use plic::{Interrupt, INTERRUPT_CONTROLLER, INTERRUPT_NUMBER_MAX};
 
 pub mod plic;
 mod sbi;
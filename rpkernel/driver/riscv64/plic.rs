@@ -8,14 +8,21 @@ use tock_registers::{
 use crate::core_id;
 use crate::kernel::interrupt::{
   InterProcessInterrupt as IPI, InterProcessorInterruptController, InterruptController,
+  InterruptPriority, TriggerMode, INTERRUPT_PRIORITY_DEFAULT,
 };
 
 // platform level interrupt controller
 // https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc
 const PLIC_BASE_ADDR: usize = 0xffff_ffff_0000_0000 + 0x0c00_0000;
+const PLIC_INTERRUPT_NUM: usize = 0x400;
+
+// upper bound of interrupt numbers
+pub const INTERRUPT_NUMBER_MAX: usize = PLIC_INTERRUPT_NUM;
 
 pub struct Rv64InterruptController {
   ipi_mailboxes: Mutex<[Option<(IPI, usize)>; crate::MAX_CPU_NUMBER]>,
+  // core each source is enabled on, see `set_affinity`
+  route: Mutex<[u8; PLIC_INTERRUPT_NUM]>,
 }
 
 register_structs! {
@@ -79,6 +86,31 @@ impl PlicMmio {
   fn ptr(&self) -> *const PlicBlock {
     self.base_addr as *const _
   }
+
+  // enable bits of the supervisor context of `core_id`
+  fn enable_regs(&self, core_id: usize) -> &[ReadWrite<u32>; 0x20] {
+    match core_id {
+      0 => &self.InterruptEnableCtx1,
+      1 => &self.InterruptEnableCtx3,
+      2 => &self.InterruptEnableCtx5,
+      3 => &self.InterruptEnableCtx7,
+      _ => panic!(),
+    }
+  }
+
+  fn set_enable(&self, core_id: usize, i: Interrupt, enable: bool) {
+    let reg = &self.enable_regs(core_id)[i / 32];
+    let bit_mask: u32 = 1 << (i % 32);
+    if enable {
+      reg.set(reg.get() | bit_mask);
+    } else {
+      reg.set(reg.get() & !bit_mask);
+    }
+  }
+
+  fn enabled(&self, core_id: usize, i: Interrupt) -> bool {
+    self.enable_regs(core_id)[i / 32].get() & (1 << (i % 32)) != 0
+  }
 }
 
 static PLIC_MMIO: PlicMmio = PlicMmio::new(PLIC_BASE_ADDR);
@@ -94,53 +126,41 @@ impl InterruptController for Rv64InterruptController {
       3 => plic.PriorityThresholdCtx7.set(0),
       _ => panic!(),
     }
+    if core_id == 0 {
+      for i in 1..PLIC_INTERRUPT_NUM {
+        self.set_priority(i, INTERRUPT_PRIORITY_DEFAULT);
+      }
+    }
   }
 
   fn enable(&self, i: Interrupt) {
-    let plic = &PLIC_MMIO;
-    let core_id = core_id();
-    let reg_idx = i / 32;
-    let bit_idx = i % 32;
-    let bit_mask: u32 = 1 << bit_idx;
+    let route = self.route.lock();
+    PLIC_MMIO.set_enable(route[i] as usize, i, true);
+  }
 
-    match core_id {
-      0 => {
-        plic.InterruptEnableCtx1[reg_idx].set(plic.InterruptEnableCtx1[reg_idx].get() | bit_mask)
-      }
-      1 => {
-        plic.InterruptEnableCtx3[reg_idx].set(plic.InterruptEnableCtx3[reg_idx].get() | bit_mask)
-      }
-      2 => {
-        plic.InterruptEnableCtx5[reg_idx].set(plic.InterruptEnableCtx5[reg_idx].get() | bit_mask)
-      }
-      3 => {
-        plic.InterruptEnableCtx7[reg_idx].set(plic.InterruptEnableCtx7[reg_idx].get() | bit_mask)
-      }
-      _ => panic!(),
+  fn disable(&self, i: Interrupt) {
+    // Note: a masked line must stay quiet on every hart
+    for core_id in 0..crate::cpu_number() {
+      PLIC_MMIO.set_enable(core_id, i, false);
     }
-    plic.InterruptPriority[i].set(1);
   }
 
-  fn disable(&self, i: Interrupt) {
-    let plic = &PLIC_MMIO;
-    let core_id = core_id();
-    let reg_idx = i / 32;
-    let bit_idx = i % 32;
-    let bit_mask: u32 = 1 << bit_idx;
-    match core_id {
-      0 => {
-        plic.InterruptEnableCtx1[reg_idx].set(plic.InterruptEnableCtx1[reg_idx].get() & !bit_mask)
-      }
-      1 => {
-        plic.InterruptEnableCtx3[reg_idx].set(plic.InterruptEnableCtx3[reg_idx].get() & !bit_mask)
-      }
-      2 => {
-        plic.InterruptEnableCtx5[reg_idx].set(plic.InterruptEnableCtx5[reg_idx].get() & !bit_mask)
-      }
-      3 => {
-        plic.InterruptEnableCtx7[reg_idx].set(plic.InterruptEnableCtx7[reg_idx].get() & !bit_mask)
-      }
-      _ => panic!(),
+  fn set_priority(&self, i: Interrupt, priority: InterruptPriority) {
+    // Note: priority 0 means never interrupt on PLIC
+    PLIC_MMIO.InterruptPriority[i].set(priority.max(1) as u32);
+  }
+
+  fn set_trigger(&self, _i: Interrupt, _trigger: TriggerMode) {
+    // Note: gateway type of each source is fixed by the platform
+  }
+
+  fn set_affinity(&self, i: Interrupt, core_id: usize) {
+    let mut route = self.route.lock();
+    let prev = route[i] as usize;
+    route[i] = core_id as u8;
+    if prev != core_id && PLIC_MMIO.enabled(prev, i) {
+      PLIC_MMIO.set_enable(prev, i, false);
+      PLIC_MMIO.set_enable(core_id, i, true);
     }
   }
 
@@ -176,6 +196,7 @@ impl InterruptController for Rv64InterruptController {
 
 pub static INTERRUPT_CONTROLLER: Rv64InterruptController = Rv64InterruptController {
   ipi_mailboxes: Mutex::new([None; crate::MAX_CPU_NUMBER]),
+  route: Mutex::new([0; PLIC_INTERRUPT_NUM]),
 };
 
 pub type Interrupt = usize;
@@ -1,4 +1,4 @@
-use x2apic::ioapic::IoApic;
+use x2apic::ioapic::{IoApic, IrqFlags};
 use x2apic::lapic::{xapic_base, LocalApic, LocalApicBuilder};
 
 use spin::{Mutex, Once};
@@ -6,6 +6,7 @@ use spin::{Mutex, Once};
 use crate::{core_id, MAX_CPU_NUMBER};
 use crate::kernel::interrupt::{
   InterProcessInterrupt as IPI, InterProcessorInterruptController, InterruptController,
+  InterruptPriority, TriggerMode,
 };
 
 pub const TIMER_INTERRUPT_NUMBER: u8 = 123;
@@ -15,6 +16,14 @@ pub const SPURIOUS_INTERRUPT_NUMBER: u8 = 127;
 /// The timer IRQ number.
 pub const INT_TIMER: usize = TIMER_INTERRUPT_NUMBER as usize;
 
+// upper bound of interrupt numbers
+pub const INTERRUPT_NUMBER_MAX: usize = 1024;
+
+// IO APIC input `n` (i.e., GSI `n`) raises vector `IO_APIC_VECTOR_BASE + n`, which is dispatched as interrupt `n`
+pub const IO_APIC_VECTOR_BASE: u8 = 0x21;
+// inputs with an entry stub, see interrupt.S
+pub const IO_APIC_INTERRUPT_NUM: usize = 24;
+
 // TODO: remove this hardcode address
 const IO_APIC_BASE: usize = 0xFEC0_0000;
 
@@ -85,7 +94,7 @@ impl InterruptController for Once<Apic> {
 
       unsafe {
         let mut io_apic = IoApic::new(ioapic_vaddr as u64);
-        io_apic.init(0x21);
+        io_apic.init(IO_APIC_VECTOR_BASE);
 
         let max_entry = io_apic.max_table_entry() + 1;
         info!(
@@ -111,8 +120,8 @@ impl InterruptController for Once<Apic> {
   }
 
   fn enable(&self, int: Interrupt) {
-    info!("InterruptController apic enable int {}", int);
-    if int < TIMER_INTERRUPT_NUMBER as _ {
+    trace!("InterruptController apic enable int {}", int);
+    if int < IO_APIC_INTERRUPT_NUM {
       unsafe {
         self.get().unwrap().io_apic.lock().enable_irq(int as u8);
       }
@@ -120,14 +129,44 @@ impl InterruptController for Once<Apic> {
   }
 
   fn disable(&self, int: Interrupt) {
-    info!("InterruptController apic disable int {}", int);
-    if int < TIMER_INTERRUPT_NUMBER as _ {
+    trace!("InterruptController apic disable int {}", int);
+    if int < IO_APIC_INTERRUPT_NUM {
       unsafe {
         self.get().unwrap().io_apic.lock().disable_irq(int as u8);
       }
     }
   }
 
+  fn set_priority(&self, _int: Interrupt, _priority: InterruptPriority) {
+    // Note: priority of a local APIC is the class of the vector, which is fixed as `IRQ offset + int`
+  }
+
+  fn set_trigger(&self, int: Interrupt, trigger: TriggerMode) {
+    // Note: only IO APIC inputs have a redirection entry, MSI vectors carry their own
+    if int < IO_APIC_INTERRUPT_NUM {
+      let mut io_apic = self.get().unwrap().io_apic.lock();
+      unsafe {
+        let mut entry = io_apic.table_entry(int as u8);
+        let mut flags = entry.flags();
+        flags.set(IrqFlags::LEVEL_TRIGGERED, trigger == TriggerMode::Level);
+        entry.set_flags(flags);
+        io_apic.set_table_entry(int as u8, entry);
+      }
+    }
+  }
+
+  fn set_affinity(&self, int: Interrupt, core_id: usize) {
+    if int < IO_APIC_INTERRUPT_NUM {
+      let mut io_apic = self.get().unwrap().io_apic.lock();
+      unsafe {
+        let mut entry = io_apic.table_entry(int as u8);
+        // Note: APIC id equals core id, see `local_apic`
+        entry.set_dest(core_id as u8);
+        io_apic.set_table_entry(int as u8, entry);
+      }
+    }
+  }
+
   fn fetch(&self) -> Option<(Interrupt, usize)> {
     todo!()
   }
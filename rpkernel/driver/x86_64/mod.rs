@@ -1,3 +1,3 @@
 pub mod apic;
-pub use apic::{Interrupt, INTERRUPT_CONTROLLER};
+pub use apic::{Interrupt, INTERRUPT_CONTROLLER, INTERRUPT_NUMBER_MAX};
 pub mod timer;
\ No newline at end of file
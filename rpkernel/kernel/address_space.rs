@@ -1,24 +1,43 @@
-use alloc::collections::BTreeMap;
+use alloc::boxed::Box;
 use alloc::sync::Arc;
-use core::sync::atomic::{AtomicU16, Ordering};
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use rpabi::{CONFIG_ELF_IMAGE, PAGE_SIZE};
-use rpabi::syscall::error::{ERROR_OOM, ERROR_OOR};
-use spin::{Mutex, MutexGuard};
+use rpabi::syscall::error::ERROR_OOM;
+use spin::{Mutex, MutexGuard, Once};
 
+use crate::arch::AddressSpaceId;
+use crate::kernel::handle::HandleTable;
+use crate::kernel::thread::Tid;
 use crate::kernel::traits::Address;
-use crate::mm::page_table::PageTable;
+use crate::mm::page_table::{PageBudget, PageTable};
+use crate::mm::slab::{ADDRESS_SPACE_CACHE, SlabAllocator};
 use rpabi::syscall::mm::EntryAttribute;
 use crate::util::round_up;
 
 pub type Asid = u16;
 pub type Error = usize;
 
+pub const IO_PORT_NUM: usize = 0x10000;
+// One bit per I/O port, a set bit denies the access, as the x86 TSS I/O permission bitmap
+pub type IoPortBitmap = [u64; IO_PORT_NUM / 64];
+
+// Asid layout: [ 4-bit generation ][ 12-bit slot index ]
+// Slot index doubles as hardware ASID (x86_64 PCID is 12-bit)
+const ASID_INDEX_BITS: usize = 12;
+const ASID_GENERATION_BITS: usize = 4;
+
 #[derive(Debug)]
 struct Inner {
   asid: Asid,
+  // address space that allocated this one, if allocated by user
+  parent: Option<Asid>,
   page_table: Mutex<PageTable>,
   exception_handler: Mutex<Option<usize>>,
+  pager: Mutex<Option<Tid>>,
+  io_port_bitmap: Mutex<Option<Box<IoPortBitmap>>>,
+  destroyed: AtomicBool,
 }
 
 impl Drop for Inner {
@@ -28,7 +47,7 @@ impl Drop for Inner {
 }
 
 #[derive(Debug, Clone)]
-pub struct AddressSpace(Arc<Inner>);
+pub struct AddressSpace(Arc<Inner, SlabAllocator>);
 
 impl PartialEq for AddressSpace {
   fn eq(&self, other: &Self) -> bool {
@@ -40,6 +59,15 @@ impl AddressSpace {
   pub fn asid(&self) -> Asid {
     self.0.asid
   }
+
+  pub fn is_child_of(&self, a: &AddressSpace) -> bool {
+    self.0.parent == Some(a.asid())
+  }
+
+  pub fn hardware_asid(&self) -> AddressSpaceId {
+    self.0.asid & ((1 << ASID_INDEX_BITS) - 1)
+  }
+
   pub fn page_table(&self) -> MutexGuard<PageTable> {
     self.0.page_table.lock()
   }
@@ -53,56 +81,111 @@ impl AddressSpace {
     let mut lock = self.0.exception_handler.lock();
     *lock = handler;
   }
+
+  pub fn pager(&self) -> Option<Tid> {
+    let lock = self.0.pager.lock();
+    lock.clone()
+  }
+
+  pub fn set_pager(&self, pager: Option<Tid>) {
+    let mut lock = self.0.pager.lock();
+    *lock = pager;
+  }
+
+  pub fn io_port_bitmap(&self) -> MutexGuard<Option<Box<IoPortBitmap>>> {
+    self.0.io_port_bitmap.lock()
+  }
+
+  // Note: the bitmap is allocated on first grant, address spaces without port access pay nothing
+  pub fn io_port_grant(&self, ports: Range<usize>) -> Result<(), Error> {
+    let mut lock = self.0.io_port_bitmap.lock();
+    if lock.is_none() {
+      *lock = Some(Box::try_new([u64::MAX; IO_PORT_NUM / 64]).map_err(|_| ERROR_OOM)?);
+    }
+    let bitmap = lock.as_mut().unwrap();
+    for port in ports {
+      bitmap[port / 64] &= !(1 << (port % 64));
+    }
+    Ok(())
+  }
+
+  pub fn destroyed(&self) -> bool {
+    self.0.destroyed.load(Ordering::Relaxed)
+  }
+
+  pub fn set_destroyed(&self) {
+    self.0.destroyed.store(true, Ordering::Relaxed);
+  }
 }
 
-static ASID_ALLOCATOR: AtomicU16 = AtomicU16::new(1);
+static ADDRESS_SPACE_MAP: Mutex<HandleTable<AddressSpace, ASID_INDEX_BITS, ASID_GENERATION_BITS>> = Mutex::new(HandleTable::new());
 
-fn new_asid() -> Asid {
-  ASID_ALLOCATOR.fetch_add(1, Ordering::Relaxed)
+// address space of the trusted root, the only one allowed to hand out device interrupts
+static TRUSTED_ASID: Once<Asid> = Once::new();
+
+pub fn set_trusted(a: &AddressSpace) {
+  TRUSTED_ASID.call_once(|| a.asid());
 }
 
-static ADDRESS_SPACE_MAP: Mutex<BTreeMap<Asid, AddressSpace>> = Mutex::new(BTreeMap::new());
+pub fn is_trusted(a: &AddressSpace) -> bool {
+  TRUSTED_ASID.get() == Some(&a.asid())
+}
 
-pub fn address_space_alloc() -> Result<AddressSpace, Error> {
-  let id = new_asid();
-  if id == 0 {
-    return Err(ERROR_OOR);
-  }
-  let page_table = PageTable::new()?;
+// Pages of a new address space are charged to the budget of `parent` as well, so allocating one escapes no budget
+pub fn address_space_alloc(parent: Option<&AddressSpace>) -> Result<AddressSpace, Error> {
+  let budget = PageBudget::new(parent.map(|p| p.page_table().budget().clone()))?;
+  let page_table = PageTable::new(budget)?;
   page_table.recursive_map(rpabi::CONFIG_RECURSIVE_PAGE_TABLE_BTM);
-  let a = AddressSpace(Arc::try_new(Inner {
-    asid: id,
+  let mut map = ADDRESS_SPACE_MAP.lock();
+  map.insert_with(|id| Ok(AddressSpace(Arc::try_new_in(Inner {
+    asid: id as Asid,
+    parent: parent.map(|p| p.asid()),
     page_table: Mutex::new(page_table),
     exception_handler: Mutex::new(None),
-  }).map_err(|_| ERROR_OOM)?);
-  let mut map = ADDRESS_SPACE_MAP.lock();
-  map.insert(id, a.clone());
-  Ok(a)
+    pager: Mutex::new(None),
+    io_port_bitmap: Mutex::new(None),
+    destroyed: AtomicBool::new(false),
+  }, SlabAllocator(&ADDRESS_SPACE_CACHE)).map_err(|_| ERROR_OOM)?)))
 }
 
-pub fn address_space_lookup(asid: Asid) -> Option<AddressSpace> {
+pub fn address_space_lookup(asid: Asid) -> Result<AddressSpace, Error> {
   let map = ADDRESS_SPACE_MAP.lock();
-  match map.get(&asid) {
-    Some(a) => Some(a).cloned(),
-    None => None
-  }
+  map.get(asid as usize)
 }
 
+// Kill all threads of `a` and release its user pages
+// Page table itself is freed once the last cpu switches away from it
 pub fn address_space_destroy(a: AddressSpace) {
   trace!("Destroy AS{}", a.asid());
   let mut map = ADDRESS_SPACE_MAP.lock();
-  map.remove(&a.asid());
+  let _ = map.remove(a.asid() as usize);
+  drop(map);
+  crate::kernel::thread::thread_destroy_all(&a);
+  address_space_release(&a);
 }
 
-pub fn load_image(elf: &'static [u8]) -> (AddressSpace, usize) {
-  let a = address_space_alloc().unwrap();
+// Release user pages of a destroyed `a` once no thread of it is left
+// Note: a thread still running on another cpu is reaped there later, the last one to go releases the pages
+pub fn address_space_release(a: &AddressSpace) {
+  if !crate::kernel::thread::thread_any(a) {
+    a.page_table().remove_all_pages();
+  }
+}
+
+pub fn load_image(elf: &'static [u8]) -> Result<(AddressSpace, usize), Error> {
+  let a = address_space_alloc(None)?;
   let mut page_table = a.page_table();
   let len = round_up(elf.len(), PAGE_SIZE);
-  for i in (0..len).step_by(PAGE_SIZE) {
+  let r = (0..len).step_by(PAGE_SIZE).try_for_each(|i| {
     let pa = (elf.as_ptr() as usize + i).kva2pa();
-    page_table.map(CONFIG_ELF_IMAGE + i, pa, EntryAttribute::user_readonly()).expect("page_table map error");
-  }
-  let entry = crate::kernel::elf::load(elf, &mut page_table).expect("elf load error");
+    page_table.map(CONFIG_ELF_IMAGE + i, pa, EntryAttribute::user_readonly())
+  }).and_then(|_| crate::kernel::elf::load(elf, &mut page_table));
   drop(page_table);
-  (a, entry)
+  match r {
+    Ok(entry) => Ok((a, entry)),
+    Err(e) => {
+      address_space_destroy(a);
+      Err(e)
+    }
+  }
 }
@@ -3,11 +3,10 @@ use spin::Mutex;
 use alloc::collections::VecDeque as RunQueue;
 
 use crate::MAX_CPU_NUMBER;
-use crate::arch::{AddressSpaceId, ContextFrame, PAGE_SIZE};
+use crate::arch::{ContextFrame, PAGE_SIZE};
 use crate::core_id;
 use crate::kernel::address_space::AddressSpace;
-use crate::kernel::scheduler::scheduler;
-use crate::kernel::thread::Thread;
+use crate::kernel::thread::{Status, Thread};
 use crate::kernel::traits::*;
 use crate::mm::PhysicalFrame;
 
@@ -75,7 +74,7 @@ impl Core {
         let t = crate::kernel::thread::new_kernel(
           idle_thread as usize,
           frame.kva() + PAGE_SIZE,
-          0);
+          0).expect("fail to allocate idle thread");
         self.idle_stack.call_once(|| frame);
         self.idle_thread.call_once(|| t).clone()
       }
@@ -98,6 +97,11 @@ impl Core {
     r
   }
 
+  pub fn dequeue_task<F>(&self, f: F) where F: Fn(&Thread) -> bool {
+    let mut run_queue = self.run_queue.lock();
+    run_queue.retain(|t| !f(t));
+  }
+
   pub fn tick(&mut self, queue_prev: bool) {
     let mut run_queue = self.run_queue.lock();
     if let Some(next) = run_queue.pop_front() {
@@ -118,9 +122,12 @@ impl Core {
       // Note: normal switch
       prev.set_context(*self.context());
       prev.clear_running_cpu();
-      // add back to scheduler queue if it explicitly yield
-      if prev.tid() != self.idle_thread().tid() && queue_prev {
-        scheduler().add(prev.clone());
+      if prev.status() == Status::Exited {
+        // Note: killed by another cpu while running here, see `thread_destroy_all`
+        crate::kernel::thread::thread_destroy(prev);
+      } else if prev.tid() != self.idle_thread().tid() && queue_prev {
+        // add back to scheduler queue if it explicitly yield
+        prev.enqueue();
       }
       *self.context_mut() = t.context();
     } else {
@@ -134,6 +141,7 @@ impl Core {
     }
     self.set_running_thread(Some(t.clone()));
     t.set_running_cpu(core_id());
+    crate::arch::Arch::set_user_single_step(t.context().single_step());
     if let Some(a) = t.address_space() {
       self.set_address_space(a);
     }
@@ -151,7 +159,8 @@ impl Core {
       // info!("switch as from {} to {}", prev.asid(), a.asid());
     }
     self.address_space = Some(a.clone());
-    crate::arch::Arch::install_user_page_table(a.page_table().directory_pa(), a.asid() as AddressSpaceId);
+    crate::arch::Arch::install_user_page_table(a.page_table().directory_pa(), a.hardware_asid());
+    crate::arch::Arch::install_io_port_bitmap(a.io_port_bitmap().as_deref());
   }
 }
 
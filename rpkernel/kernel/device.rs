@@ -4,90 +4,168 @@ use crate::mm::Frame;
 use crate::util::round_down;
 use alloc::vec::Vec;
 use core::ops::Range;
-use core::panic;
 use fdt::node::FdtNode;
 use fdt::Fdt;
+use spin::Once;
 
 pub use rpabi::platform::*;
 
-#[allow(dead_code)]
-pub fn device_new(
-  name: &'static str,
-  register: Range<usize>,
-  interrupt: Option<Interrupt>,
-  driver: Option<Driver>,
-) -> Device {
-  let mut r = Device {
-    name: [0; DEVICE_NAME_LEN],
-    register,
-    interrupt,
-    driver,
+pub const DEVICE_REGISTER_MAX: usize = 8;
+pub const DEVICE_INTERRUPT_MAX: usize = 8;
+
+const VIRTIO_MMIO_MAGIC_VALUE: usize = 0x000;
+const VIRTIO_MMIO_DEVICE_ID: usize = 0x008;
+const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+const VIRTIO_ID_NET: u32 = 1;
+const VIRTIO_ID_BLOCK: u32 = 2;
+const VIRTIO_ID_CONSOLE: u32 = 3;
+const VIRTIO_ID_RNG: u32 = 4;
+
+static DEVICE_TREE: Once<Range<usize>> = Once::new();
+
+/// Keep the device tree blob in place for the trusted root
+///
+/// Returns the paged memory range with the blob carved out. A blob inside the kernel heap is not kept.
+pub fn device_tree_reserve(device_tree: Range<usize>, heap: &Range<usize>, paged: Range<usize>) -> Range<usize> {
+  if device_tree.start < heap.end && device_tree.end > heap.start {
+    warn!("device tree {:x?} overlaps kernel heap, not passed to user space", device_tree);
+    return paged;
+  }
+  let paged = if paged.contains(&device_tree.start) {
+    paged.start..round_down(device_tree.start, PAGE_SIZE)
+  } else {
+    paged
   };
-  let bytes = name.as_bytes();
-  for i in 0..bytes.len() {
-    r.name[i] = bytes[i];
+  DEVICE_TREE.call_once(|| device_tree);
+  paged
+}
+
+pub fn device_tree() -> Option<Range<usize>> {
+  DEVICE_TREE.get().cloned()
+}
+
+static ACPI_TABLES: Once<(usize, Vec<Range<usize>>)> = Once::new();
+
+/// Keep the ACPI RSDP and the table regions handed to the trusted root
+pub fn acpi_tables_register(rsdp: usize, tables: Vec<Range<usize>>) {
+  ACPI_TABLES.call_once(|| (rsdp, tables));
+}
+
+pub fn acpi_tables() -> Option<&'static (usize, Vec<Range<usize>>)> {
+  ACPI_TABLES.get()
+}
+
+// Note: a full table drops the device instead of failing the boot
+pub fn device_add(info: &mut PlatformInfo, device: &Device) {
+  if info.push(device).is_err() {
+    warn!("platform info full, device {} dropped", device.name);
   }
-  r
 }
 
-#[allow(dead_code)]
-pub fn device_from_fdt_node(fdt: &Fdt, node: &FdtNode, driver: Option<Driver>) -> Device {
-  let mut regs = node.reg().unwrap();
-  let register;
-  let interrupt;
-  let reg = regs.next().unwrap();
-  let start = reg.starting_address as usize;
-  register = start..(start + reg.size.unwrap());
-  let intc = match node.interrupt_parent() {
-    Some(node) => node,
-    None => match fdt.find_node("/").unwrap().interrupt_parent() {
-      Some(node) => node,
-      None => panic!(),
-    },
-  };
-  match intc.interrupt_cells() {
-    Some(3) => {
-      // GIC
-      interrupt = match node.property("interrupts") {
-        Some(prop) => match prop.as_triple_u32() {
-          Some((irq_type, irq_num, _trigger_type)) => {
-            assert_eq!(irq_type, 0); // only spi type is allowed
-            Some(irq_num as usize + 32)
-          }
-          None => None,
-        },
-        None => None,
+pub fn fdt_cell(value: &[u8], index: usize) -> Option<u32> {
+  let c = value.get((index * 4)..(index * 4 + 4))?;
+  Some(u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+}
+
+// Translate an interrupt specifier of `cells` cells starting at cell `index` of `value`
+pub fn fdt_interrupt(value: &[u8], index: usize, cells: usize) -> Option<Interrupt> {
+  match cells {
+    // GIC
+    3 => match fdt_cell(value, index)? {
+      // only spi type is allowed
+      0 => Some(fdt_cell(value, index + 1)? as usize + 32),
+      irq_type => {
+        warn!("interrupt type {} not supported", irq_type);
+        None
       }
+    },
+    // PLIC
+    1 => Some(fdt_cell(value, index)? as usize),
+    _ => {
+      warn!("interrupt parent with {} cells not supported", cells);
+      None
     }
-    Some(1) => {
-      // PLIC
-      if let Some(mut iter) = node.interrupts() {
-        let irq_num = iter.next().unwrap();
-        interrupt = Some(irq_num);
-      } else {
-        interrupt = None;
+  }
+}
+
+// Malformed or unsupported nodes are skipped (or added without interrupts) instead of panicking
+pub fn device_add_fdt_node(info: &mut PlatformInfo, fdt: &Fdt, node: &FdtNode, driver: Option<Driver>) {
+  let mut registers: [Range<usize>; DEVICE_REGISTER_MAX] = Default::default();
+  let mut register_num = 0;
+  if let Some(reg) = node.reg() {
+    for r in reg.take(DEVICE_REGISTER_MAX) {
+      let start = r.starting_address as usize;
+      registers[register_num] = start..(start + r.size.unwrap_or(0));
+      register_num += 1;
+    }
+  }
+  if register_num == 0 {
+    warn!("device {} has no register", node.name);
+    return;
+  }
+
+  let mut interrupts = [0; DEVICE_INTERRUPT_MAX];
+  let mut interrupt_num = 0;
+  if let Some(prop) = node.property("interrupts") {
+    let intc = node.interrupt_parent().or_else(|| fdt.find_node("/")?.interrupt_parent());
+    match intc.and_then(|intc| intc.interrupt_cells()) {
+      Some(cells) => {
+        let mut index = 0;
+        while (index + cells) * 4 <= prop.value.len() && interrupt_num < DEVICE_INTERRUPT_MAX {
+          if let Some(interrupt) = fdt_interrupt(prop.value, index, cells) {
+            interrupts[interrupt_num] = interrupt;
+            interrupt_num += 1;
+          }
+          index += cells;
+        }
       }
+      None => warn!("device {} interrupt parent not supported", node.name),
     }
-    _ => panic!(),
   }
-  let mut r = Device {
-    name: [0; DEVICE_NAME_LEN],
-    register: register,
-    interrupt: interrupt,
+
+  let compatible = node
+    .property("compatible")
+    .and_then(|prop| core::str::from_utf8(prop.value).ok())
+    .unwrap_or("");
+  device_add(info, &Device {
+    name: node.name,
+    compatible,
+    registers: &registers[..register_num],
+    ports: &[],
+    interrupts: &interrupts[..interrupt_num],
     driver,
-  };
-  let bytes = node.name.as_bytes();
-  for i in 0..bytes.len() {
-    r.name[i] = bytes[i];
+    pci: None,
+  });
+}
+
+/// Pick the driver of a virtio-mmio transport by its device id
+///
+/// Unused transports of QEMU virt read device id 0 and get no driver.
+#[allow(dead_code)]
+pub fn virtio_mmio_driver(node: &FdtNode) -> Option<Driver> {
+  use crate::kernel::traits::Address;
+  let base = (node.reg()?.next()?.starting_address as usize).pa2kva();
+  let read = |offset: usize| unsafe { ((base + offset) as *const u32).read_volatile() };
+  if read(VIRTIO_MMIO_MAGIC_VALUE) != VIRTIO_MMIO_MAGIC {
+    return None;
+  }
+  match read(VIRTIO_MMIO_DEVICE_ID) {
+    VIRTIO_ID_NET => Some(Driver::VirtioNet),
+    VIRTIO_ID_BLOCK => Some(Driver::VirtioBlk),
+    VIRTIO_ID_CONSOLE => Some(Driver::VirtioConsole),
+    VIRTIO_ID_RNG => Some(Driver::VirtioRng),
+    _ => None,
   }
-  r
 }
 
 pub fn device_to_user_frames(device: &Device) -> Vec<Frame> {
   let mut result = Vec::new();
-  let start = round_down(device.register.start, PAGE_SIZE);
-  for pa in (start..device.register.end).step_by(PAGE_SIZE) {
-    result.push(Frame::from(pa));
+  let bars = device.pci.iter().flat_map(|pci| pci.bar_ranges());
+  for range in device.registers.iter().cloned().chain(bars) {
+    let start = round_down(range.start, PAGE_SIZE);
+    for pa in (start..range.end).step_by(PAGE_SIZE) {
+      result.push(Frame::from(pa));
+    }
   }
   result
 }
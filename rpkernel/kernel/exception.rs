@@ -1,13 +1,20 @@
+use alloc::vec::Vec;
 use core::mem::size_of;
 
+use rpabi::syscall::mm::EntryAttribute;
+use spin::Mutex;
+
 #[cfg(feature = "error_unwind")]
 use unwind::unwind_from_exception;
 
 use crate::arch::{ContextFrame, PAGE_SIZE};
 use crate::kernel::cpu::cpu;
-use crate::kernel::thread::thread_destroy;
-use crate::kernel::traits::ArchTrait;
-use crate::kernel::traits::ContextFrameTrait;
+use crate::kernel::thread::{
+  Status, Thread, Tid, thread_destroy, thread_lookup, thread_sleep, thread_suspend, thread_wake,
+};
+use crate::kernel::address_space::AddressSpace;
+use crate::kernel::traits::{Address, ArchTrait, ContextFrameTrait};
+use crate::syscall::SyscallOutRegisters::Pentad;
 use crate::util::{round_down, round_up};
 
 enum HandleResult {
@@ -18,48 +25,80 @@ enum HandleResult {
   Err(&'static str), // system state corrupt (something goes very wrong)
 }
 
+// `ContextFrame` and fault address pushed to the user stack
+const FRAME_SIZE: usize = size_of::<ContextFrame>() + size_of::<usize>();
+
+// Copy `data` to user `va` of `a` through the kernel mapping of its frames, allocating pages not mapped yet
+// Note: the user stack pointer is untrusted, every page touched must be writable by user already
+fn push_user(a: &AddressSpace, va: usize, data: &[u8]) -> Result<(), &'static str> {
+  let mut pt = a.page_table();
+  let end = va + data.len();
+  for page in (round_down(va, PAGE_SIZE)..end).step_by(PAGE_SIZE) {
+    let entry = match pt.lookup_page(page) {
+      Some(entry) => entry,
+      None => {
+        let frame = crate::mm::page_pool::page_alloc().map_err(|_| "out of memory")?;
+        frame.zero();
+        pt.insert_page(page, crate::mm::Frame::from(frame), EntryAttribute::user_default())
+          .map_err(|_| "exception stack page insert failed")?;
+        pt.lookup_page(page).ok_or("exception stack page insert failed")?
+      }
+    };
+    let attr = entry.attribute();
+    if !attr.writable() || !attr.u_readable() || attr.device() || attr.copy_on_write() {
+      return Err("exception stack not writable");
+    }
+    let from = va.max(page);
+    let to = end.min(page + PAGE_SIZE);
+    unsafe {
+      core::ptr::copy_nonoverlapping(
+        data[from - va..to - va].as_ptr(),
+        (entry.pa() + (from - page)).pa2kva() as *mut u8,
+        to - from,
+      );
+    }
+  }
+  Ok(())
+}
+
+// Exception delivery protocol (any address space with a handler registered):
+// the faulting `ContextFrame` followed by the fault address is pushed below the user stack pointer,
+// then the thread jumps to the handler with a pointer to the pushed frame as its argument.
+// The handler may resume the faulting context by `SYS_EXCEPTION_RETURN`.
 fn handle() -> HandleResult {
   if let Some(t) = crate::kernel::cpu::cpu().running_thread() {
     if let Some(a) = t.address_space() {
-      if a.asid() == 1 {
-        // trusted exception
-        if let Some(handler) = a.exception_handler() {
-          let ctx = cpu().context_mut();
-          info!(
-            "trusted exception elr {:016x} far {:016x} sp {:016x}",
-            ctx.exception_pc(),
-            crate::arch::Arch::fault_address(),
-            ctx.stack_pointer()
-          );
-          let ctx_copied = *ctx;
-          ctx.set_exception_pc(handler);
-          let sp = ctx.stack_pointer() - round_up(size_of::<ContextFrame>(), 16);
-          ctx.set_stack_pointer(sp);
-          let sp_va = round_down(sp, PAGE_SIZE);
-          let mut pt = a.page_table();
-          if let None = pt.lookup_user_page(sp_va) {
-            if let Ok(frame) = crate::mm::page_pool::page_alloc() {
-              if let Err(_) = pt.insert_page(
-                sp_va,
-                crate::mm::Frame::from(frame),
-                rpabi::syscall::mm::EntryAttribute::user_default(),
-              ) {
-                return HandleResult::Err("page insert failed");
-              }
-            } else {
-              thread_destroy(t);
-              return HandleResult::Kill("out of memory");
-            }
-          }
-          unsafe {
-            (sp as *mut ContextFrame).write(ctx_copied);
+      if let Some(handler) = a.exception_handler() {
+        let ctx = cpu().context_mut();
+        let fault_address = crate::arch::Arch::fault_address();
+        info!(
+          "user exception asid {} elr {:016x} far {:016x} sp {:016x}",
+          a.asid(),
+          ctx.exception_pc(),
+          fault_address,
+          ctx.stack_pointer()
+        );
+        let ctx_copied = *ctx;
+        let sp = match round_down(ctx.stack_pointer(), 16).checked_sub(round_up(FRAME_SIZE, 16)) {
+          Some(sp) if sp + FRAME_SIZE <= rpabi::CONFIG_USER_LIMIT => sp,
+          _ => {
+            thread_destroy(t);
+            return HandleResult::Kill("exception stack out of user space");
           }
-          ctx.set_argument(sp);
-          HandleResult::Ok
-        } else {
+        };
+        let mut frame = [0u8; FRAME_SIZE];
+        unsafe {
+          (frame.as_mut_ptr() as *mut ContextFrame).write_unaligned(ctx_copied);
+          (frame.as_mut_ptr().add(size_of::<ContextFrame>()) as *mut usize).write_unaligned(fault_address);
+        }
+        if let Err(e) = push_user(&a, sp, &frame) {
           thread_destroy(t);
-          HandleResult::Kill("trusted has no handler")
+          return HandleResult::Kill(e);
         }
+        ctx.set_exception_pc(handler);
+        ctx.set_stack_pointer(sp);
+        ctx.set_argument(sp);
+        HandleResult::Ok
       } else {
         thread_destroy(t);
         HandleResult::Kill("user program exception")
@@ -72,7 +111,68 @@ fn handle() -> HandleResult {
   }
 }
 
+// (pager, thread) of threads faulting while their pager was busy
+static PAGER_WAITERS: Mutex<Vec<(Tid, Thread)>> = Mutex::new(Vec::new());
+
+// `pager` waits for its next request, threads queued on it retry their faulting instruction
+pub fn pager_idle(pager: Tid) {
+  PAGER_WAITERS.lock().retain(|(p, t)| {
+    if *p == pager {
+      thread_wake(t);
+      false
+    } else {
+      true
+    }
+  });
+}
+
+// Forget `tid` as a waiter and wake the threads waiting on it as a pager, called as it is destroyed
+pub fn pager_release(tid: Tid) {
+  PAGER_WAITERS.lock().retain(|(_, t)| t.tid() != tid);
+  pager_idle(tid);
+}
+
+// Send the exception to the pager of the address space as an IPC request from the faulting thread
+// The faulting thread sleeps until the pager replies, see `syscall::ipc::pager_reply`
+fn forward_to_pager() -> bool {
+  let t = match cpu().running_thread() {
+    Some(t) => t,
+    None => return false,
+  };
+  if t.take_pager_bypass() {
+    return false;
+  }
+  let pager = match t.address_space().and_then(|a| a.pager()) {
+    Some(tid) => match thread_lookup(tid) {
+      Ok(pager) => pager,
+      Err(_) => return false,
+    },
+    None => return false,
+  };
+  let pc = cpu().context().exception_pc();
+  let va = crate::arch::Arch::fault_address();
+  let access = crate::arch::Arch::fault_access();
+  // Note: held across the attempt, a pager turning idle meanwhile finds the thread queued, see `pager_idle`
+  let mut waiters = PAGER_WAITERS.lock();
+  if !pager.wait_for_request(|| {
+    pager.map_with_context(|ctx| {
+      ctx.set_syscall_result(&Pentad(t.tid(), rpabi::pager::MESSAGE_FAULT, va, access, pc), 0);
+    });
+    thread_sleep(&t, Status::WaitForPager);
+  }) {
+    // Note: pager is busy; the faulting instruction faults again once the pager waits for a request
+    waiters.push((pager.tid(), t.clone()));
+    thread_sleep(&t, Status::Sleep);
+  }
+  drop(waiters);
+  cpu().tick(false);
+  true
+}
+
 pub fn handle_user() {
+  if forward_to_pager() {
+    return;
+  }
   match handle() {
     HandleResult::Ok => {}
     HandleResult::Kill(e) => {
@@ -83,6 +183,19 @@ pub fn handle_user() {
   }
 }
 
+// A thread armed by `thread_resume(.., single_step)` retired one instruction
+// Disarm and park it for the debugger, see `EVENT_THREAD_SUSPEND`
+pub fn handle_single_step() {
+  let ctx = cpu().context_mut();
+  ctx.set_single_step(false);
+  if let Some(t) = cpu().running_thread() {
+    thread_suspend(&t);
+    cpu().tick(false);
+  } else {
+    panic!("single step without running thread");
+  }
+}
+
 pub fn handle_kernel(ctx: &ContextFrame, is_page_fault: bool) {
   if is_page_fault {
     error!(
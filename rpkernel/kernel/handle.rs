@@ -0,0 +1,95 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use rpabi::syscall::error::{ERROR_INVARG, ERROR_OOM, ERROR_OOR, ERROR_STALE};
+
+pub type Error = usize;
+
+struct Slot<T> {
+  generation: usize,
+  object: Option<T>,
+}
+
+// Handle layout: [ generation ][ index ]
+// Index 0 is never handed out, so a valid handle is never 0 (0 means "current" in syscalls).
+// Freeing a slot bumps its generation, so handles kept after the object died are rejected
+// with `ERROR_STALE` instead of aliasing the object that reuses the slot.
+pub struct HandleTable<T, const INDEX_BITS: usize, const GENERATION_BITS: usize> {
+  slots: Vec<Slot<T>>,
+  free: VecDeque<usize>,
+}
+
+impl<T: Clone, const INDEX_BITS: usize, const GENERATION_BITS: usize> HandleTable<T, INDEX_BITS, GENERATION_BITS> {
+  const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+  const GENERATION_MASK: usize = (1 << GENERATION_BITS) - 1;
+
+  pub const fn new() -> Self {
+    HandleTable {
+      slots: Vec::new(),
+      free: VecDeque::new(),
+    }
+  }
+
+  fn handle(index: usize, generation: usize) -> usize {
+    (generation << INDEX_BITS) | index
+  }
+
+  fn slot(&self, handle: usize) -> Result<&Slot<T>, Error> {
+    let index = handle & Self::INDEX_MASK;
+    let generation = handle >> INDEX_BITS;
+    if index == 0 || generation > Self::GENERATION_MASK {
+      return Err(ERROR_INVARG);
+    }
+    match self.slots.get(index - 1) {
+      None => Err(ERROR_INVARG),
+      Some(slot) if slot.generation != generation => Err(ERROR_STALE),
+      Some(slot) if slot.object.is_none() => Err(ERROR_INVARG),
+      Some(slot) => Ok(slot),
+    }
+  }
+
+  // `f` receives the handle so objects can record their own identifier
+  pub fn insert_with<F>(&mut self, f: F) -> Result<T, Error> where F: FnOnce(usize) -> Result<T, Error> {
+    // Note: reuse the least recently freed slot to keep generations apart as long as possible
+    let index = match self.free.pop_front() {
+      Some(index) => index,
+      None => {
+        if self.slots.len() == Self::INDEX_MASK {
+          return Err(ERROR_OOR);
+        }
+        self.slots.try_reserve(1).map_err(|_| ERROR_OOM)?;
+        self.slots.push(Slot { generation: 0, object: None });
+        self.slots.len()
+      }
+    };
+    let slot = &mut self.slots[index - 1];
+    match f(Self::handle(index, slot.generation)) {
+      Ok(object) => {
+        slot.object = Some(object.clone());
+        Ok(object)
+      }
+      Err(e) => {
+        self.free.push_front(index);
+        Err(e)
+      }
+    }
+  }
+
+  pub fn get(&self, handle: usize) -> Result<T, Error> {
+    self.slot(handle).map(|slot| slot.object.clone().unwrap())
+  }
+
+  pub fn remove(&mut self, handle: usize) -> Result<T, Error> {
+    self.slot(handle)?;
+    let index = handle & Self::INDEX_MASK;
+    let slot = &mut self.slots[index - 1];
+    let object = slot.object.take().unwrap();
+    slot.generation = (slot.generation + 1) & Self::GENERATION_MASK;
+    self.free.push_back(index);
+    Ok(object)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item=&T> {
+    self.slots.iter().filter_map(|slot| slot.object.as_ref())
+  }
+}
@@ -1,10 +1,25 @@
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 
+use rpabi::syscall::error::{ERROR_DENIED, ERROR_INVARG};
 use spin::Mutex;
 
-use crate::driver::Interrupt;
+use crate::driver::{Interrupt, INTERRUPT_CONTROLLER};
 use crate::kernel::semaphore::{Semaphore, SemaphoreWaitResult};
-use crate::kernel::thread::Thread;
+use crate::kernel::thread::{Status, Thread, Tid};
+
+pub type Error = usize;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TriggerMode {
+  Edge,
+  Level,
+}
+
+// 0 (least urgent) ..= `INTERRUPT_PRIORITY_MAX`, scaled by each controller to its own encoding
+pub type InterruptPriority = usize;
+pub const INTERRUPT_PRIORITY_MAX: InterruptPriority = rpabi::irq::PRIORITY_MAX;
+pub const INTERRUPT_PRIORITY_DEFAULT: InterruptPriority = rpabi::irq::PRIORITY_DEFAULT;
 
 pub trait InterruptController {
   fn init(&self);
@@ -12,6 +27,20 @@ pub trait InterruptController {
   fn enable(&self, int: Interrupt);
   fn disable(&self, int: Interrupt);
 
+  fn set_priority(&self, int: Interrupt, priority: InterruptPriority);
+  fn set_trigger(&self, int: Interrupt, trigger: TriggerMode);
+  // route the interrupt to core `core_id`
+  fn set_affinity(&self, int: Interrupt, core_id: usize);
+
+  // Allocate a message-signalled interrupt for device `device_id`
+  // Returns (interrupt, doorbell physical address, data) that the device writes to raise it
+  fn msi_alloc(&self, _device_id: usize) -> Result<(Interrupt, usize, usize), Error> {
+    Err(ERROR_INVARG)
+  }
+  fn msi_free(&self, _int: Interrupt) -> Result<(), Error> {
+    Err(ERROR_INVARG)
+  }
+
   fn fetch(&self) -> Option<(Interrupt, usize)>;
   fn finish(&self, int: Interrupt);
 }
@@ -26,31 +55,156 @@ pub trait InterProcessorInterruptController {
   fn send_to_multiple(&self, irq: InterProcessInterrupt, target_mask: usize);
 }
 
-pub struct InterruptSemaphore(Mutex<BTreeMap<Interrupt, Semaphore>>);
+// upper bound of interrupt numbers accepted from user space
+pub const INTERRUPT_NUMBER_MAX: usize = crate::driver::INTERRUPT_NUMBER_MAX;
+
+// Binding of a device interrupt line to one driver thread
+struct IrqHandler {
+  owner: Tid,
+  semaphore: Semaphore,
+  // masked by owner explicitly
+  masked: bool,
+  // delivered but not yet acknowledged
+  in_service: bool,
+}
 
-pub static INT_SEM: InterruptSemaphore = InterruptSemaphore(Mutex::new(BTreeMap::new()));
+// Device interrupt line bound to one thread, or fanned out to several if shared
+// The line is masked by kernel upon delivery and unmasked once every woken handler has called `irq_ack`,
+// so that a level-triggered device stays quiet until its driver has serviced it
+struct IrqLine {
+  shared: bool,
+  handlers: Vec<IrqHandler>,
+}
 
-impl InterruptSemaphore {
-  pub fn wait(&self, t: Thread, i: Interrupt) -> SemaphoreWaitResult {
-    let mut map = self.0.lock();
-    if let Some(sem) = map.get(&i) {
-      sem.wait(t)
+impl IrqLine {
+  fn update(&self, int: Interrupt) {
+    let in_service = self.handlers.iter().any(|h| h.in_service);
+    let listening = self.handlers.iter().any(|h| !h.masked);
+    if listening && !in_service {
+      INTERRUPT_CONTROLLER.enable(int);
     } else {
-      let sem = Semaphore::new();
-      sem.wait(t);
-      map.insert(i, sem);
-      SemaphoreWaitResult::Enqueued
+      INTERRUPT_CONTROLLER.disable(int);
     }
   }
 
-  pub fn signal(&self, i: Interrupt) {
+  fn handler_mut(&mut self, owner: Tid) -> Option<&mut IrqHandler> {
+    self.handlers.iter_mut().find(|h| h.owner == owner)
+  }
+}
+
+pub struct IrqTable(Mutex<BTreeMap<Interrupt, IrqLine>>);
+
+pub static IRQ_TABLE: IrqTable = IrqTable(Mutex::new(BTreeMap::new()));
+
+impl IrqTable {
+  // Bind line `int` to thread `owner` and unmask it
+  // A line bound exclusively to another thread must be unbound first
+  pub fn bind(&self, int: Interrupt, owner: Tid, shared: bool) -> Result<(), Error> {
     let mut map = self.0.lock();
-    if let Some(sem) = map.get(&i) {
-      sem.signal();
-    } else {
-      let sem = Semaphore::new();
-      sem.signal();
-      map.insert(i, sem);
+    let line = map.entry(int).or_insert_with(|| IrqLine {
+      shared,
+      handlers: Vec::new(),
+    });
+    if line.handlers.iter().any(|h| h.owner == owner) {
+      return Ok(());
+    }
+    if !line.handlers.is_empty() && !(line.shared && shared) {
+      return Err(ERROR_DENIED);
+    }
+    line.handlers.push(IrqHandler {
+      owner,
+      semaphore: Semaphore::new(),
+      masked: false,
+      in_service: false,
+    });
+    line.update(int);
+    Ok(())
+  }
+
+  pub fn unbind(&self, int: Interrupt) -> Result<(), Error> {
+    let mut map = self.0.lock();
+    match map.remove(&int) {
+      Some(line) => {
+        INTERRUPT_CONTROLLER.disable(int);
+        // Note: wake owners which are waiting; their following `irq_ack` fails
+        for h in line.handlers.iter() {
+          h.semaphore.signal();
+        }
+        Ok(())
+      }
+      None => Err(ERROR_INVARG),
+    }
+  }
+
+  // Unbind every handler whose owner is released, called when threads are destroyed
+  pub fn release<F>(&self, f: F) where F: Fn(Tid) -> bool {
+    let mut map = self.0.lock();
+    map.retain(|int, line| {
+      let before = line.handlers.len();
+      line.handlers.retain(|h| !f(h.owner));
+      if line.handlers.is_empty() {
+        INTERRUPT_CONTROLLER.disable(*int);
+        false
+      } else {
+        if line.handlers.len() != before {
+          line.update(*int);
+        }
+        true
+      }
+    });
+  }
+
+  pub fn wait(&self, t: Thread, int: Interrupt) -> Result<SemaphoreWaitResult, Error> {
+    let mut map = self.0.lock();
+    match map.get_mut(&int).and_then(|line| line.handler_mut(t.tid())) {
+      Some(h) => Ok(h.semaphore.wait(t)),
+      None => Err(ERROR_DENIED),
+    }
+  }
+
+  fn modify<F>(&self, owner: Tid, int: Interrupt, f: F) -> Result<(), Error> where F: FnOnce(&mut IrqHandler) {
+    let mut map = self.0.lock();
+    match map.get_mut(&int) {
+      Some(line) => match line.handler_mut(owner) {
+        Some(h) => {
+          f(h);
+          line.update(int);
+          Ok(())
+        }
+        None => Err(ERROR_DENIED),
+      },
+      None => Err(ERROR_DENIED),
+    }
+  }
+
+  pub fn mask(&self, owner: Tid, int: Interrupt) -> Result<(), Error> {
+    self.modify(owner, int, |h| h.masked = true)
+  }
+
+  pub fn unmask(&self, owner: Tid, int: Interrupt) -> Result<(), Error> {
+    self.modify(owner, int, |h| h.masked = false)
+  }
+
+  pub fn ack(&self, owner: Tid, int: Interrupt) -> Result<(), Error> {
+    self.modify(owner, int, |h| h.in_service = false)
+  }
+
+  fn signal(&self, int: Interrupt) {
+    let mut map = self.0.lock();
+    match map.get_mut(&int) {
+      Some(line) => {
+        // Note: every unmasked handler of a shared line checks its own device
+        for h in line.handlers.iter_mut().filter(|h| !h.masked) {
+          h.in_service = true;
+          h.semaphore.signal();
+        }
+        line.update(int);
+      }
+      None => {
+        // Note: nobody owns the line, keep it quiet
+        warn!("unbound interrupt {}", int);
+        INTERRUPT_CONTROLLER.disable(int);
+      }
     }
   }
 }
@@ -58,7 +212,7 @@ impl InterruptSemaphore {
 // Routine that exception handler calls to handle external interrupt (SPI interrupts in AArch64 or PLIC interrupts in Riscv64)
 pub fn interrupt(int: Interrupt) {
   trace!("external {}", int);
-  INT_SEM.signal(int);
+  IRQ_TABLE.signal(int);
 }
 
 // Routine that exception handler calls to handle inter process interrupts
@@ -68,6 +222,12 @@ pub fn ipi_interrupt(ipi: InterProcessInterrupt, _src_cpu: usize) {
       let this = crate::kernel::cpu::cpu();
       if this.running_idle() {
         this.tick(false);
+      } else if let Some(t) = this.running_thread() {
+        // Note: running thread was killed (see `thread_destroy_all`) or suspended (see `thread_suspend`) by another
+        // cpu, switching away reaps or parks it
+        if t.status() == Status::Exited || t.suspended() {
+          this.tick(false);
+        }
       }
     }
   }
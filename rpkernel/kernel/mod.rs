@@ -8,7 +8,9 @@ pub mod cpu;
 pub mod interrupt;
 pub mod stack;
 pub mod device;
+pub mod pci;
 pub mod traits;
 pub mod timer;
 pub mod exception;
-pub mod semaphore;
\ No newline at end of file
+pub mod semaphore;
+pub mod handle;
\ No newline at end of file
@@ -0,0 +1,338 @@
+use core::fmt::Write;
+use core::ops::Range;
+
+use fdt::Fdt;
+
+use crate::driver::Interrupt;
+use crate::kernel::device::{fdt_cell as cell, fdt_interrupt, Device, Driver, PciFunction, PCI_BAR_NUM};
+#[allow(unused_imports)]
+use crate::kernel::traits::Address;
+
+pub const PCI_DEVICE_NUM: u8 = 32;
+pub const PCI_FUNCTION_NUM: u8 = 8;
+pub const PCI_CONFIG_SPACE_SIZE: usize = 0x1000;
+
+const PCI_VENDOR_ID: usize = 0x00;
+const PCI_DEVICE_ID: usize = 0x02;
+const PCI_COMMAND: usize = 0x04;
+const PCI_CLASS_REVISION: usize = 0x08;
+const PCI_HEADER_TYPE: usize = 0x0e;
+const PCI_BAR0: usize = 0x10;
+const PCI_INTERRUPT_PIN: usize = 0x3d;
+
+const PCI_COMMAND_MEMORY: u16 = 1 << 1;
+const PCI_COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+const PCI_HEADER_TYPE_MASK: u8 = 0x7f;
+const PCI_HEADER_TYPE_NORMAL: u8 = 0;
+const PCI_HEADER_TYPE_MULTI_FUNCTION: u8 = 0x80;
+
+const PCI_BAR_IO: u32 = 0b1;
+const PCI_BAR_TYPE_MASK: u32 = 0b110;
+const PCI_BAR_TYPE_64: u32 = 0b100;
+const PCI_BAR_MEMORY_MASK: u32 = !0xf;
+
+const PCI_CLASS_BRIDGE: u32 = 0x06;
+const PCI_SPACE_MEMORY_32: u32 = 0b10;
+const PCI_VENDOR_NONE: u16 = 0xffff;
+
+const PCI_VENDOR_VIRTIO: u16 = 0x1af4;
+// transitional and modern (0x1040 + virtio device id) ids
+const PCI_DEVICE_VIRTIO_BLK: [u16; 2] = [0x1001, 0x1042];
+const PCI_DEVICE_VIRTIO_NET: [u16; 2] = [0x1000, 0x1041];
+const PCI_DEVICE_VIRTIO_CONSOLE: [u16; 2] = [0x1003, 0x1043];
+const PCI_DEVICE_VIRTIO_RNG: [u16; 2] = [0x1005, 0x1044];
+
+/// Memory window of a host bridge which BARs are assigned from
+///
+/// `bus` is the address range seen by devices, `cpu_offset` is added to get the physical address.
+pub struct MemoryWindow {
+  pub bus: Range<usize>,
+  pub cpu_offset: usize,
+}
+
+#[cfg(target_arch = "x86_64")]
+fn config_va(pa: usize) -> usize {
+  pa | (crate::arch::mmu::NON_CACHE_BASE as usize)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn config_va(pa: usize) -> usize {
+  pa.pa2kva()
+}
+
+struct Config(usize);
+
+impl Config {
+  fn read<T: Copy>(&self, offset: usize) -> T {
+    unsafe { ((self.0 + offset) as *const T).read_volatile() }
+  }
+
+  fn write<T: Copy>(&self, offset: usize, value: T) {
+    unsafe { ((self.0 + offset) as *mut T).write_volatile(value) }
+  }
+}
+
+/// Enumerate functions behind an ECAM window
+///
+/// `config` returns the physical address of the configuration space of a function, or `None` if the bus is not covered.
+/// If `window` is present, memory BARs are (re)assigned from it. Otherwise addresses left by firmware are kept.
+/// `route` maps an interrupt pin (1 for INTA to 4 for INTD) of a function to a platform interrupt.
+/// Bridges are neither configured nor reported.
+pub fn enumerate<C, R, F>(segment: u16, config: C, window: Option<MemoryWindow>, route: R, mut f: F)
+where
+  C: Fn(u8, u8, u8) -> Option<usize>,
+  R: Fn(u8, u8, u8, u8) -> Option<Interrupt>,
+  F: FnMut(&Device),
+{
+  let mut next = window.as_ref().map(|w| w.bus.start);
+  for bus in 0..=u8::MAX {
+    for device in 0..PCI_DEVICE_NUM {
+      for function in 0..PCI_FUNCTION_NUM {
+        let pa = match config(bus, device, function) {
+          Some(pa) => pa,
+          None => continue,
+        };
+        let c = Config(config_va(pa));
+        let vendor_id: u16 = c.read(PCI_VENDOR_ID);
+        if vendor_id == PCI_VENDOR_NONE {
+          if function == 0 {
+            break;
+          }
+          continue;
+        }
+        let header_type: u8 = c.read(PCI_HEADER_TYPE);
+        let class = c.read::<u32>(PCI_CLASS_REVISION) >> 8;
+        if header_type & PCI_HEADER_TYPE_MASK == PCI_HEADER_TYPE_NORMAL && class >> 16 != PCI_CLASS_BRIDGE {
+          let mut pci = PciFunction {
+            class,
+            segment,
+            vendor_id,
+            device_id: c.read(PCI_DEVICE_ID),
+            bus,
+            device,
+            function,
+            bars: Default::default(),
+          };
+          let command: u16 = c.read(PCI_COMMAND);
+          c.write(PCI_COMMAND, command & !(PCI_COMMAND_MEMORY | PCI_COMMAND_BUS_MASTER));
+          probe_bars(&c, &mut pci, window.as_ref(), &mut next);
+          c.write(PCI_COMMAND, command | PCI_COMMAND_MEMORY | PCI_COMMAND_BUS_MASTER);
+          let pin: u8 = c.read(PCI_INTERRUPT_PIN);
+          let interrupt = match pin {
+            1..=4 => route(bus, device, function, pin),
+            _ => None,
+          };
+          info!(
+            "pci {:04x}:{:02x}:{:02x}.{} [{:04x}:{:04x}] class {:06x} interrupt {:?}",
+            segment, bus, device, function, vendor_id, pci.device_id, class, interrupt
+          );
+          let mut name = Name([0; PCI_NAME_LEN], 0);
+          let _ = write!(name, "pci{:04x}:{:02x}:{:02x}.{}", segment, bus, device, function);
+          // Note: the same form as `compatible` of PCI nodes in FDT
+          let mut compatible = Name([0; PCI_NAME_LEN], 0);
+          let _ = write!(compatible, "pci{:x},{:x}", vendor_id, pci.device_id);
+          let registers = [pa..(pa + PCI_CONFIG_SPACE_SIZE)];
+          let interrupts = [interrupt.unwrap_or(0)];
+          f(&Device {
+            name: name.as_str(),
+            compatible: compatible.as_str(),
+            registers: &registers,
+            ports: &[],
+            interrupts: &interrupts[..interrupt.map_or(0, |_| 1)],
+            driver: driver(&pci),
+            pci: Some(pci),
+          });
+        }
+        if function == 0 && header_type & PCI_HEADER_TYPE_MULTI_FUNCTION == 0 {
+          break;
+        }
+      }
+    }
+  }
+}
+
+// Note: virtio drivers in user space speak the modern transport only
+fn driver(pci: &PciFunction) -> Option<Driver> {
+  match pci.vendor_id {
+    PCI_VENDOR_VIRTIO if PCI_DEVICE_VIRTIO_BLK.contains(&pci.device_id) => Some(Driver::VirtioBlk),
+    PCI_VENDOR_VIRTIO if PCI_DEVICE_VIRTIO_NET.contains(&pci.device_id) => Some(Driver::VirtioNet),
+    PCI_VENDOR_VIRTIO if PCI_DEVICE_VIRTIO_CONSOLE.contains(&pci.device_id) => Some(Driver::VirtioConsole),
+    PCI_VENDOR_VIRTIO if PCI_DEVICE_VIRTIO_RNG.contains(&pci.device_id) => Some(Driver::VirtioRng),
+    _ => None,
+  }
+}
+
+fn probe_bars(
+  c: &Config,
+  pci: &mut PciFunction,
+  window: Option<&MemoryWindow>,
+  next: &mut Option<usize>,
+) {
+  let mut i = 0;
+  while i < PCI_BAR_NUM {
+    let offset = PCI_BAR0 + i * 4;
+    let low: u32 = c.read(offset);
+    if low & PCI_BAR_IO != 0 {
+      i += 1;
+      continue;
+    }
+    let is_64 = low & PCI_BAR_TYPE_MASK == PCI_BAR_TYPE_64 && i + 1 < PCI_BAR_NUM;
+    let high: u32 = if is_64 { c.read(offset + 4) } else { 0 };
+    c.write(offset, u32::MAX);
+    let mut mask = (c.read::<u32>(offset) & PCI_BAR_MEMORY_MASK) as u64;
+    c.write(offset, low);
+    if is_64 {
+      c.write(offset + 4, u32::MAX);
+      mask |= (c.read::<u32>(offset + 4) as u64) << 32;
+      c.write(offset + 4, high);
+    } else if mask != 0 {
+      mask |= 0xffff_ffff_0000_0000;
+    }
+    if mask != 0 {
+      let size = (!mask).wrapping_add(1) as usize;
+      let base = match (window, next.as_mut()) {
+        (Some(w), Some(next)) => {
+          let addr = crate::util::round_up(*next, size);
+          if addr + size <= w.bus.end {
+            *next = addr + size;
+            c.write(offset, (addr as u32) | (low & !PCI_BAR_MEMORY_MASK));
+            if is_64 {
+              c.write(offset + 4, (addr as u64 >> 32) as u32);
+            }
+            Some(addr.wrapping_add(w.cpu_offset))
+          } else {
+            warn!("pci bar size {:x} exceeds memory window", size);
+            None
+          }
+        }
+        _ => {
+          // Note: keep the address assigned by firmware
+          let addr = ((high as u64) << 32 | (low & PCI_BAR_MEMORY_MASK) as u64) as usize;
+          if addr != 0 { Some(addr) } else { None }
+        }
+      };
+      pci.set_bar(i, base.map(|base| base..(base + size)));
+    }
+    i += if is_64 { 2 } else { 1 };
+  }
+}
+
+const PCI_NAME_LEN: usize = 32;
+
+struct Name([u8; PCI_NAME_LEN], usize);
+
+impl Name {
+  fn as_str(&self) -> &str {
+    core::str::from_utf8(&self.0[..self.1]).unwrap_or("")
+  }
+}
+
+impl Write for Name {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    for b in s.bytes() {
+      if self.1 < PCI_NAME_LEN {
+        self.0[self.1] = b;
+        self.1 += 1;
+      }
+    }
+    Ok(())
+  }
+}
+
+fn cells_to_usize(value: &[u8], range: Range<usize>) -> usize {
+  range.fold(0, |acc, i| (acc << 32) | cell(value, i).unwrap_or(0) as usize)
+}
+
+/// Enumerate the `pci-host-ecam-generic` host bridge described by FDT
+///
+/// BARs are assigned from the first 32-bit memory window in `ranges`, interrupts follow `interrupt-map`.
+pub fn enumerate_fdt<F: FnMut(&Device)>(fdt: &Fdt, f: F) {
+  let node = match fdt.find_compatible(&["pci-host-ecam-generic"]) {
+    Some(node) => node,
+    None => return,
+  };
+  let (ecam, ecam_size) = match node.reg().and_then(|mut reg| reg.next()) {
+    Some(reg) => (reg.starting_address as usize, reg.size.unwrap_or(0)),
+    None => {
+      warn!("pci host {} has no ecam window", node.name);
+      return;
+    }
+  };
+  let (bus_start, bus_end) = match node.property("bus-range") {
+    Some(prop) => (
+      cell(prop.value, 0).unwrap_or(0) as usize,
+      cell(prop.value, 1).unwrap_or(0) as usize,
+    ),
+    None => (0, (ecam_size >> 20).max(1) - 1),
+  };
+  let segment = node
+    .property("linux,pci-domain")
+    .and_then(|prop| prop.as_usize())
+    .unwrap_or(0) as u16;
+
+  let address_cells = node.cell_sizes().address_cells;
+  let size_cells = node.cell_sizes().size_cells;
+  // Note: host bridges of QEMU virt boards sit under nodes sharing the cell sizes of root
+  let parent_address_cells = fdt.root().cell_sizes().address_cells;
+  let mut window = None;
+  if let Some(prop) = node.property("ranges") {
+    let entry_cells = address_cells + parent_address_cells + size_cells;
+    let mut i = 0;
+    while (i + entry_cells) * 4 <= prop.value.len() {
+      let cpu = i + address_cells;
+      let size = cpu + parent_address_cells;
+      if (cell(prop.value, i).unwrap_or(0) >> 24) & 0b11 == PCI_SPACE_MEMORY_32 {
+        let bus = cells_to_usize(prop.value, (i + 1)..cpu);
+        let cpu = cells_to_usize(prop.value, cpu..size);
+        let size = cells_to_usize(prop.value, size..(i + entry_cells));
+        window = Some(MemoryWindow {
+          bus: bus..(bus + size),
+          cpu_offset: cpu.wrapping_sub(bus),
+        });
+        break;
+      }
+      i += entry_cells;
+    }
+  }
+  if window.is_none() {
+    warn!("pci host {} has no 32-bit memory window", node.name);
+  }
+
+  let config = |bus: u8, device: u8, function: u8| {
+    let bus = bus as usize;
+    if bus < bus_start || bus > bus_end {
+      return None;
+    }
+    Some(ecam + ((bus - bus_start) << 20 | (device as usize) << 15 | (function as usize) << 12))
+  };
+  let route = |bus: u8, device: u8, function: u8, pin: u8| {
+    let mask = node.property("interrupt-map-mask")?.value;
+    let map = node.property("interrupt-map")?.value;
+    let child_cells = address_cells + node.interrupt_cells()?;
+    let child = |i: usize| match i {
+      0 => (bus as u32) << 16 | (device as u32) << 11 | (function as u32) << 8,
+      i if i == child_cells - 1 => pin as u32,
+      _ => 0,
+    };
+    let mut i = 0;
+    while (i + child_cells) * 4 < map.len() {
+      let parent = fdt.find_phandle(cell(map, i + child_cells)?)?;
+      let parent_address_cells = parent
+        .property("#address-cells")
+        .and_then(|prop| prop.as_usize())
+        .unwrap_or(0);
+      let spec = i + child_cells + 1 + parent_address_cells;
+      let next = spec + parent.interrupt_cells()?;
+      let matched = (0..child_cells).all(|j| {
+        Some(child(j) & cell(mask, j).unwrap_or(0)) == cell(map, i + j)
+      });
+      if matched {
+        return fdt_interrupt(map, spec, next - spec);
+      }
+      i = next;
+    }
+    None
+  };
+  enumerate(segment, config, window, route, f);
+}
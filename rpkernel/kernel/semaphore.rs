@@ -39,6 +39,11 @@ impl Semaphore {
     }
   }
 
+  pub fn remove<F>(&self, f: F) where F: Fn(&Thread) -> bool {
+    let mut inner = self.inner.lock();
+    inner.queue.retain(|t| !f(t));
+  }
+
   pub fn signal(&self) {
     let mut inner = self.inner.lock();
     if inner.value != 0 {
@@ -30,11 +30,27 @@ static SYSCALL_NAMES: [&str; SYS_MAX] = [
   "getc",
   "yield_to",
   "reply_recv",
+  "thread_suspend",
+  "thread_resume",
+  "thread_register_read",
+  "thread_register_write",
+  "exception_return",
+  "set_pager",
+  "set_page_limit",
+  "kernel_stat",
+  "irq_bind",
+  "irq_mask",
+  "irq_unmask",
+  "irq_ack",
+  "irq_config",
+  "msi_alloc",
+  "msi_free",
+  "io_port_grant",
 ];
 
 
 static SYSCALL_ARGC: [usize; SYS_MAX] = [
-  1, 1, 1, 0, 0, 1, 2, 3, 5, 2, 0, 4, 2, 1, 0, 5, 5, 1, 1, 1, 0, 1, 5
+  1, 1, 1, 0, 0, 1, 2, 3, 5, 2, 0, 4, 2, 1, 0, 5, 5, 1, 1, 1, 0, 1, 5, 1, 2, 2, 3, 1, 2, 2, 1, 3, 1, 1, 1, 3, 1, 1, 3
 ];
 
 pub fn syscall() {
@@ -49,14 +65,22 @@ pub fn syscall() {
     SYS_PUTC => misc::putc(arg(0) as u8 as char),
     SYS_GETC => misc::getc(),
     SYS_SET_EXCEPTION_HANDLER => misc::set_exception_handler(arg(0)),
+    SYS_EXCEPTION_RETURN => misc::exception_return(arg(0)),
+    SYS_KERNEL_STAT => misc::kernel_stat(arg(0)),
     SYS_GET_ASID => address_space::get_asid(arg(0)),
     SYS_ADDRESS_SPACE_ALLOC => address_space::address_space_alloc(),
     SYS_ADDRESS_SPACE_DESTROY => address_space::address_space_destroy(arg(0) as u16),
+    SYS_SET_PAGER => address_space::set_pager(arg(0) as u16, arg(1)),
+    SYS_SET_PAGE_LIMIT => address_space::set_page_limit(arg(0) as u16, arg(1)),
     SYS_GET_TID => thread::get_tid(),
     SYS_THREAD_YIELD => thread::thread_yield(),
     SYS_THREAD_DESTROY => thread::thread_destroy(arg(0)),
     SYS_THREAD_ALLOC => thread::thread_alloc(arg(0) as u16, arg(1), arg(2), arg(3)),
     SYS_THREAD_SET_STATUS => thread::thread_set_status(arg(0), arg(1)),
+    SYS_THREAD_SUSPEND => thread::thread_suspend(arg(0)),
+    SYS_THREAD_RESUME => thread::thread_resume(arg(0), arg(1)),
+    SYS_THREAD_REGISTER_READ => thread::thread_register_read(arg(0), arg(1)),
+    SYS_THREAD_REGISTER_WRITE => thread::thread_register_write(arg(0), arg(1), arg(2)),
     SYS_EVENT_WAIT => event::event_wait(arg(0), arg(1)),
     SYS_MEM_ALLOC => mm::mem_alloc(arg(0) as u16, arg(1), arg(2)),
     SYS_MEM_MAP => mm::mem_map(arg(0) as u16, arg(1), arg(2) as u16, arg(3), arg(4)),
@@ -67,6 +91,14 @@ pub fn syscall() {
     SYS_REPLY_RECV => ipc::itc_reply_recv(arg(0), arg(1), arg(2), arg(3), arg(4)),
     SYS_SERVER_REGISTER => server::server_register(arg(0)),
     SYS_SERVER_TID => server::server_tid(arg(0)),
+    SYS_IRQ_BIND => irq::irq_bind(arg(0), arg(1), arg(2)),
+    SYS_IRQ_MASK => irq::irq_mask(arg(0)),
+    SYS_IRQ_UNMASK => irq::irq_unmask(arg(0)),
+    SYS_IRQ_ACK => irq::irq_ack(arg(0)),
+    SYS_IRQ_CONFIG => irq::irq_config(arg(0), arg(1), arg(2)),
+    SYS_MSI_ALLOC => irq::msi_alloc(arg(0)),
+    SYS_MSI_FREE => irq::msi_free(arg(0)),
+    SYS_IO_PORT_GRANT => io_port::io_port_grant(arg(0) as u16, arg(1), arg(2)),
     _ => {
       warn!("system call: unrecognized system call number");
       Err(ERROR_INVARG)
@@ -83,19 +115,19 @@ pub fn syscall() {
       
       let need_schedule = res.1;
       if need_schedule {
-        cpu().tick(num == SYS_THREAD_YIELD);
+        cpu().tick(num == SYS_THREAD_YIELD || num == SYS_EXCEPTION_RETURN);
       } else {
         ctx.set_syscall_result(&res.0, 0);
       }
       
     }
     Err(err) => {
-      if err != ERROR_HOLD_ON {
+      // Note: `num` is user input and may be out of the tables
+      if err != ERROR_HOLD_ON && num < SYS_MAX {
         let mut arg_str = String::from("(");
-        for i in 0..(SYSCALL_ARGC[num]-1) {
+        for i in 0..SYSCALL_ARGC[num] {
           arg_str += format!("{:x},", arg(i)).as_str();
         }
-        arg_str += format!("{:x},", arg(SYSCALL_ARGC[num]-1)).as_str();
         arg_str += ")";
         info!("{} t{} arg{} Err {:x?}", SYSCALL_NAMES[num], tid, arg_str, err);
       }
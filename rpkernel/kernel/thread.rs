@@ -1,17 +1,21 @@
-use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
-use core::sync::atomic::AtomicUsize;
-use core::sync::atomic::Ordering::Relaxed;
+use alloc::vec::Vec;
 
+use rpabi::syscall::error::{ERROR_OOM, ERROR_STALE};
 use spin::Mutex;
 
 use crate::arch::ContextFrame;
 use crate::kernel::address_space::AddressSpace;
+use crate::kernel::cpu::{cpu, cpu_nth};
+use crate::kernel::handle::HandleTable;
+use crate::kernel::interrupt::{IRQ_TABLE, InterProcessInterrupt, InterProcessorInterruptController};
 use crate::kernel::scheduler::scheduler;
 use crate::kernel::traits::*;
+use crate::mm::slab::{SlabAllocator, THREAD_CACHE};
 use crate::syscall::event::thread_exit_signal;
 
 pub type Tid = usize;
+pub type Error = usize;
 
 #[derive(Debug)]
 pub enum PrivilegedLevel {
@@ -26,6 +30,8 @@ pub enum Status {
   WaitForEvent,
   WaitForReply,
   WaitForRequest,
+  WaitForPager,
+  Exited,
 }
 
 #[derive(Debug)]
@@ -41,6 +47,8 @@ struct InnerMut {
   status: Mutex<Status>,
   context_frame: Mutex<ContextFrame>,
   running_cpu: Mutex<Option<usize>>,
+  suspended: Mutex<bool>,
+  pager_bypass: Mutex<bool>,
 }
 
 struct ControlBlock {
@@ -55,7 +63,7 @@ impl Drop for ControlBlock {
 }
 
 #[derive(Clone)]
-pub struct Thread(Arc<ControlBlock>);
+pub struct Thread(Arc<ControlBlock, SlabAllocator>);
 
 impl Thread {
   pub fn tid(&self) -> Tid {
@@ -96,12 +104,13 @@ impl Thread {
     lock.clone()
   }
 
+  // `wait_for_*` wake the thread only from their own wait status, never an `Exited` one (see `thread_sleep`)
   pub fn wait_for_reply<F>(&self, f: F) -> bool where F: FnOnce() {
     let mut status = self.0.inner_mut.status.lock();
     if *status == Status::WaitForReply {
       f();
       *status = Status::Runnable;
-      scheduler().add(self.clone());
+      self.enqueue();
       true
     } else {
       false
@@ -113,13 +122,50 @@ impl Thread {
     if *status == Status::WaitForRequest {
       f();
       *status = Status::Runnable;
-      scheduler().add(self.clone());
+      self.enqueue();
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn wait_for_pager<F>(&self, f: F) -> bool where F: FnOnce() {
+    let mut status = self.0.inner_mut.status.lock();
+    if *status == Status::WaitForPager {
+      f();
+      *status = Status::Runnable;
+      self.enqueue();
       true
     } else {
       false
     }
   }
 
+  // Next exception skips the pager, see `rpabi::pager::REPLY_UPCALL`
+  pub fn set_pager_bypass(&self) {
+    let mut bypass = self.0.inner_mut.pager_bypass.lock();
+    *bypass = true;
+  }
+
+  pub fn take_pager_bypass(&self) -> bool {
+    let mut bypass = self.0.inner_mut.pager_bypass.lock();
+    core::mem::replace(&mut *bypass, false)
+  }
+
+  pub fn suspended(&self) -> bool {
+    let lock = self.0.inner_mut.suspended.lock();
+    lock.clone()
+  }
+
+  // Hand a runnable thread to the scheduler unless a debugger holds it
+  // Lock order: status -> suspended -> run queues
+  pub fn enqueue(&self) {
+    let suspended = self.0.inner_mut.suspended.lock();
+    if !*suspended {
+      scheduler().add(self.clone());
+    }
+  }
+
   pub fn address_space(&self) -> Option<AddressSpace> {
     self.0.inner.address_space.clone()
   }
@@ -145,17 +191,19 @@ impl Thread {
   }
 }
 
-static THREAD_UUID_ALLOCATOR: AtomicUsize = AtomicUsize::new(100);
-
-fn new_tid() -> Tid {
-  THREAD_UUID_ALLOCATOR.fetch_add(1, Relaxed)
-}
+// Tid layout: [ 48-bit generation ][ 16-bit slot index ]
+const TID_INDEX_BITS: usize = 16;
+const TID_GENERATION_BITS: usize = 48;
 
-static THREAD_MAP: Mutex<BTreeMap<Tid, Thread>> = Mutex::new(BTreeMap::new());
+static THREAD_MAP: Mutex<HandleTable<Thread, TID_INDEX_BITS, TID_GENERATION_BITS>> = Mutex::new(HandleTable::new());
 
-pub fn new_user(pc: usize, sp: usize, arg: usize, a: AddressSpace, parent: Option<Tid>) -> Thread {
-  let id = new_tid();
-  let t = Thread(Arc::new(ControlBlock {
+pub fn new_user(pc: usize, sp: usize, arg: usize, a: AddressSpace, parent: Option<Tid>) -> Result<Thread, Error> {
+  let mut map = THREAD_MAP.lock();
+  // Note: checked under `THREAD_MAP` lock so no thread can slip into an address space being torn down
+  if a.destroyed() {
+    return Err(ERROR_STALE);
+  }
+  map.insert_with(|id| Ok(Thread(Arc::try_new_in(ControlBlock {
     inner: Inner {
       uuid: id,
       parent,
@@ -166,16 +214,15 @@ pub fn new_user(pc: usize, sp: usize, arg: usize, a: AddressSpace, parent: Optio
       status: Mutex::new(Status::Sleep),
       context_frame: Mutex::new(ContextFrame::new(pc, sp, arg, false)),
       running_cpu: Mutex::new(None),
+      suspended: Mutex::new(false),
+      pager_bypass: Mutex::new(false),
     },
-  }));
-  let mut map = THREAD_MAP.lock();
-  map.insert(id, t.clone());
-  t
+  }, SlabAllocator(&THREAD_CACHE)).map_err(|_| ERROR_OOM)?)))
 }
 
-pub fn new_kernel(pc: usize, sp: usize, arg: usize) -> Thread {
-  let id = new_tid();
-  let t = Thread(Arc::new(ControlBlock {
+pub fn new_kernel(pc: usize, sp: usize, arg: usize) -> Result<Thread, Error> {
+  let mut map = THREAD_MAP.lock();
+  map.insert_with(|id| Ok(Thread(Arc::try_new_in(ControlBlock {
     inner: Inner {
       uuid: id,
       parent: None,
@@ -186,16 +233,15 @@ pub fn new_kernel(pc: usize, sp: usize, arg: usize) -> Thread {
       status: Mutex::new(Status::Sleep),
       context_frame: Mutex::new(ContextFrame::new(pc, sp, arg, true)),
       running_cpu: Mutex::new(None),
+      suspended: Mutex::new(false),
+      pager_bypass: Mutex::new(false),
     },
-  }));
-  let mut map = THREAD_MAP.lock();
-  map.insert(id, t.clone());
-  t
+  }, SlabAllocator(&THREAD_CACHE)).map_err(|_| ERROR_OOM)?)))
 }
 
-pub fn thread_lookup(tid: Tid) -> Option<Thread> {
+pub fn thread_lookup(tid: Tid) -> Result<Thread, Error> {
   let map = THREAD_MAP.lock();
-  map.get(&tid).cloned()
+  map.get(tid)
 }
 
 pub fn thread_destroy(t: Thread) {
@@ -205,22 +251,108 @@ pub fn thread_destroy(t: Thread) {
       crate::kernel::cpu::cpu().set_running_thread(None);
     }
   }
+  // Note: a thread killed by `thread_destroy_all` may be reaped by two cpus at once, the first one wins
+  let mut map = THREAD_MAP.lock();
+  if map.remove(t.tid()).is_err() {
+    return;
+  }
+  drop(map);
   if let Some(parent) = t.parent() {
     thread_exit_signal(t.tid(), parent);
   }
+  IRQ_TABLE.release(|tid| tid == t.tid());
+  crate::kernel::timer::release(|tid| tid == t.tid());
+  crate::kernel::exception::pager_release(t.tid());
+  if let Some(a) = t.address_space() {
+    if a.destroyed() {
+      crate::kernel::address_space::address_space_release(&a);
+    }
+  }
+}
+
+pub fn thread_any(a: &AddressSpace) -> bool {
+  let map = THREAD_MAP.lock();
+  map.iter().any(|t| t.address_space().as_ref() == Some(a))
+}
+
+// Stop and destroy every thread of `a`
+// On return none of them is queued on any cpu or waiting on an interrupt
+// Threads running on other cpus are reaped there as they switch away (see `Core::run`), without waiting for them
+pub fn thread_destroy_all(a: &AddressSpace) {
   let mut map = THREAD_MAP.lock();
-  map.remove(&t.tid());
+  a.set_destroyed();
+  let victims: Vec<Thread> = map.iter()
+    .filter(|t| t.address_space().as_ref() == Some(a))
+    .cloned()
+    .collect();
+  for t in victims.iter() {
+    let mut status = t.0.inner_mut.status.lock();
+    *status = Status::Exited;
+  }
+  drop(map);
+  let is_victim = |t: &Thread| victims.iter().any(|v| v.tid() == t.tid());
+  for i in 0..crate::cpu_number() {
+    cpu_nth(i).dequeue_task(is_victim);
+  }
+  IRQ_TABLE.release(|tid| victims.iter().any(|v| v.tid() == tid));
+  crate::kernel::timer::release(|tid| victims.iter().any(|v| v.tid() == tid));
+  let current = cpu().running_thread().map(|t| t.tid());
+  for t in victims {
+    match t.running_cpu() {
+      Some(target) if current != Some(t.tid()) => {
+        // target cpu switches away upon IPI, see `ipi_interrupt`
+        crate::driver::INTERRUPT_CONTROLLER.send_to_one(InterProcessInterrupt::IPI0, target);
+      }
+      _ => thread_destroy(t),
+    }
+  }
 }
 
 pub fn thread_wake(t: &Thread) {
   let mut status = t.0.inner_mut.status.lock();
+  if *status == Status::Exited {
+    return;
+  }
   *status = Status::Runnable;
-  scheduler().add(t.clone());
+  t.enqueue();
 }
 
+// Take `t` off every run queue and off its cpu; it keeps its status, so wake-ups still land
+// A thread running on another cpu leaves it upon IPI, `EVENT_THREAD_SUSPEND` tells when its context frame is saved
+pub fn thread_suspend(t: &Thread) {
+  let mut suspended = t.0.inner_mut.suspended.lock();
+  *suspended = true;
+  drop(suspended);
+  let is_target = |x: &Thread| x.tid() == t.tid();
+  for i in 0..crate::cpu_number() {
+    cpu_nth(i).dequeue_task(is_target);
+  }
+  if let Some(target) = t.running_cpu() {
+    if target != crate::core_id() {
+      // target cpu drops the thread upon IPI, see `ipi_interrupt`
+      crate::driver::INTERRUPT_CONTROLLER.send_to_one(InterProcessInterrupt::IPI0, target);
+    }
+  }
+}
+
+// `t` is suspended and off every cpu
+pub fn thread_resume(t: &Thread) {
+  let status = t.0.inner_mut.status.lock();
+  let mut suspended = t.0.inner_mut.suspended.lock();
+  *suspended = false;
+  drop(suspended);
+  if *status == Status::Runnable {
+    scheduler().add(t.clone());
+  }
+}
+
+// Note: a thread killed meanwhile by `thread_destroy_all` stays `Exited`, so that it is reaped as it leaves the cpu
 pub fn thread_sleep(t: &Thread, reason: Status) {
   assert_ne!(reason, Status::Runnable);
   let mut status = t.0.inner_mut.status.lock();
+  if *status == Status::Exited {
+    return;
+  }
   *status = reason;
   drop(status);
 }
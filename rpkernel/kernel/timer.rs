@@ -1,3 +1,9 @@
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::kernel::thread::{Thread, Tid, thread_wake};
+
 #[allow(dead_code)]
 const TIMER_SEC_TO_MS: usize = 1000;
 #[allow(dead_code)]
@@ -44,7 +50,35 @@ pub fn mdelay(ms: usize) {
   }
 }
 
+// (deadline in ms, thread) of threads waiting for `EVENT_TIMER`
+static SLEEPERS: Mutex<Vec<(usize, Thread)>> = Mutex::new(Vec::new());
+
+// Wake `t` once `ms` milliseconds passed, the caller puts it to sleep
+pub fn sleep(t: Thread, ms: usize) {
+  SLEEPERS.lock().push((current_ms().saturating_add(ms), t));
+}
+
+// Forget sleepers matching `f`, called as they are destroyed
+pub fn release<F: Fn(Tid) -> bool>(f: F) {
+  SLEEPERS.lock().retain(|(_, t)| !f(t.tid()));
+}
+
+fn wake_expired() {
+  let now = current_ms();
+  let mut sleepers = SLEEPERS.lock();
+  // Note: a sleeper still on its way off a cpu is woken on a later tick
+  sleepers.retain(|(deadline, t)| {
+    if *deadline <= now && t.running_cpu().is_none() {
+      thread_wake(t);
+      false
+    } else {
+      true
+    }
+  });
+}
+
 pub fn interrupt() {
+  wake_expired();
   crate::kernel::cpu::cpu().tick(true);
 }
 
@@ -9,8 +9,13 @@ pub trait ArchTrait {
   fn wait_for_interrupt();
   fn nop();
   fn fault_address() -> usize;
+  // access type of the pending user fault as defined in `rpabi::pager`
+  fn fault_access() -> usize;
   fn raw_arch_id() -> usize;
   fn install_user_page_table(base: usize, asid: crate::arch::AddressSpaceId);
+  fn set_user_single_step(enable: bool);
+  // I/O permission of the user address space being switched to, only x86_64 has port I/O
+  fn install_io_port_bitmap(bitmap: Option<&crate::kernel::address_space::IoPortBitmap>);
 }
 
 pub trait ContextFrameTrait {
@@ -25,4 +30,9 @@ pub trait ContextFrameTrait {
   fn set_stack_pointer(&mut self, sp: usize);
   fn set_argument(&mut self, arg: usize);
   fn gpr(&self, index: usize) -> usize;
+  // index as defined in `rpabi::debug`
+  fn register(&self, index: usize) -> Option<usize>;
+  fn set_register(&mut self, index: usize, value: usize) -> Option<()>;
+  fn single_step(&self) -> bool;
+  fn set_single_step(&mut self, enable: bool) -> Option<()>;
 }
@@ -154,8 +154,9 @@ extern "C" fn main(core_id: arch::CoreId, boot_data: usize) -> ! {
     let bin = include_bytes_align_as!(AlignPage, "../trusted/target/x86_64-unknown-rustpi/release/trusted.bin");
 
     info!("embedded trusted {:x}", bin.as_ptr() as usize);
-    let (a, entry) = kernel::address_space::load_image(bin);
+    let (a, entry) = kernel::address_space::load_image(bin).expect("failed to load trusted image");
     info!("load_image ok");
+    kernel::address_space::set_trusted(&a);
 
     let mut page_table = a.page_table();
     let stack_frame = mm::page_pool::page_alloc().expect("failed to allocate trusted stack");
@@ -166,14 +167,49 @@ extern "C" fn main(core_id: arch::CoreId, boot_data: usize) -> ! {
         EntryAttribute::user_default(),
       )
       .unwrap();
-    let plat_info_pa = (&board::PLATFORM_INFO as *const _ as usize).kva2pa();
-    page_table
-      .insert_page(
-        rpabi::CONFIG_TRUSTED_PLATFORM_INFO,
-        mm::Frame::from(mm::PhysicalFrame::new(plat_info_pa)),
-        EntryAttribute::user_readonly(),
-      )
-      .unwrap();
+    let mut plat_info = board::PLATFORM_INFO.lock();
+    // Note: the original device tree is mapped read-only next to platform info
+    if let Some(device_tree) = kernel::device::device_tree() {
+      let start = util::round_down(device_tree.start, arch::PAGE_SIZE);
+      for pa in (start..device_tree.end).step_by(arch::PAGE_SIZE) {
+        page_table
+          .insert_page(
+            rpabi::CONFIG_TRUSTED_DEVICE_TREE + (pa - start),
+            mm::Frame::from(pa),
+            EntryAttribute::user_readonly(),
+          )
+          .unwrap();
+      }
+      plat_info.set_device_tree(device_tree.start - start, device_tree.len());
+    }
+    // Note: ACPI tables are mapped read-only at their physical address plus an offset, as MMIO of drivers
+    if let Some((rsdp, tables)) = kernel::device::acpi_tables() {
+      let mut pages = alloc::collections::BTreeSet::new();
+      for table in tables {
+        let start = util::round_down(table.start, arch::PAGE_SIZE);
+        pages.extend((start..table.end).step_by(arch::PAGE_SIZE));
+      }
+      for pa in pages {
+        page_table
+          .insert_page(
+            rpabi::platform::USER_SPACE_ACPI_OFFSET + pa,
+            mm::Frame::from(pa),
+            EntryAttribute::user_readonly(),
+          )
+          .unwrap();
+      }
+      plat_info.set_acpi_rsdp(*rsdp);
+    }
+    let plat_info_pa = (&*plat_info as *const _ as usize).kva2pa();
+    for offset in (0..rpabi::platform::PLATFORM_INFO_SIZE).step_by(arch::PAGE_SIZE) {
+      page_table
+        .insert_page(
+          rpabi::CONFIG_TRUSTED_PLATFORM_INFO + offset,
+          mm::Frame::from(mm::PhysicalFrame::new(plat_info_pa + offset)),
+          EntryAttribute::user_readonly(),
+        )
+        .unwrap();
+    }
 
     #[cfg(feature = "k210")]
     {
@@ -196,26 +232,22 @@ extern "C" fn main(core_id: arch::CoreId, boot_data: usize) -> ! {
       rpabi::CONFIG_TRUSTED_PLATFORM_INFO,
       a.clone(),
       None,
-    );
+    ).expect("failed to create trusted thread");
     kernel::thread::thread_wake(&t);
 
-    for device in &board::PLATFORM_INFO.get().unwrap().devices {
-      if let Some(device) = device {
-        for uf in kernel::device::device_to_user_frames(device).iter() {
-          page_table
-            .insert_page(
-              rpabi::platform::USER_SPACE_DRIVER_MMIO_OFFSET + uf.pa(),
-              uf.clone(),
-              rpabi::syscall::mm::EntryAttribute::user_device(),
-            )
-            .unwrap();
-        }
-        for i in device.interrupt.iter() {
-          crate::driver::INTERRUPT_CONTROLLER.enable(*i);
-        }
+    for device in plat_info.devices() {
+      for uf in kernel::device::device_to_user_frames(&device).iter() {
+        page_table
+          .insert_page(
+            rpabi::platform::USER_SPACE_DRIVER_MMIO_OFFSET + uf.pa(),
+            uf.clone(),
+            rpabi::syscall::mm::EntryAttribute::user_device(),
+          )
+          .unwrap();
       }
     }
     info!("device added to user space");
+    drop(plat_info);
     drop(page_table);
   }
 
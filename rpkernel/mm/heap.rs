@@ -1,14 +1,72 @@
+use core::alloc::{GlobalAlloc, Layout};
 use core::ops::Range;
+use core::ptr::NonNull;
 
-use buddy_system_allocator::LockedHeap;
+use buddy_system_allocator::Heap;
+use spin::Mutex;
 
 use crate::kernel::traits::*;
 
 pub fn init(range: Range<usize>) {
   unsafe {
-    HEAP_ALLOCATOR.lock().init(range.start.pa2kva(), range.end - range.start)
+    HEAP_ALLOCATOR.0.lock().heap.init(range.start.pa2kva(), range.end - range.start)
+  }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HeapStats {
+  pub total: usize,
+  // bytes requested by callers
+  pub user: usize,
+  // bytes taken from the buddy system (rounded up to power of two)
+  pub actual: usize,
+  pub peak: usize,
+  pub failures: usize,
+}
+
+pub fn stats() -> HeapStats {
+  let inner = HEAP_ALLOCATOR.0.lock();
+  HeapStats {
+    total: inner.heap.stats_total_bytes(),
+    user: inner.heap.stats_alloc_user(),
+    actual: inner.heap.stats_alloc_actual(),
+    peak: inner.peak,
+    failures: inner.failures,
+  }
+}
+
+struct Inner {
+  heap: Heap<32>,
+  peak: usize,
+  failures: usize,
+}
+
+// `buddy_system_allocator::LockedHeap` with peak usage and failure accounting
+struct KernelHeap(Mutex<Inner>);
+
+unsafe impl GlobalAlloc for KernelHeap {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let mut inner = self.0.lock();
+    match inner.heap.alloc(layout) {
+      Ok(ptr) => {
+        inner.peak = inner.peak.max(inner.heap.stats_alloc_actual());
+        ptr.as_ptr()
+      }
+      Err(_) => {
+        inner.failures += 1;
+        core::ptr::null_mut()
+      }
+    }
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    self.0.lock().heap.dealloc(NonNull::new_unchecked(ptr), layout)
   }
 }
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::empty();
+static HEAP_ALLOCATOR: KernelHeap = KernelHeap(Mutex::new(Inner {
+  heap: Heap::<32>::new(),
+  peak: 0,
+  failures: 0,
+}));
@@ -2,6 +2,7 @@ pub use self::page_frame::*;
 
 mod page_frame;
 pub mod page_pool;
+pub mod slab;
 pub mod heap;
 pub mod config;
 pub mod page_table;
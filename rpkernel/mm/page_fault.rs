@@ -15,8 +15,8 @@ pub fn handle() {
         let addr = crate::arch::Arch::fault_address();
         let va = round_down(addr, PAGE_SIZE);
 
-        // NOTE: allocate stack region automatically
-        if addr > CONFIG_USER_STACK_BTM && addr < CONFIG_USER_STACK_TOP {
+        // NOTE: allocate stack region automatically unless a pager takes over the policy
+        if a.pager().is_none() && addr > CONFIG_USER_STACK_BTM && addr < CONFIG_USER_STACK_TOP {
           let mut pt = a.page_table();
           match pt.lookup_page(va) {
             None => {
@@ -40,10 +40,13 @@ pub fn handle() {
             }
           }
         }
-        let pt = a.page_table();
-        info!("thread t{} asid {} page fault va {:x} pte {:X?} fall through", t.tid(), a.asid(), addr, pt.lookup_page(va));
+        if a.pager().is_none() {
+          let pt = a.page_table();
+          info!("thread t{} asid {} page fault va {:x} pte {:X?} fall through", t.tid(), a.asid(), addr, pt.lookup_page(va));
+          drop(pt);
+        }
 
-        // default to user exception handler
+        // forward to pager or user exception handler
         crate::kernel::exception::handle_user();
       }
     }
@@ -50,6 +50,11 @@ impl From<usize> for Frame {
 }
 
 impl Frame {
+  // fallible `From<PhysicalFrame>` for syscall paths
+  pub fn try_new(physical_frame: PhysicalFrame) -> Result<Self, usize> {
+    Ok(Frame::PhysicalMemory(Arc::try_new(physical_frame).map_err(|_| rpabi::syscall::error::ERROR_OOM)?))
+  }
+
   pub fn pa(&self) -> usize {
     match self {
       Frame::PhysicalMemory(frame) => { frame.pa }
@@ -1,6 +1,8 @@
 use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use crate::arch::PAGE_SHIFT;
@@ -49,6 +51,63 @@ impl Display for Entry {
 
 pub type Error = usize;
 
+/// Pages charged to an address space, also charged to the budget of the one which allocated it
+#[derive(Debug)]
+pub struct PageBudget {
+  used: AtomicUsize,
+  // 0 is unlimited, see `SYS_SET_PAGE_LIMIT`
+  limit: AtomicUsize,
+  parent: Option<Arc<PageBudget>>,
+}
+
+impl PageBudget {
+  pub fn new(parent: Option<Arc<PageBudget>>) -> Result<Arc<Self>, Error> {
+    Arc::try_new(PageBudget {
+      used: AtomicUsize::new(0),
+      limit: AtomicUsize::new(0),
+      parent,
+    })
+    .map_err(|_| rpabi::syscall::error::ERROR_OOM)
+  }
+
+  pub fn used(&self) -> usize {
+    self.used.load(Ordering::Relaxed)
+  }
+
+  pub fn set_limit(&self, limit: usize) {
+    self.limit.store(limit, Ordering::Relaxed);
+  }
+
+  // Note: checked before charging, concurrent allocations may exceed a shared budget by the few pages in flight
+  fn exhausted(&self) -> bool {
+    let mut budget = Some(self);
+    while let Some(b) = budget {
+      let limit = b.limit.load(Ordering::Relaxed);
+      if limit != 0 && b.used() >= limit {
+        return true;
+      }
+      budget = b.parent.as_deref();
+    }
+    false
+  }
+
+  fn charge(&self, pages: usize) {
+    let mut budget = Some(self);
+    while let Some(b) = budget {
+      b.used.fetch_add(pages, Ordering::Relaxed);
+      budget = b.parent.as_deref();
+    }
+  }
+
+  fn credit(&self, pages: usize) {
+    let mut budget = Some(self);
+    while let Some(b) = budget {
+      b.used.fetch_sub(pages, Ordering::Relaxed);
+      budget = b.parent.as_deref();
+    }
+  }
+}
+
 pub trait PageTableTrait {
   fn new(directory_kva: usize, table_frames: &mut Vec<PhysicalFrame>) -> Self;
   fn map(
@@ -72,21 +131,34 @@ where
   directory: PhysicalFrame,
   table_frames: Vec<PhysicalFrame>,
   user_frames: BTreeMap<usize, Frame>,
+  // charged with `user_frames` and `table_frames` together
+  budget: Arc<PageBudget>,
+}
+
+impl<T> Drop for GenericPageTable<T>
+where
+  T: PageTableTrait,
+{
+  fn drop(&mut self) {
+    self.budget.credit(self.page_usage());
+  }
 }
 
 impl<T> GenericPageTable<T>
 where
   T: PageTableTrait,
 {
-  pub fn new() -> Result<Self, usize> {
+  pub fn new(budget: Arc<PageBudget>) -> Result<Self, usize> {
     let directory = super::page_pool::page_alloc()?;
     let mut table_frames = Vec::new();
     let arch_pt = T::new(directory.kva(), &mut table_frames);
+    budget.charge(table_frames.len());
     let r = GenericPageTable {
       arch_pt,
       directory,
       table_frames,
       user_frames: BTreeMap::new(),
+      budget,
     };
     Ok(r)
   }
@@ -97,6 +169,10 @@ where
     user_frame: Frame,
     attr: EntryAttribute,
   ) -> Result<(), Error> {
+    // Note: page tables the mapping needs are charged too, one more page may take a few of them
+    if !self.user_frames.contains_key(&va) && self.budget.exhausted() {
+      return Err(rpabi::syscall::error::ERROR_OOM);
+    }
     let pa = user_frame.pa();
     if let Some(p) = self.arch_pt.lookup_page(va) {
       if p.pa() != pa {
@@ -104,9 +180,13 @@ where
         self.remove_page(va)?;
       }
     }
-    self.arch_pt.map(va, pa, attr, &mut self.table_frames)?;
-    self.user_frames.insert(va, user_frame);
-    Ok(())
+    let usage = self.page_usage();
+    let r = self.arch_pt.map(va, pa, attr, &mut self.table_frames);
+    if r.is_ok() {
+      self.user_frames.insert(va, user_frame);
+    }
+    self.budget.charge(self.page_usage() - usage);
+    r
   }
 
   pub fn lookup_user_page(&self, va: usize) -> Option<Frame> {
@@ -116,13 +196,31 @@ where
   pub fn remove_page(&mut self, va: usize) -> Result<(), Error> {
     if let Some(_) = self.arch_pt.lookup_page(va) {
       self.arch_pt.unmap(va);
-      self.user_frames.remove(&va);
+      if self.user_frames.remove(&va).is_some() {
+        self.budget.credit(1);
+      }
       Ok(())
     } else {
       Err(rpabi::syscall::error::ERROR_INVARG)
     }
   }
 
+  pub fn remove_all_pages(&mut self) {
+    for va in self.user_frames.keys() {
+      self.arch_pt.unmap(*va);
+    }
+    self.budget.credit(self.user_frames.len());
+    self.user_frames.clear();
+  }
+
+  pub fn page_usage(&self) -> usize {
+    self.user_frames.len() + self.table_frames.len()
+  }
+
+  pub fn budget(&self) -> &Arc<PageBudget> {
+    &self.budget
+  }
+
   pub fn recursive_map(&self, va: usize) {
     self.arch_pt.recursive_map(va);
   }
@@ -136,7 +234,10 @@ where
   }
 
   pub fn map(&mut self, va: usize, pa: usize, attr: EntryAttribute) -> Result<(), Error> {
-    self.arch_pt.map(va, pa, attr, &mut self.table_frames)
+    let usage = self.page_usage();
+    let r = self.arch_pt.map(va, pa, attr, &mut self.table_frames);
+    self.budget.charge(self.page_usage() - usage);
+    r
   }
 }
 
@@ -0,0 +1,117 @@
+use core::alloc::{AllocError, Allocator, Layout};
+use core::ptr::NonNull;
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::arch::PAGE_SIZE;
+use crate::mm::PhysicalFrame;
+use crate::util::round_up;
+
+// Fixed-size object cache carved out of pool pages, keeping hot kernel objects off the kernel heap
+// Slot size is fixed by the first allocation; pages are kept once taken from the pool
+pub struct SlabCache {
+  name: &'static str,
+  inner: Mutex<Inner>,
+}
+
+struct Inner {
+  slot_size: usize,
+  // intrusive free list, 0 terminated
+  free: usize,
+  frames: Vec<PhysicalFrame>,
+  in_use: usize,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SlabStats {
+  pub slot_size: usize,
+  pub in_use: usize,
+  pub pages: usize,
+}
+
+impl SlabCache {
+  pub const fn new(name: &'static str) -> Self {
+    SlabCache {
+      name,
+      inner: Mutex::new(Inner {
+        slot_size: 0,
+        free: 0,
+        frames: Vec::new(),
+        in_use: 0,
+      }),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    self.name
+  }
+
+  pub fn stats(&self) -> SlabStats {
+    let inner = self.inner.lock();
+    SlabStats {
+      slot_size: inner.slot_size,
+      in_use: inner.in_use,
+      pages: inner.frames.len(),
+    }
+  }
+
+  fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    let mut inner = self.inner.lock();
+    if inner.slot_size == 0 {
+      if layout.align() > PAGE_SIZE {
+        return Err(AllocError);
+      }
+      let slot_size = round_up(layout.size().max(core::mem::size_of::<usize>()), layout.align());
+      if slot_size > PAGE_SIZE {
+        return Err(AllocError);
+      }
+      inner.slot_size = slot_size;
+    } else if layout.size() > inner.slot_size || inner.slot_size % layout.align() != 0 {
+      return Err(AllocError);
+    }
+    if inner.free == 0 {
+      inner.frames.try_reserve(1).map_err(|_| AllocError)?;
+      let frame = crate::mm::page_pool::page_alloc().map_err(|_| AllocError)?;
+      let slot_size = inner.slot_size;
+      for slot in (frame.kva()..frame.kva() + PAGE_SIZE - slot_size + 1).step_by(slot_size).rev() {
+        unsafe { (slot as *mut usize).write(inner.free); }
+        inner.free = slot;
+      }
+      inner.frames.push(frame);
+    }
+    let slot = inner.free;
+    inner.free = unsafe { (slot as *const usize).read() };
+    inner.in_use += 1;
+    Ok(unsafe { NonNull::new_unchecked(slot as *mut u8) })
+  }
+
+  fn deallocate(&self, ptr: NonNull<u8>) {
+    let mut inner = self.inner.lock();
+    let slot = ptr.as_ptr() as usize;
+    unsafe { (slot as *mut usize).write(inner.free); }
+    inner.free = slot;
+    inner.in_use -= 1;
+  }
+}
+
+// Allocator handle to use a cache with `Arc::try_new_in` and friends
+#[derive(Copy, Clone)]
+pub struct SlabAllocator(pub &'static SlabCache);
+
+unsafe impl Allocator for SlabAllocator {
+  fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+    let ptr = self.0.allocate(layout)?;
+    Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+  }
+
+  unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+    self.0.deallocate(ptr)
+  }
+}
+
+pub static THREAD_CACHE: SlabCache = SlabCache::new("thread");
+pub static ADDRESS_SPACE_CACHE: SlabCache = SlabCache::new("address_space");
+
+// index + 1 is the `SYS_KERNEL_STAT` item of each cache, see `rpabi::kstat`
+pub static CACHES: [&SlabCache; 2] = [&THREAD_CACHE, &ADDRESS_SPACE_CACHE];
@@ -1,8 +1,19 @@
 use rpabi::syscall::error::*;
 
+use crate::kernel::address_space::AddressSpace;
 use crate::kernel::thread::Tid;
 
-use super::{Result, SyscallOutRegisters::*, VOID};
+use super::{Result, SyscallOutRegisters::*, VOID, VOID_SCHEDULE};
+
+// Note: an address space is managed by the trusted root or by the address space that allocated it
+fn check_manager(a: &AddressSpace) -> core::result::Result<(), super::Error> {
+  let current = super::current_thread()?.address_space().ok_or(ERROR_INTERNAL)?;
+  if crate::kernel::address_space::is_trusted(&current) || a.is_child_of(&current) {
+    Ok(())
+  } else {
+    Err(ERROR_DENIED)
+  }
+}
 
 #[inline(never)]
 pub fn get_asid(tid: Tid) -> Result {
@@ -12,25 +23,56 @@ pub fn get_asid(tid: Tid) -> Result {
       Some(a) => Ok((Single(a.asid() as usize), false)),
     }
   } else {
-    match crate::kernel::thread::thread_lookup(tid) {
+    match crate::kernel::thread::thread_lookup(tid)?.address_space() {
       None => Err(ERROR_INVARG),
-      Some(t) => match t.address_space() {
-        None => Err(ERROR_INVARG),
-        Some(a) => Ok((Single(a.asid() as usize), false)),
-      },
+      Some(a) => Ok((Single(a.asid() as usize), false)),
     }
   }
 }
 
 #[inline(never)]
 pub fn address_space_alloc() -> Result {
-  let a = crate::kernel::address_space::address_space_alloc()?;
+  let current = super::current_thread()?.address_space().ok_or(ERROR_INTERNAL)?;
+  let a = crate::kernel::address_space::address_space_alloc(Some(&current))?;
   Ok((Single(a.asid() as usize), false))
 }
 
 #[inline(never)]
 pub fn address_space_destroy(asid: u16) -> Result {
   let a = super::lookup_as(asid)?;
+  let suicide = super::current_thread()?.address_space().as_ref() == Some(&a);
   crate::kernel::address_space::address_space_destroy(a);
+  if suicide {
+    VOID_SCHEDULE
+  } else {
+    VOID
+  }
+}
+
+#[inline(never)]
+pub fn set_pager(asid: u16, tid: Tid) -> Result {
+  let a = super::lookup_as(asid)?;
+  check_manager(&a)?;
+  if tid == 0 {
+    a.set_pager(None);
+    return VOID;
+  }
+  let pager = crate::kernel::thread::thread_lookup(tid)?;
+  // Note: a pager faulting in its own address space would wait for itself
+  if pager.address_space().as_ref() == Some(&a) {
+    return Err(ERROR_INVARG);
+  }
+  a.set_pager(Some(tid));
   VOID
 }
+
+#[inline(never)]
+pub fn set_page_limit(asid: u16, limit: usize) -> Result {
+  let a = super::lookup_as(asid)?;
+  // Note: an address space cannot lift its own budget
+  check_manager(&a)?;
+  // Note: pages of address spaces allocated by `a` count too, lifting their own limits lifts none of `a`
+  let pt = a.page_table();
+  pt.budget().set_limit(limit);
+  Ok((Single(pt.budget().used()), false))
+}
@@ -5,11 +5,19 @@ use rpabi::event::*;
 use rpabi::syscall::error::{ERROR_HOLD_ON, ERROR_INVARG};
 use spin::Mutex;
 
-use crate::kernel::interrupt::INT_SEM;
+use crate::kernel::interrupt::{IRQ_TABLE, INTERRUPT_NUMBER_MAX};
 use crate::kernel::semaphore::SemaphoreWaitResult;
-use crate::kernel::thread::{Tid, thread_sleep};
+use crate::kernel::thread::{Thread, Tid, thread_sleep};
+use crate::kernel::traits::ContextFrameTrait;
 
-use super::{Result, VOID, VOID_SCHEDULE};
+use super::{Result, SyscallOutRegisters::Unit, VOID, VOID_SCHEDULE};
+
+// Note: a woken thread returns to user space without passing the syscall exit, its result is set before it sleeps
+fn sleep(t: &Thread) -> Result {
+  crate::kernel::cpu::cpu().context_mut().set_syscall_result(&Unit, 0);
+  thread_sleep(t, crate::kernel::thread::Status::WaitForEvent);
+  VOID_SCHEDULE
+}
 
 #[inline(never)]
 pub fn event_wait(event_type: usize, event_num: usize) -> Result {
@@ -17,28 +25,46 @@ pub fn event_wait(event_type: usize, event_num: usize) -> Result {
   if let Some(e) = Event::from(event_type, event_num) {
     match e {
       Event::Interrupt(i) => {
-        match INT_SEM.wait(t.clone(), i) {
+        if i >= INTERRUPT_NUMBER_MAX {
+          return Err(ERROR_INVARG);
+        }
+        // Note: only the thread bound by `irq_bind` may wait for the line
+        match IRQ_TABLE.wait(t.clone(), i)? {
           SemaphoreWaitResult::Acquired => {
             VOID
           },
-          SemaphoreWaitResult::Enqueued => {
-            thread_sleep(&t, crate::kernel::thread::Status::WaitForEvent);
-            VOID_SCHEDULE
-          },
+          SemaphoreWaitResult::Enqueued => sleep(&t),
         }
       }
       Event::ThreadExit(tid) => {
-        let map = PARENT_WAIT_CHILD_MAP.lock();
-        if let Some(vec) = map.get(&t.tid()) {
-          if vec.contains(&tid) {
-            VOID
-          } else {
-            Err(ERROR_HOLD_ON)
+        let mut map = PARENT_WAIT_CHILD_MAP.lock();
+        if let Some(vec) = map.get_mut(&t.tid()) {
+          if let Some(i) = vec.iter().position(|x| *x == tid) {
+            // Note: consume the record so exited children do not pile up in kernel heap
+            vec.swap_remove(i);
+            if vec.is_empty() {
+              map.remove(&t.tid());
+            }
+            return VOID;
           }
+        }
+        // Note: a dead thread not recorded as our child will never signal, report it instead of holding on
+        crate::kernel::thread::thread_lookup(tid)?;
+        Err(ERROR_HOLD_ON)
+      }
+      Event::ThreadSuspend(tid) => {
+        let target = crate::kernel::thread::thread_lookup(tid)?;
+        if target.suspended() && target.running_cpu().is_none() {
+          VOID
         } else {
           Err(ERROR_HOLD_ON)
         }
       }
+      Event::Timer(0) => VOID,
+      Event::Timer(ms) => {
+        crate::kernel::timer::sleep(t.clone(), ms);
+        sleep(&t)
+      }
     }
   } else {
     Err(ERROR_INVARG)
@@ -49,6 +75,8 @@ pub fn event_wait(event_type: usize, event_num: usize) -> Result {
 enum Event {
   Interrupt(usize),
   ThreadExit(usize),
+  ThreadSuspend(usize),
+  Timer(usize),
 }
 
 impl Event {
@@ -56,6 +84,8 @@ impl Event {
     match event_type {
       EVENT_INTERRUPT => Some(Event::Interrupt(event_num)),
       EVENT_THREAD_EXIT => Some(Event::ThreadExit(event_num)),
+      EVENT_THREAD_SUSPEND => Some(Event::ThreadSuspend(event_num)),
+      EVENT_TIMER => Some(Event::Timer(event_num)),
       _ => None,
     }
   }
@@ -66,6 +96,8 @@ static PARENT_WAIT_CHILD_MAP: Mutex<BTreeMap<Tid, Vec<Tid>>> = Mutex::new(BTreeM
 // called when a thread exits
 pub fn thread_exit_signal(child_tid: Tid, parent_tid: Tid) {
   let mut map = PARENT_WAIT_CHILD_MAP.lock();
+  // records of the exiting thread's own children will never be consumed
+  map.remove(&child_tid);
   if let Some(vec) = map.get_mut(&parent_tid) {
     vec.push(child_tid);
   } else {
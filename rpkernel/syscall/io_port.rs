@@ -0,0 +1,26 @@
+use rpabi::syscall::error::*;
+
+use crate::kernel::address_space::IO_PORT_NUM;
+use crate::kernel::cpu::cpu;
+use crate::kernel::traits::ArchTrait;
+
+use super::{Result, VOID};
+
+// Note: I/O ports are handed out by the trusted root only, and only x86_64 has them
+#[inline(never)]
+pub fn io_port_grant(asid: u16, port: usize, len: usize) -> Result {
+  let current = super::current_thread()?.address_space().ok_or(ERROR_INTERNAL)?;
+  if !crate::kernel::address_space::is_trusted(&current) {
+    return Err(ERROR_DENIED);
+  }
+  if !cfg!(target_arch = "x86_64") || len == 0 || port >= IO_PORT_NUM || len > IO_PORT_NUM - port {
+    return Err(ERROR_INVARG);
+  }
+  let a = super::lookup_as(asid)?;
+  a.io_port_grant(port..(port + len))?;
+  // other cores pick the new bitmap up on their next switch to the address space
+  if cpu().address_space().as_ref() == Some(&a) {
+    crate::arch::Arch::install_io_port_bitmap(a.io_port_bitmap().as_deref());
+  }
+  VOID
+}
@@ -1,6 +1,7 @@
+use rpabi::pager::{REPLY_KILL, REPLY_RETRY, REPLY_UPCALL};
 use rpabi::syscall::error::*;
 
-use crate::kernel::thread::{thread_sleep, Tid};
+use crate::kernel::thread::{thread_sleep, Thread, Tid};
 use crate::kernel::thread::Status as ThreadStatus;
 use crate::kernel::traits::ContextFrameTrait;
 
@@ -10,13 +11,51 @@ use super::{Result, SyscallOutRegisters::*, VOID_SCHEDULE, VOID};
 pub fn itc_receive() -> Result {
   let t = super::current_thread()?;
   thread_sleep(&t, ThreadStatus::WaitForRequest);
+  crate::kernel::exception::pager_idle(t.tid());
   VOID_SCHEDULE
 }
 
+// Reply of a pager to a thread blocked on exception, see `kernel::exception::forward_to_pager`
+fn pager_reply(current: &Thread, target: Thread, action: usize) -> Result {
+  match target.address_space().and_then(|a| a.pager()) {
+    Some(pager) if pager == current.tid() => {}
+    _ => return Err(ERROR_DENIED),
+  }
+  match action {
+    REPLY_RETRY => {
+      if target.wait_for_pager(|| {}) {
+        VOID
+      } else {
+        Err(ERROR_DENIED)
+      }
+    }
+    REPLY_UPCALL => {
+      // Note: the thread faults again and goes to the exception handler
+      if target.wait_for_pager(|| target.set_pager_bypass()) {
+        VOID
+      } else {
+        Err(ERROR_DENIED)
+      }
+    }
+    REPLY_KILL => {
+      if target.status() == ThreadStatus::WaitForPager {
+        crate::kernel::thread::thread_destroy(target);
+        VOID
+      } else {
+        Err(ERROR_DENIED)
+      }
+    }
+    _ => Err(ERROR_INVARG),
+  }
+}
+
 #[inline(never)]
 pub fn itc_send(tid: Tid, a: usize, b: usize, c: usize, d: usize) -> Result {
   let current = super::current_thread()?;
-  let target = crate::kernel::thread::thread_lookup(tid).ok_or_else(|| ERROR_INVARG)?;
+  let target = crate::kernel::thread::thread_lookup(tid)?;
+  if target.status() == ThreadStatus::WaitForPager {
+    return pager_reply(&current, target, a);
+  }
   if target.wait_for_reply(|| {
     target.map_with_context(|ctx| {
       ctx.set_syscall_result(&Pentad(current.tid() as usize, a, b, c, d), 0);
@@ -31,7 +70,7 @@ pub fn itc_send(tid: Tid, a: usize, b: usize, c: usize, d: usize) -> Result {
 #[inline(never)]
 pub fn itc_call(tid: Tid, a: usize, b: usize, c: usize, d: usize) -> Result {
   let current = super::current_thread()?;
-  let target = crate::kernel::thread::thread_lookup(tid).ok_or_else(|| ERROR_INVARG)?;
+  let target = crate::kernel::thread::thread_lookup(tid)?;
   if target.wait_for_request(|| {
     target.map_with_context(|ctx| {
       ctx.set_syscall_result(&Pentad(current.tid() as usize, a, b, c, d), 0);
@@ -47,8 +86,12 @@ pub fn itc_call(tid: Tid, a: usize, b: usize, c: usize, d: usize) -> Result {
 #[inline(never)]
 pub fn itc_reply_recv(tid: Tid, a: usize, b: usize, c: usize, d: usize) -> Result {
   let current = super::current_thread()?;
-  let target = crate::kernel::thread::thread_lookup(tid).ok_or_else(|| ERROR_INVARG)?;
-  if !target.wait_for_reply(|| {
+  let target = crate::kernel::thread::thread_lookup(tid)?;
+  if target.status() == ThreadStatus::WaitForPager {
+    if let Err(e) = pager_reply(&current, target.clone(), a) {
+      warn!("t{} pager reply to t{} failed {}", current.tid(), target.tid(), e);
+    }
+  } else if !target.wait_for_reply(|| {
     target.map_with_context(|ctx| {
       ctx.set_syscall_result(&Pentad(current.tid() as usize, a, b, c, d), 0);
     });
@@ -56,5 +99,6 @@ pub fn itc_reply_recv(tid: Tid, a: usize, b: usize, c: usize, d: usize) -> Resul
     warn!("t{} not wait for reply from t{} status {:?}", target.tid(), current.tid(), target.status());
   }
   thread_sleep(&current, ThreadStatus::WaitForRequest);
+  crate::kernel::exception::pager_idle(current.tid());
   VOID_SCHEDULE
 }
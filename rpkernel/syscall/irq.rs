@@ -0,0 +1,86 @@
+use rpabi::syscall::error::*;
+
+use crate::driver::INTERRUPT_CONTROLLER;
+use crate::kernel::interrupt::{InterruptController, IRQ_TABLE, INTERRUPT_NUMBER_MAX, INTERRUPT_PRIORITY_MAX, TriggerMode};
+use crate::kernel::thread::Tid;
+
+use super::{Result, SyscallOutRegisters::*, VOID};
+
+// Note: device interrupts are handed out and configured by the trusted root only
+fn check_trusted(irq: usize) -> core::result::Result<(), super::Error> {
+  if irq >= INTERRUPT_NUMBER_MAX {
+    return Err(ERROR_INVARG);
+  }
+  let a = super::current_thread()?.address_space().ok_or(ERROR_INTERNAL)?;
+  if !crate::kernel::address_space::is_trusted(&a) {
+    return Err(ERROR_DENIED);
+  }
+  Ok(())
+}
+
+#[inline(never)]
+pub fn irq_bind(irq: usize, tid: Tid, flags: usize) -> Result {
+  check_trusted(irq)?;
+  if tid == 0 {
+    IRQ_TABLE.unbind(irq)?;
+  } else {
+    crate::kernel::thread::thread_lookup(tid)?;
+    IRQ_TABLE.bind(irq, tid, flags & rpabi::irq::BIND_SHARED != 0)?;
+  }
+  VOID
+}
+
+#[inline(never)]
+pub fn irq_config(irq: usize, item: usize, value: usize) -> Result {
+  check_trusted(irq)?;
+  match item {
+    rpabi::irq::CONFIG_PRIORITY if value <= INTERRUPT_PRIORITY_MAX => {
+      INTERRUPT_CONTROLLER.set_priority(irq, value)
+    }
+    rpabi::irq::CONFIG_TRIGGER if value == rpabi::irq::TRIGGER_EDGE => {
+      INTERRUPT_CONTROLLER.set_trigger(irq, TriggerMode::Edge)
+    }
+    rpabi::irq::CONFIG_TRIGGER if value == rpabi::irq::TRIGGER_LEVEL => {
+      INTERRUPT_CONTROLLER.set_trigger(irq, TriggerMode::Level)
+    }
+    rpabi::irq::CONFIG_AFFINITY if value < crate::cpu_number() => {
+      INTERRUPT_CONTROLLER.set_affinity(irq, value)
+    }
+    _ => return Err(ERROR_INVARG),
+  }
+  VOID
+}
+
+#[inline(never)]
+pub fn msi_alloc(device_id: usize) -> Result {
+  check_trusted(0)?;
+  let (irq, doorbell, data) = INTERRUPT_CONTROLLER.msi_alloc(device_id)?;
+  Ok((Triple(irq, doorbell, data), false))
+}
+
+#[inline(never)]
+pub fn msi_free(irq: usize) -> Result {
+  check_trusted(irq)?;
+  // Note: the line may not be bound yet
+  let _ = IRQ_TABLE.unbind(irq);
+  INTERRUPT_CONTROLLER.msi_free(irq)?;
+  VOID
+}
+
+#[inline(never)]
+pub fn irq_mask(irq: usize) -> Result {
+  IRQ_TABLE.mask(super::current_thread()?.tid(), irq)?;
+  VOID
+}
+
+#[inline(never)]
+pub fn irq_unmask(irq: usize) -> Result {
+  IRQ_TABLE.unmask(super::current_thread()?.tid(), irq)?;
+  VOID
+}
+
+#[inline(never)]
+pub fn irq_ack(irq: usize) -> Result {
+  IRQ_TABLE.ack(super::current_thread()?.tid(), irq)?;
+  VOID
+}
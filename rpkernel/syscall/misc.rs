@@ -1,8 +1,13 @@
-use rpabi::syscall::error::ERROR_INVARG;
+use core::mem::{size_of, MaybeUninit};
 
+use rpabi::syscall::error::{ERROR_INTERNAL, ERROR_INVARG};
+
+use crate::arch::{ContextFrame, PAGE_SIZE};
 use crate::kernel::print::DebugUart;
+use crate::kernel::traits::{Address, ContextFrameTrait};
+use crate::util::round_down;
 
-use super::{Result, SyscallOutRegisters::*, VOID};
+use super::{Result, SyscallOutRegisters::*, VOID, VOID_SCHEDULE};
 
 #[inline(never)]
 pub fn null() -> Result {
@@ -12,7 +17,7 @@ pub fn null() -> Result {
 #[inline(never)]
 pub fn putc(c: char) -> Result {
   let mut c = c as u8;
-  let uart = crate::board::DEBUG_UART.get().unwrap();
+  let uart = crate::board::DEBUG_UART.get().ok_or(ERROR_INTERNAL)?;
   if c == 127 {
     uart.putc(8);
     uart.putc(b' ');
@@ -24,7 +29,7 @@ pub fn putc(c: char) -> Result {
 
 #[inline(never)]
 pub fn getc() -> Result {
-  let uart = crate::board::DEBUG_UART.get().unwrap();
+  let uart = crate::board::DEBUG_UART.get().ok_or(ERROR_INTERNAL)?;
   match uart.getc() {
     None => Err(rpabi::syscall::error::ERROR_HOLD_ON),
     Some(c) => Ok((Single(c as usize), false))
@@ -42,3 +47,69 @@ pub fn set_exception_handler(handler: usize) -> Result {
     }
   }
 }
+
+#[inline(never)]
+pub fn kernel_stat(item: usize) -> Result {
+  use rpabi::kstat::*;
+  match item {
+    ITEM_HEAP => {
+      let s = crate::mm::heap::stats();
+      Ok((Pentad(s.total, s.user, s.actual, s.peak, s.failures), false))
+    }
+    ITEM_CACHE_THREAD..=ITEM_CACHE_ADDRESS_SPACE => {
+      let s = crate::mm::slab::CACHES[item - 1].stats();
+      Ok((Pentad(s.slot_size, s.in_use, s.pages, 0, 0), false))
+    }
+    ITEM_CLOCK => {
+      let ms = crate::kernel::timer::current_ms();
+      let us = crate::kernel::timer::current_us();
+      Ok((Pentad(ms, us, 0, 0, 0), false))
+    }
+    _ => Err(rpabi::syscall::error::ERROR_OOR),
+  }
+}
+
+// Resume the context pushed by `kernel::exception::handle`, possibly modified by the handler
+// Only registers writable through `ContextFrameTrait::set_register` are restored
+#[inline(never)]
+pub fn exception_return(frame: usize) -> Result {
+  let t = super::current_thread()?;
+  let a = t.address_space().ok_or(ERROR_INVARG)?;
+  if frame % size_of::<usize>() != 0 || frame.checked_add(size_of::<ContextFrame>()).is_none() {
+    return Err(ERROR_INVARG);
+  }
+  let end = frame + size_of::<ContextFrame>();
+  let mut saved = MaybeUninit::<ContextFrame>::uninit();
+  let dst = saved.as_mut_ptr() as *mut u8;
+  // Note: copied through the physical frames under the page table lock, so the pages cannot be unmapped meanwhile
+  let pt = a.page_table();
+  for page in (round_down(frame, PAGE_SIZE)..end).step_by(PAGE_SIZE) {
+    let entry = match pt.lookup_page(page) {
+      Some(entry) if pt.lookup_user_page(page).is_some() => entry,
+      _ => return Err(ERROR_INVARG),
+    };
+    if !entry.attribute().u_readable() || entry.attribute().device() {
+      return Err(ERROR_INVARG);
+    }
+    let from = frame.max(page);
+    let to = end.min(page + PAGE_SIZE);
+    unsafe {
+      core::ptr::copy_nonoverlapping(
+        (entry.pa() + (from - page)).pa2kva() as *const u8,
+        dst.add(from - frame),
+        to - from,
+      );
+    }
+  }
+  drop(pt);
+  let saved = unsafe { saved.assume_init() };
+  let ctx = crate::kernel::cpu::cpu().context_mut();
+  for i in 0..rpabi::debug::REGISTER_NUM {
+    if let Some(value) = saved.register(i) {
+      let _ = ctx.set_register(i, value);
+    }
+  }
+  // Note: returning normally would clobber the restored registers with syscall results
+  // so the thread yields instead (see `kernel::syscall::syscall`)
+  VOID_SCHEDULE
+}
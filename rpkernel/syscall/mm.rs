@@ -6,15 +6,33 @@ use crate::util::round_down;
 
 use super::{Result, VOID};
 
+// Note: pages above the user limit belong to kernel (e.g., recursive page table)
+fn check_user_va(va: usize) -> core::result::Result<(), usize> {
+  if va >= rpabi::CONFIG_USER_LIMIT {
+    Err(ERROR_INVARG)
+  } else {
+    Ok(())
+  }
+}
+
+// Note: keep out-of-memory (including page limit) visible to user
+fn quota_error(e: usize) -> usize {
+  match e {
+    ERROR_OOM => ERROR_OOM,
+    _ => ERROR_INTERNAL,
+  }
+}
+
 #[inline(never)]
 pub fn mem_alloc(asid: u16, va: usize, attr: usize) -> Result {
   let va = round_down(va, PAGE_SIZE);
+  check_user_va(va)?;
   let a = super::lookup_as(asid)?;
   let frame = crate::mm::page_pool::page_alloc().map_err(|_| ERROR_OOM)?;
   frame.zero();
   let attr = EntryAttribute::from(attr).filter();
-  let uf = crate::mm::Frame::from(frame);
-  a.page_table().insert_page(va, uf, attr).map_err(|_| ERROR_INTERNAL)?;
+  let uf = crate::mm::Frame::try_new(frame)?;
+  a.page_table().insert_page(va, uf, attr).map_err(quota_error)?;
   VOID
 }
 
@@ -22,21 +40,21 @@ pub fn mem_alloc(asid: u16, va: usize, attr: usize) -> Result {
 pub fn mem_map(src_asid: u16, src_va: usize, dst_asid: u16, dst_va: usize, attr: usize) -> Result {
   let src_va = round_down(src_va, PAGE_SIZE);
   let dst_va = round_down(dst_va, PAGE_SIZE);
+  check_user_va(src_va)?;
+  check_user_va(dst_va)?;
   let src_as = super::lookup_as(src_asid)?;
   let dst_as = super::lookup_as(dst_asid)?;
   let attr = EntryAttribute::from(attr).filter();
-  let pt = src_as.page_table();
-  if let Some(uf) = pt.lookup_user_page(src_va) {
-    dst_as.page_table().insert_page(dst_va, uf, attr).map_err(|_| ERROR_INTERNAL)?;
-    VOID
-  } else {
-    Err(ERROR_MEM_NOT_MAP)
-  }
+  // Note: never hold both page tables, src and dst may be the same address space
+  let uf = src_as.page_table().lookup_user_page(src_va).ok_or(ERROR_MEM_NOT_MAP)?;
+  dst_as.page_table().insert_page(dst_va, uf, attr).map_err(quota_error)?;
+  VOID
 }
 
 #[inline(never)]
 pub fn mem_unmap(asid: u16, va: usize) -> Result {
   let va = round_down(va, PAGE_SIZE);
+  check_user_va(va)?;
   let a = super::lookup_as(asid)?;
   a.page_table().remove_page(va).map_err(|_| ERROR_INTERNAL)?;
   VOID
@@ -15,6 +15,8 @@ pub mod address_space;
 pub mod event;
 pub mod ipc;
 pub mod server;
+pub mod irq;
+pub mod io_port;
 
 pub type Error = usize;
 
@@ -54,12 +56,11 @@ pub const VOID: Result = Ok((Unit, false));
 pub const VOID_SCHEDULE: Result = Ok((Unit, true));
 
 fn lookup_as(asid: u16) -> core::result::Result<AddressSpace, Error> {
-  let a = if asid == 0 {
-    current_thread()?.address_space()
+  if asid == 0 {
+    current_thread()?.address_space().ok_or(ERROR_INVARG)
   } else {
     crate::kernel::address_space::address_space_lookup(asid)
-  };
-  a.ok_or(ERROR_INVARG)
+  }
 }
 
 fn current_thread() -> core::result::Result<Thread, Error> {
@@ -9,6 +9,9 @@ use super::{Result, SyscallOutRegisters::*, VOID};
 
 #[inline(never)]
 pub fn server_register(server_id: usize) -> Result {
+  if server_id >= rpabi::server::SERVER_MAX {
+    return Err(ERROR_INVARG);
+  }
   let t = super::current_thread()?;
   set(server_id, t.tid());
   VOID
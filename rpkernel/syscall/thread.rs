@@ -1,6 +1,7 @@
-use rpabi::syscall::error::{ERROR_DENIED, ERROR_INVARG};
+use rpabi::syscall::error::{ERROR_DENIED, ERROR_HOLD_ON, ERROR_INVARG};
 
-use crate::kernel::thread::{thread_sleep, thread_wake, Tid};
+use crate::kernel::thread::{thread_sleep, thread_wake, Thread, Tid};
+use crate::kernel::traits::ContextFrameTrait;
 
 use super::{Result, SyscallOutRegisters::*, VOID_SCHEDULE, VOID};
 
@@ -21,16 +22,12 @@ pub fn thread_destroy(tid: Tid) -> Result {
     crate::kernel::thread::thread_destroy(current_thread);
     VOID_SCHEDULE
   } else {
-    match crate::kernel::thread::thread_lookup(tid) {
-      None => Err(ERROR_INVARG),
-      Some(t) => {
-        if t.is_child_of(current_thread.tid()) {
-          crate::kernel::thread::thread_destroy(t);
-          VOID
-        } else {
-          Err(ERROR_DENIED)
-        }
-      }
+    let t = crate::kernel::thread::thread_lookup(tid)?;
+    if t.is_child_of(current_thread.tid()) {
+      crate::kernel::thread::thread_destroy(t);
+      VOID
+    } else {
+      Err(ERROR_DENIED)
     }
   }
 }
@@ -39,7 +36,7 @@ pub fn thread_destroy(tid: Tid) -> Result {
 pub fn thread_alloc(asid: u16, entry: usize, sp: usize, arg: usize) -> Result {
   let t = super::current_thread()?;
   let a = super::lookup_as(asid)?;
-  let child_thread = crate::kernel::thread::new_user(entry, sp, arg, a.clone(), Some(t.tid()));
+  let child_thread = crate::kernel::thread::new_user(entry, sp, arg, a.clone(), Some(t.tid()))?;
   Ok((Single(child_thread.tid() as usize), false))
 }
 
@@ -54,19 +51,70 @@ pub fn thread_set_status(tid: usize, status: usize) -> Result {
   if tid == 0 {
     return Err(ERROR_INVARG)
   }
-  match crate::kernel::thread::thread_lookup(tid) {
-    None => Err(ERROR_INVARG),
-    Some(t) => {
-      if runnable {
-        thread_wake(&t);
-      } else {
-        let current = super::current_thread()?;
-        if current.tid() == t.tid() {
-          return Err(ERROR_INVARG);
-        }
-        thread_sleep(&t, crate::kernel::thread::Status::Sleep);
-      }
-      VOID
+  let t = crate::kernel::thread::thread_lookup(tid)?;
+  if runnable {
+    thread_wake(&t);
+  } else {
+    let current = super::current_thread()?;
+    if current.tid() == t.tid() {
+      return Err(ERROR_INVARG);
     }
+    thread_sleep(&t, crate::kernel::thread::Status::Sleep);
+  }
+  VOID
+}
+
+// A thread of the caller's own, the same rule as `thread_destroy`
+fn child(tid: Tid) -> core::result::Result<Thread, super::Error> {
+  let t = crate::kernel::thread::thread_lookup(tid)?;
+  let current = super::current_thread()?;
+  if current.tid() == t.tid() {
+    return Err(ERROR_INVARG);
   }
+  if !t.is_child_of(current.tid()) {
+    return Err(ERROR_DENIED);
+  }
+  Ok(t)
+}
+
+// A child suspended and parked off every cpu, whose context frame can be accessed
+fn suspended_child(tid: Tid) -> core::result::Result<Thread, super::Error> {
+  let t = child(tid)?;
+  if !t.suspended() {
+    return Err(ERROR_DENIED);
+  }
+  // Note: the cpu it ran on has not taken the IPI yet, see `EVENT_THREAD_SUSPEND`
+  if t.running_cpu().is_some() {
+    return Err(ERROR_HOLD_ON);
+  }
+  Ok(t)
+}
+
+#[inline(never)]
+pub fn thread_suspend(tid: Tid) -> Result {
+  let t = child(tid)?;
+  crate::kernel::thread::thread_suspend(&t);
+  VOID
+}
+
+#[inline(never)]
+pub fn thread_resume(tid: Tid, single_step: usize) -> Result {
+  let t = suspended_child(tid)?;
+  t.map_with_context(|ctx| ctx.set_single_step(single_step != 0)).ok_or(ERROR_INVARG)?;
+  crate::kernel::thread::thread_resume(&t);
+  VOID
+}
+
+#[inline(never)]
+pub fn thread_register_read(tid: Tid, index: usize) -> Result {
+  let t = suspended_child(tid)?;
+  let value = t.map_with_context(|ctx| ctx.register(index)).ok_or(ERROR_INVARG)?;
+  Ok((Single(value), false))
+}
+
+#[inline(never)]
+pub fn thread_register_write(tid: Tid, index: usize, value: usize) -> Result {
+  let t = suspended_child(tid)?;
+  t.map_with_context(|ctx| ctx.set_register(index, value)).ok_or(ERROR_INVARG)?;
+  VOID
 }
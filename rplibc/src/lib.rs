@@ -102,3 +102,20 @@ extern "C" fn close(fd: i32) -> i32 {
   drop(f);
   0
 }
+
+const EFAULT: isize = 14;
+
+// Note: `flags` (GRND_NONBLOCK, GRND_RANDOM) make no difference, the generator never blocks once seeded
+#[no_mangle]
+extern "C" fn getrandom(buf: *mut u8, len: usize, _flags: u32) -> isize {
+  if len == 0 {
+    return 0;
+  }
+  if buf.is_null() {
+    return -EFAULT;
+  }
+  match rpstdlib::random::fill(unsafe { core::slice::from_raw_parts_mut(buf, len) }) {
+    Ok(()) => len as isize,
+    Err(_e) => -1,
+  }
+}
@@ -6,6 +6,7 @@ int open(const char* path, int flags);
 size_t read(int fd, void* buf, size_t cnt);
 size_t write(int fd, void* buf, size_t cnt); 
 int close(int fd);
+long getrandom(void* buf, size_t buflen, unsigned int flags);
 
 #define O_RDONLY     0x00010000
 #define O_WRONLY     0x00020000
@@ -9,6 +9,7 @@ pub mod mm {
     pub const OK: usize = 0;
     pub const ERR: usize = 1;
     pub const UNKNOWN_ACTION: usize = 2;
+    pub const OOM: usize = 3;
   }
 }
 
@@ -17,6 +18,7 @@ pub mod pm {
     pub const SPAWN: usize = 1;
     pub const WAIT: usize = 2;
     pub const PS: usize = 3;
+    pub const KILL: usize = 4;
   }
 
   pub mod result {
@@ -27,15 +29,201 @@ pub mod pm {
   }
 }
 
+/// Block devices, each served by a thread of its own
+///
+/// `SERVER_BLK` is the registry which resolves a device name (e.g. `vda`, `ramdisk0`) with `OPEN`, other requests go
+/// to the thread it returns. Requests are (sector, count, buffer, action) in 512-byte sectors and replies are (result,
+/// value, value, value). The buffer is an address of the client. Clients in another address space than the server
+/// `SHARE` the pages holding their buffers first, the device then transfers into them without copying.
 pub mod blk {
   pub mod action {
     pub const READ: usize = 0;
     pub const WRITE: usize = 1;
+    // -> capacity in bytes, in place of the result
     pub const SIZE: usize = 2;
+    // make completed writes durable
+    pub const FLUSH: usize = 3;
+    // sector, count: the range no longer holds data, fails with `INVARG` past the limits of the device
+    pub const DISCARD: usize = 4;
+    // -> capacity in sectors, block size in bytes, `info::*` flags
+    pub const INFO: usize = 5;
+    // registry only, name, length -> thread serving the device
+    pub const OPEN: usize = 6;
+    // page aligned address, pages: keep them mapped in the server for transfers, a client holds a few at most
+    pub const SHARE: usize = 7;
+    // address given to `SHARE`, fails with `BUSY` while requests on the pages are in flight
+    pub const UNSHARE: usize = 8;
   }
 
   pub mod result {
     pub const OK: usize = 0;
     pub const ERR: usize = 1;
+    pub const UNSUPPORTED: usize = 2;
+    pub const READ_ONLY: usize = 3;
+    pub const INVARG: usize = 4;
+    pub const NOT_FOUND: usize = 5;
+    // the device is still being initialised
+    pub const HOLD_ON: usize = 6;
+    // requests on the shared pages are in flight
+    pub const BUSY: usize = 7;
+  }
+
+  pub mod info {
+    pub const READ_ONLY: usize = 1;
+    pub const FLUSH: usize = 2;
+    pub const DISCARD: usize = 4;
+  }
+}
+
+/// Write-back cache of block devices, served by `SERVER_BLK_CACHE`
+///
+/// Requests are (device, block, buffer, action), `device` is the thread serving the device as returned by `OPEN` of
+/// `SERVER_BLK`. Blocks and buffers are `BLOCK_SIZE` bytes, buffers are addresses of the client. Writes reach the
+/// device on `FLUSH`, on eviction or by the periodic write-back of the cache.
+pub mod blk_cache {
+  pub const BLOCK_SIZE: usize = 4096;
+
+  pub mod action {
+    pub const READ: usize = 0;
+    pub const WRITE: usize = 1;
+    // write back dirty blocks of the device, then make them durable
+    pub const FLUSH: usize = 3;
+  }
+
+  pub mod result {
+    pub const OK: usize = 0;
+    pub const ERR: usize = 1;
+    pub const UNKNOWN_ACTION: usize = 2;
+    pub const INVARG: usize = 4;
+  }
+}
+
+/// TCP/UDP over IPv4, served by `SERVER_NET`
+///
+/// Requests are (action, b, c, d) and replies are (result, value, value, 0). Buffers are passed as (address, length)
+/// in the address space of the client. Calls that cannot complete yet reply `HOLD_ON` and are to be retried.
+pub mod net {
+  pub mod action {
+    // b: `kind::*` -> handle
+    pub const SOCKET: usize = 1;
+    // b: handle, c: endpoint. UDP sockets only remember the remote endpoint.
+    pub const CONNECT: usize = 2;
+    // b: handle, c: local port
+    pub const BIND: usize = 3;
+    // b: handle, c: local port
+    pub const LISTEN: usize = 4;
+    // b: handle -> handle of the new connection
+    pub const ACCEPT: usize = 5;
+    // b: handle, c: buffer, d: length -> bytes sent
+    pub const SEND: usize = 6;
+    // b: handle, c: buffer, d: length -> bytes received (0 for end of stream), endpoint of the sender
+    pub const RECV: usize = 7;
+    // b: handle | endpoint << 16, c: buffer, d: length -> bytes sent
+    pub const SEND_TO: usize = 8;
+    // b: handle
+    pub const CLOSE: usize = 9;
+  }
+
+  pub mod kind {
+    pub const TCP: usize = 0;
+    pub const UDP: usize = 1;
+  }
+
+  pub mod result {
+    pub const OK: usize = 0;
+    pub const ERR: usize = 1;
+    pub const UNKNOWN_ACTION: usize = 2;
+    pub const HOLD_ON: usize = 3;
+    pub const INVARG: usize = 4;
+  }
+
+  /// Pack an IPv4 address and a port into one word
+  pub fn endpoint(addr: [u8; 4], port: u16) -> usize {
+    (u32::from_be_bytes(addr) as usize) << 16 | port as usize
+  }
+
+  pub fn endpoint_split(endpoint: usize) -> ([u8; 4], u16) {
+    (((endpoint >> 16) as u32).to_be_bytes(), endpoint as u16)
+  }
+}
+
+/// Local stream and datagram sockets named by absolute paths, served by `SERVER_UNIX`
+///
+/// Messages follow `net`. Paths are passed as (address, length). A bound path is created as a node of the file
+/// system served by `SERVER_REDOX_FS` and unlinked on close: binding an existing path fails with `ADDR_IN_USE`,
+/// a path in a missing directory (or without a file system) with `NOT_FOUND`.
+pub mod unix {
+  pub mod action {
+    // b: `kind::*` -> handle
+    pub const SOCKET: usize = 1;
+    // b: handle, c: path, d: length
+    pub const BIND: usize = 2;
+    // b: handle
+    pub const LISTEN: usize = 3;
+    // b: handle -> handle of the new connection
+    pub const ACCEPT: usize = 4;
+    // b: handle, c: path, d: length. Datagram sockets only remember the peer.
+    pub const CONNECT: usize = 5;
+    // b: handle, c: buffer, d: length -> bytes sent
+    pub const SEND: usize = 6;
+    // b: handle, c: buffer, d: length -> bytes received (0 for end of stream)
+    pub const RECV: usize = 7;
+    // b: handle
+    pub const CLOSE: usize = 8;
+  }
+
+  pub mod kind {
+    pub const STREAM: usize = 0;
+    pub const DATAGRAM: usize = 1;
+  }
+
+  pub mod result {
+    pub const OK: usize = 0;
+    pub const ERR: usize = 1;
+    pub const UNKNOWN_ACTION: usize = 2;
+    pub const HOLD_ON: usize = 3;
+    pub const INVARG: usize = 4;
+    pub const ADDR_IN_USE: usize = 5;
+    pub const NOT_FOUND: usize = 6;
+  }
+}
+
+/// Ports of a virtio console, served by `SERVER_CONSOLE`
+///
+/// `GETC` matches the request of `SERVER_TERMINAL` and reads the console port. Buffers are passed as
+/// (address, length) in the address space of the client. Calls that cannot complete yet reply `HOLD_ON`.
+pub mod console {
+  pub mod action {
+    // -> character of the console port, 0 if none
+    pub const GETC: usize = 0;
+    // c: name, d: length -> port id
+    pub const OPEN: usize = 1;
+    // b: port id, c: buffer, d: length -> bytes received
+    pub const READ: usize = 2;
+    // b: port id, c: buffer, d: length -> bytes sent
+    pub const WRITE: usize = 3;
+  }
+
+  pub mod result {
+    pub const OK: usize = 0;
+    pub const ERR: usize = 1;
+    pub const UNKNOWN_ACTION: usize = 2;
+    pub const HOLD_ON: usize = 3;
+    pub const INVARG: usize = 4;
+    pub const NOT_FOUND: usize = 5;
+  }
+}
+
+/// Random numbers from a CSPRNG, served by `SERVER_RNG`
+pub mod rng {
+  pub mod action {
+    // c: buffer, d: length -> bytes filled, which may be less than requested
+    pub const FILL: usize = 1;
+  }
+
+  pub mod result {
+    pub const OK: usize = 0;
+    pub const ERR: usize = 1;
+    pub const UNKNOWN_ACTION: usize = 2;
   }
 }
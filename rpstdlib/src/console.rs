@@ -0,0 +1,53 @@
+use rpabi::server::SERVER_CONSOLE;
+use rpservapi::console::{action, result};
+use rpsyscall::message::Message;
+
+// Call the console server, retrying while it replies `HOLD_ON`
+fn call(a: usize, b: usize, c: usize, d: usize) -> Result<Message, &'static str> {
+  loop {
+    let msg = Message::new(a, b, c, d).call(SERVER_CONSOLE).map_err(|_| "server call failed")?;
+    match msg.a {
+      result::OK => return Ok(msg),
+      result::HOLD_ON => rpsyscall::thread_yield(),
+      result::INVARG => return Err("no such port"),
+      result::NOT_FOUND => return Err("no port of this name"),
+      result::UNKNOWN_ACTION => return Err("unknown action"),
+      _ => return Err("console error"),
+    }
+  }
+}
+
+/// A port of the virtio console, e.g., a `virtserialport` of QEMU
+pub struct Port(usize);
+
+impl Port {
+  /// Port by the name given by the host
+  pub fn open(name: &str) -> Result<Port, &'static str> {
+    call(action::OPEN, 0, name.as_ptr() as usize, name.len()).map(|msg| Port(msg.b))
+  }
+
+  pub fn from_id(id: usize) -> Port {
+    Port(id)
+  }
+
+  pub fn id(&self) -> usize {
+    self.0
+  }
+
+  /// Read some bytes, waiting until any arrives
+  pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+    call(action::READ, self.0, buf.as_mut_ptr() as usize, buf.len()).map(|msg| msg.b)
+  }
+
+  pub fn write(&mut self, buf: &[u8]) -> Result<usize, &'static str> {
+    call(action::WRITE, self.0, buf.as_ptr() as usize, buf.len()).map(|msg| msg.b)
+  }
+
+  pub fn write_all(&mut self, mut buf: &[u8]) -> Result<(), &'static str> {
+    while !buf.is_empty() {
+      let n = self.write(buf)?;
+      buf = &buf[n..];
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,125 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ContextFrame {
+  pub gpr: [u64; 31],
+  pub spsr: u64,
+  pub elr: u64,
+  pub sp: u64,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl ContextFrame {
+  pub fn pc(&self) -> usize {
+    self.elr as usize
+  }
+
+  pub fn set_pc(&mut self, pc: usize) {
+    self.elr = pc as u64;
+  }
+
+  pub fn stack_pointer(&self) -> usize {
+    self.sp as usize
+  }
+}
+
+#[cfg(target_arch = "riscv64")]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ContextFrame {
+  pub gpr: [u64; 32],
+  pub sstatus: u64,
+  pub sepc: u64,
+}
+
+#[cfg(target_arch = "riscv64")]
+impl ContextFrame {
+  pub fn pc(&self) -> usize {
+    self.sepc as usize
+  }
+
+  pub fn set_pc(&mut self, pc: usize) {
+    self.sepc = pc as u64;
+  }
+
+  pub fn stack_pointer(&self) -> usize {
+    self.gpr[2] as usize
+  }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ContextFrame {
+  pub rax: u64,
+  pub rbx: u64,
+  pub rcx: u64,
+  pub rdx: u64,
+  pub rsi: u64,
+  pub rdi: u64,
+  pub rbp: u64,
+  pub r8: u64,
+  pub r9: u64,
+  pub r10: u64,
+  pub r11: u64,
+  pub r12: u64,
+  pub r13: u64,
+  pub r14: u64,
+  pub r15: u64,
+  pub rip: u64,
+  pub cs: u64,
+  pub rflags: u64,
+  pub rsp: u64,
+  pub ss: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl ContextFrame {
+  pub fn pc(&self) -> usize {
+    self.rip as usize
+  }
+
+  pub fn set_pc(&mut self, pc: usize) {
+    self.rip = pc as u64;
+  }
+
+  pub fn stack_pointer(&self) -> usize {
+    self.rsp as usize
+  }
+}
+
+/// Frame pushed by kernel upon a user-space exception
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ExceptionFrame {
+  pub context: ContextFrame,
+  /// faulting address (meaningful for page faults)
+  pub fault_address: usize,
+}
+
+/// Return `true` to resume `frame.context`, `false` to terminate the faulting thread
+pub type Handler = fn(&mut ExceptionFrame) -> bool;
+
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn entry(frame: *mut ExceptionFrame) -> ! {
+  let frame_ref = unsafe { &mut *frame };
+  let handler = HANDLER.load(Ordering::Acquire);
+  if handler != 0 {
+    let handler: Handler = unsafe { core::mem::transmute(handler) };
+    if handler(frame_ref) {
+      let _ = rpsyscall::exception_return(frame as usize);
+    }
+  }
+  let asid = rpsyscall::get_asid(0).unwrap();
+  println!("[USER][exception] asid{} pc {:x} fault address {:x}", asid, frame_ref.context.pc(), frame_ref.fault_address);
+  crate::exit()
+}
+
+/// Catch exceptions of all threads in current address space
+pub fn set_handler(handler: Handler) -> Result<(), &'static str> {
+  HANDLER.store(handler as usize, Ordering::Release);
+  rpsyscall::set_exception_handler(entry as usize).map_err(|_| "set_exception_handler failed")
+}
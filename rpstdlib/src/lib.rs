@@ -18,12 +18,16 @@ macro_rules! println {
     })
 }
 
+pub mod exception;
 pub mod heap;
 pub mod mm;
 pub mod pm;
 pub mod stdio;
 pub mod rtc;
 pub mod fs;
+pub mod console;
+pub mod net;
+pub mod random;
 
 pub fn sched_yield() {
   rpsyscall::thread_yield();
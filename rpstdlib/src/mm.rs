@@ -6,6 +6,19 @@ pub fn page_alloc(va: usize) -> Result<(), &'static str> {
   ).call(rpabi::server::SERVER_MM).map_err(|_| "server call failed")?;
   match result.a {
     rpservapi::mm::result::OK => Ok(()),
+    rpservapi::mm::result::OOM => Err("out of memory"),
     _ => Err("page_alloc failed"),
   }
 }
+
+/// Print kernel heap usage and slab cache counts
+pub fn kstat() -> Result<(), &'static str> {
+  use rpabi::kstat::*;
+  let (total, user, actual, peak, failures) = rpsyscall::kernel_stat(ITEM_HEAP).map_err(|_| "kernel_stat failed")?;
+  println!("heap total {} requested {} actual {} peak {} failures {}", total, user, actual, peak, failures);
+  for (item, name) in [(ITEM_CACHE_THREAD, "thread"), (ITEM_CACHE_ADDRESS_SPACE, "address_space")] {
+    let (slot_size, in_use, pages, _, _) = rpsyscall::kernel_stat(item).map_err(|_| "kernel_stat failed")?;
+    println!("cache {:16} slot {:5} in use {:6} pages {:4}", name, slot_size, in_use, pages);
+  }
+  Ok(())
+}
@@ -0,0 +1,264 @@
+use core::fmt;
+
+use rpabi::server::{SERVER_NET, SERVER_UNIX};
+use rpservapi::net::{action, endpoint, endpoint_split, kind, result};
+use rpservapi::unix;
+use rpsyscall::message::Message;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SocketAddrV4 {
+  pub ip: [u8; 4],
+  pub port: u16,
+}
+
+impl SocketAddrV4 {
+  pub fn new(ip: [u8; 4], port: u16) -> Self {
+    SocketAddrV4 { ip, port }
+  }
+
+  /// Parse `a.b.c.d:port`
+  pub fn parse(s: &str) -> Result<Self, &'static str> {
+    let (ip, port) = s.split_once(':').ok_or("missing port")?;
+    let port = port.parse().map_err(|_| "invalid port")?;
+    let mut octets = [0u8; 4];
+    let mut parts = ip.split('.');
+    for octet in octets.iter_mut() {
+      *octet = parts.next().and_then(|p| p.parse().ok()).ok_or("invalid address")?;
+    }
+    if parts.next().is_some() {
+      return Err("invalid address");
+    }
+    Ok(SocketAddrV4 { ip: octets, port })
+  }
+
+  fn endpoint(&self) -> usize {
+    endpoint(self.ip, self.port)
+  }
+
+  fn from_endpoint(e: usize) -> Self {
+    let (ip, port) = endpoint_split(e);
+    SocketAddrV4 { ip, port }
+  }
+}
+
+impl fmt::Display for SocketAddrV4 {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let [a, b, c, d] = self.ip;
+    write!(f, "{}.{}.{}.{}:{}", a, b, c, d, self.port)
+  }
+}
+
+// Call the network server, retrying while it replies `HOLD_ON`
+fn call(a: usize, b: usize, c: usize, d: usize) -> Result<Message, &'static str> {
+  loop {
+    let msg = Message::new(a, b, c, d).call(SERVER_NET).map_err(|_| "server call failed")?;
+    match msg.a {
+      result::OK => return Ok(msg),
+      result::HOLD_ON => rpsyscall::thread_yield(),
+      result::INVARG => return Err("invalid argument"),
+      result::UNKNOWN_ACTION => return Err("unknown action"),
+      _ => return Err("network error"),
+    }
+  }
+}
+
+struct Socket(usize);
+
+impl Socket {
+  fn new(kind: usize) -> Result<Self, &'static str> {
+    call(action::SOCKET, kind, 0, 0).map(|msg| Socket(msg.b))
+  }
+
+  fn send(&self, buf: &[u8]) -> Result<usize, &'static str> {
+    call(action::SEND, self.0, buf.as_ptr() as usize, buf.len()).map(|msg| msg.b)
+  }
+
+  fn recv(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4), &'static str> {
+    call(action::RECV, self.0, buf.as_mut_ptr() as usize, buf.len())
+      .map(|msg| (msg.b, SocketAddrV4::from_endpoint(msg.c)))
+  }
+}
+
+impl Drop for Socket {
+  fn drop(&mut self) {
+    let _ = Message::new(action::CLOSE, self.0, 0, 0).call(SERVER_NET);
+  }
+}
+
+pub struct TcpStream(Socket);
+
+impl TcpStream {
+  pub fn connect(addr: SocketAddrV4) -> Result<TcpStream, &'static str> {
+    let socket = Socket::new(kind::TCP)?;
+    call(action::CONNECT, socket.0, addr.endpoint(), 0)?;
+    Ok(TcpStream(socket))
+  }
+
+  /// Read some bytes, 0 means the peer has closed the connection
+  pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+    self.0.recv(buf).map(|(n, _)| n)
+  }
+
+  pub fn write(&mut self, buf: &[u8]) -> Result<usize, &'static str> {
+    self.0.send(buf)
+  }
+
+  pub fn write_all(&mut self, mut buf: &[u8]) -> Result<(), &'static str> {
+    while !buf.is_empty() {
+      let n = self.write(buf)?;
+      buf = &buf[n..];
+    }
+    Ok(())
+  }
+}
+
+pub struct TcpListener(Socket);
+
+impl TcpListener {
+  /// Listen on `port` of any address, one connection is accepted at a time
+  pub fn bind(port: u16) -> Result<TcpListener, &'static str> {
+    let socket = Socket::new(kind::TCP)?;
+    call(action::LISTEN, socket.0, port as usize, 0)?;
+    Ok(TcpListener(socket))
+  }
+
+  pub fn accept(&self) -> Result<TcpStream, &'static str> {
+    call(action::ACCEPT, self.0 .0, 0, 0).map(|msg| TcpStream(Socket(msg.b)))
+  }
+}
+
+pub struct UdpSocket(Socket);
+
+impl UdpSocket {
+  pub fn bind(port: u16) -> Result<UdpSocket, &'static str> {
+    let socket = Socket::new(kind::UDP)?;
+    call(action::BIND, socket.0, port as usize, 0)?;
+    Ok(UdpSocket(socket))
+  }
+
+  pub fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> Result<usize, &'static str> {
+    call(action::SEND_TO, self.0 .0 | addr.endpoint() << 16, buf.as_ptr() as usize, buf.len()).map(|msg| msg.b)
+  }
+
+  pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4), &'static str> {
+    self.0.recv(buf)
+  }
+}
+
+// Call the local socket server, retrying while it replies `HOLD_ON`
+fn unix_call(a: usize, b: usize, c: usize, d: usize) -> Result<Message, &'static str> {
+  use rpservapi::unix::result;
+  loop {
+    let msg = Message::new(a, b, c, d).call(SERVER_UNIX).map_err(|_| "server call failed")?;
+    match msg.a {
+      result::OK => return Ok(msg),
+      result::HOLD_ON => rpsyscall::thread_yield(),
+      result::INVARG => return Err("invalid argument"),
+      result::ADDR_IN_USE => return Err("address in use"),
+      result::NOT_FOUND => return Err("no such socket"),
+      result::UNKNOWN_ACTION => return Err("unknown action"),
+      _ => return Err("socket error"),
+    }
+  }
+}
+
+struct UnixSocket(usize);
+
+impl UnixSocket {
+  fn new(kind: usize) -> Result<Self, &'static str> {
+    unix_call(unix::action::SOCKET, kind, 0, 0).map(|msg| UnixSocket(msg.b))
+  }
+
+  fn bind(kind: usize, path: &str) -> Result<Self, &'static str> {
+    let socket = UnixSocket::new(kind)?;
+    unix_call(unix::action::BIND, socket.0, path.as_ptr() as usize, path.len())?;
+    Ok(socket)
+  }
+
+  fn connect(&self, path: &str) -> Result<(), &'static str> {
+    unix_call(unix::action::CONNECT, self.0, path.as_ptr() as usize, path.len()).map(|_| ())
+  }
+
+  fn send(&self, buf: &[u8]) -> Result<usize, &'static str> {
+    unix_call(unix::action::SEND, self.0, buf.as_ptr() as usize, buf.len()).map(|msg| msg.b)
+  }
+
+  fn recv(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+    unix_call(unix::action::RECV, self.0, buf.as_mut_ptr() as usize, buf.len()).map(|msg| msg.b)
+  }
+}
+
+impl Drop for UnixSocket {
+  fn drop(&mut self) {
+    let _ = Message::new(unix::action::CLOSE, self.0, 0, 0).call(SERVER_UNIX);
+  }
+}
+
+/// Byte stream to a `UnixListener` of another process, named by an absolute path
+pub struct UnixStream(UnixSocket);
+
+impl UnixStream {
+  pub fn connect(path: &str) -> Result<UnixStream, &'static str> {
+    let socket = UnixSocket::new(unix::kind::STREAM)?;
+    socket.connect(path)?;
+    Ok(UnixStream(socket))
+  }
+
+  /// Read some bytes, 0 means the peer has closed the stream
+  pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+    self.0.recv(buf)
+  }
+
+  pub fn write(&mut self, buf: &[u8]) -> Result<usize, &'static str> {
+    self.0.send(buf)
+  }
+
+  pub fn write_all(&mut self, mut buf: &[u8]) -> Result<(), &'static str> {
+    while !buf.is_empty() {
+      let n = self.write(buf)?;
+      buf = &buf[n..];
+    }
+    Ok(())
+  }
+}
+
+/// The path is released when the listener is dropped
+pub struct UnixListener(UnixSocket);
+
+impl UnixListener {
+  pub fn bind(path: &str) -> Result<UnixListener, &'static str> {
+    let socket = UnixSocket::bind(unix::kind::STREAM, path)?;
+    unix_call(unix::action::LISTEN, socket.0, 0, 0)?;
+    Ok(UnixListener(socket))
+  }
+
+  pub fn accept(&self) -> Result<UnixStream, &'static str> {
+    unix_call(unix::action::ACCEPT, self.0 .0, 0, 0).map(|msg| UnixStream(UnixSocket(msg.b)))
+  }
+}
+
+/// Message boundaries are kept, datagrams are sent to the connected peer only
+pub struct UnixDatagram(UnixSocket);
+
+impl UnixDatagram {
+  pub fn bind(path: &str) -> Result<UnixDatagram, &'static str> {
+    UnixSocket::bind(unix::kind::DATAGRAM, path).map(UnixDatagram)
+  }
+
+  pub fn unbound() -> Result<UnixDatagram, &'static str> {
+    UnixSocket::new(unix::kind::DATAGRAM).map(UnixDatagram)
+  }
+
+  pub fn connect(&self, path: &str) -> Result<(), &'static str> {
+    self.0.connect(path)
+  }
+
+  pub fn send(&self, buf: &[u8]) -> Result<usize, &'static str> {
+    self.0.send(buf)
+  }
+
+  /// Receive one datagram, the part not fitting in `buf` is discarded
+  pub fn recv(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+    self.0.recv(buf)
+  }
+}
@@ -27,6 +27,16 @@ pub fn wait(pid: usize) {
   }
 }
 
+pub fn kill(pid: usize) -> Result<(), &'static str> {
+  let result = Message::new(
+    rpservapi::pm::action::KILL, pid, 0, 0,
+  ).call(rpabi::server::SERVER_PM).map_err(|_| "server call failed")?;
+  match result.a {
+    rpservapi::pm::result::OK => Ok(()),
+    _ => Err("no such running process"),
+  }
+}
+
 pub fn ps() {
   let _ = Message::new(
     rpservapi::pm::action::PS, 0, 0, 0,
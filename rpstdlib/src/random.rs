@@ -0,0 +1,25 @@
+use rpabi::server::SERVER_RNG;
+use rpservapi::rng::{action, result};
+use rpsyscall::message::Message;
+
+/// Fill `buf` with cryptographically secure random bytes
+pub fn fill(buf: &mut [u8]) -> Result<(), &'static str> {
+  let mut filled = 0;
+  while filled < buf.len() {
+    let rest = &mut buf[filled..];
+    let msg = Message::new(action::FILL, 0, rest.as_mut_ptr() as usize, rest.len())
+      .call(SERVER_RNG)
+      .map_err(|_| "server call failed")?;
+    match msg.a {
+      result::OK => filled += msg.b,
+      _ => return Err("random fill failed"),
+    }
+  }
+  Ok(())
+}
+
+pub fn random_u64() -> Result<u64, &'static str> {
+  let mut buf = [0u8; 8];
+  fill(&mut buf)?;
+  Ok(u64::from_ne_bytes(buf))
+}
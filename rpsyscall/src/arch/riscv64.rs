@@ -55,7 +55,9 @@ syscall! {
     syscall_1_1(a, b, ) -> (oa: usize, );
     syscall_2_1(a, b, c, ) -> (oa: usize, );
     syscall_0_2(a, ) -> (oa: usize, ob: usize, );
+    syscall_1_3(a, b, ) -> (oa: usize, ob: usize, oc: usize, );
     syscall_4_1(a, b, c, d, e, ) -> (oa: usize, );
     syscall_0_5(a, ) -> (oa: usize, ob: usize, oc: usize, od: usize, oe: usize, );
+    syscall_1_5(a, b, ) -> (oa: usize, ob: usize, oc: usize, od: usize, oe: usize, );
     syscall_5_5(a, b, c, d, e, f, ) -> (oa: usize, ob: usize, oc: usize, od: usize, oe: usize, );
 }
\ No newline at end of file
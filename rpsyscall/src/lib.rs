@@ -73,17 +73,22 @@ pub fn thread_destroy(tid: usize) {
 
 /// Wait for kernel event
 ///
-/// There are two types of event in rustpi:
+/// There are four types of event in rustpi:
 /// * Interrupt
 /// * Thread exit event
+/// * Thread suspend event
+/// * Timer event
 ///
 /// User-space use this syscall to sleep until the wanted event happens
 ///
 /// # Arguments
 ///
 /// * `event_type` - event types defined in `rpabi::event::`
-/// * `event_num` - for interrupt type: event_num is the interrupt number of system main interrupt controller (e.g., GIC)
+/// * `event_num` - for interrupt type: event_num is the interrupt number of system main interrupt controller (e.g., GIC),
+/// which must be bound to the calling thread by `irq_bind`
 /// ; for thread exit event: event_num is the identifier of the thread being waited to exit
+/// ; for thread suspend event: event_num is the identifier of the thread being waited to be off cpu and suspended
+/// ; for timer event: event_num is the number of milliseconds to sleep
 pub fn event_wait(event_type: usize, event_num: usize) -> Result<usize, Error> {
   syscall_2_1(SYS_EVENT_WAIT, event_type, event_num)
 }
@@ -138,6 +143,8 @@ pub fn mem_unmap(asid: u16, va: usize) -> Result<(), Error> {
 /// Create a new AddressSpace
 ///
 /// This syscall requests kernel to allocate new AddressSpace. It returns identifier of the newly created AddressSpace
+///
+/// Pages of the new AddressSpace are charged to the page budget of the current one as well, see `set_page_limit`
 pub fn address_space_alloc() -> Result<u16, Error> {
   syscall_0_1(SYS_ADDRESS_SPACE_ALLOC).map(|asid| asid as u16)
 }
@@ -168,8 +175,63 @@ pub fn thread_set_status(tid: usize, status: usize) -> Result<(), Error> {
   syscall_2_0(SYS_THREAD_SET_STATUS, tid, status)
 }
 
+/// Suspend a thread synchronously
+///
+/// The target is taken off its cpu before return; its status is kept and applies again on resume.
+/// Threads can be suspended, resumed and accessed by their parent only.
+///
+/// # Arguments
+///
+/// * `tid` - identifier of the target thread. Current thread cannot suspend itself.
+pub fn thread_suspend(tid: usize) -> Result<(), Error> {
+  use rpabi::syscall::error::ERROR_HOLD_ON;
+  syscall_1_0(SYS_THREAD_SUSPEND, tid)?;
+  // the kernel does not wait for another cpu to switch away from the target
+  loop {
+    match event_wait(rpabi::event::EVENT_THREAD_SUSPEND, tid) {
+      Err(ERROR_HOLD_ON) => thread_yield(),
+      r => break r.map(|_| ()),
+    }
+  }
+}
+
+/// Resume a suspended thread
+///
+/// # Arguments
+///
+/// * `tid` - identifier of the target thread
+/// * `single_step` - if set, the thread executes one instruction and suspends again (observable by `EVENT_THREAD_SUSPEND`).
+/// Not supported on riscv64.
+pub fn thread_resume(tid: usize, single_step: bool) -> Result<(), Error> {
+  syscall_2_0(SYS_THREAD_RESUME, tid, single_step as usize)
+}
+
+/// Read a register of a suspended thread
+///
+/// # Arguments
+///
+/// * `tid` - identifier of the target thread
+/// * `index` - register index defined in `rpabi::debug::`
+pub fn thread_register_read(tid: usize, index: usize) -> Result<usize, Error> {
+  syscall_2_1(SYS_THREAD_REGISTER_READ, tid, index)
+}
+
+/// Write a register of a suspended thread
+///
+/// # Arguments
+///
+/// * `tid` - identifier of the target thread
+/// * `index` - register index defined in `rpabi::debug::`
+/// * `value` - new register value
+pub fn thread_register_write(tid: usize, index: usize, value: usize) -> Result<(), Error> {
+  syscall_3_0(SYS_THREAD_REGISTER_WRITE, tid, index, value)
+}
+
 /// Destroy an AddressSpace
 ///
+/// All threads running in the AddressSpace are stopped and destroyed before its memory is released.
+/// Their parents receive thread exit events as usual.
+///
 /// # Arguments
 ///
 /// * `asid` - identifier of the AddressSpace to be destroyed
@@ -236,7 +298,7 @@ pub fn server_register(server_id: usize) -> Result<(), Error> {
 /// # Arguments
 ///
 /// * `server_id` - identifier of the target server
-fn server_tid(server_id: usize) -> Result<usize, Error> {
+pub fn server_tid(server_id: usize) -> Result<usize, Error> {
   syscall_1_1(SYS_SERVER_TID, server_id)
 }
 
@@ -256,7 +318,9 @@ pub fn server_tid_wait(server_id: usize) -> usize {
 
 /// Set exception handler for current AddressSpace
 ///
-/// Upon a user-space exception happens, the thread has exception will jump the specific handler with stack setup by kernel
+/// Upon a user-space exception happens, the thread has exception will jump the specific handler with stack setup by kernel.
+/// The kernel pushes the faulting context frame followed by the fault address below the stack pointer,
+/// and passes the address of the pushed frame as the first argument of the handler.
 ///
 /// # Arguments
 ///
@@ -265,6 +329,137 @@ pub fn set_exception_handler(handler: usize) -> Result<(), Error> {
   syscall_1_0(SYS_SET_EXCEPTION_HANDLER, handler)
 }
 
+/// Resume the context frame pushed by kernel upon exception
+///
+/// Never returns on success.
+///
+/// # Arguments
+///
+/// * `frame` - address of the (possibly modified) context frame passed to the exception handler
+pub fn exception_return(frame: usize) -> Result<(), Error> {
+  syscall_1_0(SYS_EXCEPTION_RETURN, frame)
+}
+
+/// Set the pager thread of an AddressSpace
+///
+/// Exceptions in the AddressSpace are then sent to the pager as messages (see `rpabi::pager`)
+/// instead of the exception handler, and stack pages are no longer allocated by kernel.
+/// Only the trusted root and the AddressSpace that allocated the target may set its pager.
+///
+/// # Arguments
+///
+/// * `asid` - identifier of the target AddressSpace. 0 means current AddressSpace.
+/// * `tid` - identifier of the pager thread, which cannot be inside the target AddressSpace. 0 to unset.
+pub fn set_pager(asid: u16, tid: usize) -> Result<(), Error> {
+  syscall_2_0(SYS_SET_PAGER, asid as usize, tid)
+}
+
+/// Set the page budget of an AddressSpace
+///
+/// Every page mapped into the AddressSpace is charged, and credited back when unmapped; so are its page tables.
+/// Pages of the AddressSpaces it allocated are charged to it as well, their own budgets only restrict them further.
+/// Allocation or mapping beyond the budget fails with `ERROR_OOM`.
+/// Only the trusted root and the AddressSpace that allocated the target may change its budget.
+///
+/// # Arguments
+///
+/// * `asid` - identifier of the target AddressSpace
+/// * `limit` - maximum number of pages. 0 means unlimited.
+///
+/// Returns the number of pages currently charged
+pub fn set_page_limit(asid: u16, limit: usize) -> Result<usize, Error> {
+  syscall_2_1(SYS_SET_PAGE_LIMIT, asid as usize, limit)
+}
+
+/// Query kernel memory statistics and the monotonic clock
+///
+/// # Arguments
+///
+/// * `item` - item defined in `rpabi::kstat::`. Unused trailing values are zero.
+pub fn kernel_stat(item: usize) -> Result<(usize, usize, usize, usize, usize), Error> {
+  syscall_1_5(SYS_KERNEL_STAT, item)
+}
+
+/// Bind a device interrupt to a driver thread
+///
+/// Only the trusted root may hand out interrupts. The line is unmasked once bound,
+/// and only the bound threads may wait for it by `event_wait` or control it.
+///
+/// # Arguments
+///
+/// * `irq` - interrupt number of system main interrupt controller
+/// * `tid` - identifier of the driver thread. 0 to unbind all threads of the line.
+/// * `flags` - `rpabi::irq::BIND_SHARED` if the line may be shared with other threads bound the same way
+pub fn irq_bind(irq: usize, tid: usize, flags: usize) -> Result<(), Error> {
+  syscall_3_0(SYS_IRQ_BIND, irq, tid, flags)
+}
+
+/// Configure a device interrupt line
+///
+/// Only the trusted root may configure interrupts.
+/// Controllers without the capability (e.g., trigger mode of PLIC) ignore the request.
+///
+/// # Arguments
+///
+/// * `irq` - interrupt number of system main interrupt controller
+/// * `item` - `rpabi::irq::CONFIG_*`
+/// * `value` - value of the item, see `rpabi::irq::`
+pub fn irq_config(irq: usize, item: usize, value: usize) -> Result<(), Error> {
+  syscall_3_0(SYS_IRQ_CONFIG, irq, item, value)
+}
+
+/// Mask an interrupt bound to current thread
+pub fn irq_mask(irq: usize) -> Result<(), Error> {
+  syscall_1_0(SYS_IRQ_MASK, irq)
+}
+
+/// Unmask an interrupt bound to current thread
+pub fn irq_unmask(irq: usize) -> Result<(), Error> {
+  syscall_1_0(SYS_IRQ_UNMASK, irq)
+}
+
+/// Acknowledge an interrupt bound to current thread
+///
+/// Kernel masks the line whenever it fires. Call this after the device has been serviced
+/// to re-enable the line, so that a level-triggered device does not fire again meanwhile.
+pub fn irq_ack(irq: usize) -> Result<(), Error> {
+  syscall_1_0(SYS_IRQ_ACK, irq)
+}
+
+/// Allocate a message-signalled interrupt for a device
+///
+/// Only the trusted root may allocate MSIs, and only on interrupt controllers supporting them
+/// (e.g., GICv3 with ITS). The interrupt starts masked until bound by `irq_bind`.
+///
+/// # Arguments
+///
+/// * `device_id` - identifier of the device, e.g., PCI requester id (bus << 8 | devfn)
+///
+/// Returns (interrupt number, doorbell physical address, data). The device raises the interrupt
+/// by writing the 32-bit data to the doorbell, i.e., MSI address and message data.
+pub fn msi_alloc(device_id: usize) -> Result<(usize, usize, usize), Error> {
+  syscall_1_3(SYS_MSI_ALLOC, device_id)
+}
+
+/// Free a message-signalled interrupt allocated by `msi_alloc`, unbinding it from its threads
+pub fn msi_free(irq: usize) -> Result<(), Error> {
+  syscall_1_0(SYS_MSI_FREE, irq)
+}
+
+/// Grant access to a range of I/O ports to an address space
+///
+/// Only the trusted root may grant I/O ports, and only on x86_64. Threads of the address space
+/// may then use `in`/`out` on the ports directly. Grants are never revoked.
+///
+/// # Arguments
+///
+/// * `asid` - identifier of the address space. 0 for current address space.
+/// * `port` - first port of the range
+/// * `len` - number of ports
+pub fn io_port_grant(asid: u16, port: usize, len: usize) -> Result<(), Error> {
+  syscall_3_0(SYS_IO_PORT_GRANT, asid as usize, port, len)
+}
+
 /// Get an input character from system console
 ///
 /// This syscall isn't implemented for all platforms
@@ -338,10 +533,14 @@ pub mod message {
 
     /// Send message to a server and wait for its reply
     pub fn call(&self, server_id: usize) -> Result<Self, super::Error> {
+      self.call_tid(super::server_tid_wait(server_id))
+    }
+
+    /// Send message to a thread and wait for its reply
+    pub fn call_tid(&self, tid: usize) -> Result<Self, super::Error> {
       use rpabi::syscall::error::ERROR_HOLD_ON;
-      let server_tid = super::server_tid_wait(server_id);
       loop {
-        match super::itc_call(server_tid, self.a, self.b, self.c, self.d) {
+        match super::itc_call(tid, self.a, self.b, self.c, self.d) {
           Ok((_, a, b, c, d)) => {
             break Ok(Message { a, b, c, d });
           }
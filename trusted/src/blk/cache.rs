@@ -0,0 +1,295 @@
+//! Page cache of block devices with write-back, served by `SERVER_BLK_CACHE` to every client
+//!
+//! Blocks are keyed by the thread serving the device, so a partition and its disk are cached apart and should not be
+//! used at the same time. Pages of the cache are shared with each device server, which transfers into them directly.
+//! The cache lock is never held across a call to a device, blocks being written back are pinned instead.
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use rpservapi::blk::action as blk_action;
+use rpservapi::blk::result as blk_result;
+use rpservapi::blk_cache::{action, result, BLOCK_SIZE};
+use rpsyscall::message::Message;
+use rpsyscall::{get_asid, get_tid};
+use spin::Mutex;
+
+use crate::blk::registry;
+use crate::common::foreign_slice::with_client_buffer;
+use crate::common::mm::virtual_alloc;
+
+const SECTOR_SIZE: usize = 512;
+// 4 MiB of cached blocks
+const CAPACITY: usize = 1024;
+const FLUSH_INTERVAL_MS: usize = 5000;
+
+struct Block {
+  // page of the pool holding the block
+  slot: usize,
+  dirty: bool,
+  // being written back, the slot is not to be reused
+  busy: bool,
+  last_use: u64,
+}
+
+struct Cache {
+  // `CAPACITY` pages, allocated on first use
+  pool: usize,
+  free: Vec<usize>,
+  // (device server, block number) -> block
+  blocks: BTreeMap<(usize, usize), Block>,
+  // devices the pool is shared with
+  shared: BTreeSet<usize>,
+  // devices written since their last `FLUSH`
+  unflushed: BTreeSet<usize>,
+  clock: u64,
+}
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+  pool: 0,
+  free: Vec::new(),
+  blocks: BTreeMap::new(),
+  shared: BTreeSet::new(),
+  unflushed: BTreeSet::new(),
+  clock: 0,
+});
+
+// Serializes write-backs, so that a flush returns only once blocks written back by another one reached the device
+static FLUSH: Mutex<()> = Mutex::new(());
+
+fn matches(server: Option<usize>, s: usize) -> bool {
+  server.is_none() || server == Some(s)
+}
+
+fn transfer(server: usize, block: usize, page: usize, op: usize) -> Result<(), &'static str> {
+  let msg = Message::new(block * (BLOCK_SIZE / SECTOR_SIZE), BLOCK_SIZE / SECTOR_SIZE, page, op)
+    .call_tid(server)
+    .map_err(|_| "server call failed")?;
+  if msg.a == blk_result::OK {
+    Ok(())
+  } else {
+    Err("transfer failed")
+  }
+}
+
+impl Cache {
+  fn page(&self, slot: usize) -> usize {
+    self.pool + slot * BLOCK_SIZE
+  }
+
+  fn page_mut(&mut self, slot: usize) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(self.page(slot) as *mut u8, BLOCK_SIZE) }
+  }
+}
+
+// Note: a device server in the same address space accepts the pool as is, sharing costs one call then
+fn share(server: usize) -> Result<(), &'static str> {
+  let mut cache = CACHE.lock();
+  if cache.pool == 0 {
+    cache.pool = virtual_alloc(CAPACITY, true).ok_or("out of memory")?;
+    cache.free = (0..CAPACITY).rev().collect();
+  }
+  if cache.shared.contains(&server) {
+    return Ok(());
+  }
+  let pool = cache.pool;
+  drop(cache);
+  let msg = Message::new(pool, CAPACITY, 0, blk_action::SHARE)
+    .call_tid(server)
+    .map_err(|_| "server call failed")?;
+  if msg.a != blk_result::OK {
+    return Err("share failed");
+  }
+  CACHE.lock().shared.insert(server);
+  Ok(())
+}
+
+/// Drop the least recently used block, written back first if dirty
+fn evict() -> Result<(), &'static str> {
+  let mut cache = CACHE.lock();
+  // Note: a linear scan, cheap next to a device transfer for the capacity used
+  let key = match cache.blocks.iter().filter(|(_, b)| !b.busy).min_by_key(|(_, b)| b.last_use) {
+    Some((key, _)) => *key,
+    None => return Err("cache busy"),
+  };
+  let block = cache.blocks.remove(&key).unwrap();
+  if !block.dirty {
+    cache.free.push(block.slot);
+    return Ok(());
+  }
+  let page = cache.page(block.slot);
+  drop(cache);
+  // the block is out of the table, nobody else touches its page meanwhile
+  let r = transfer(key.0, key.1, page, blk_action::WRITE);
+  let mut cache = CACHE.lock();
+  match r {
+    Ok(()) => {
+      cache.free.push(block.slot);
+      cache.unflushed.insert(key.0);
+    }
+    Err(_) => {
+      cache.blocks.insert(key, block);
+    }
+  }
+  r
+}
+
+/// Slot holding `block`, read from the device unless `read` is false as it is about to be overwritten
+fn load(server: usize, block: usize, read: bool) -> Result<usize, &'static str> {
+  let mut cache = CACHE.lock();
+  cache.clock += 1;
+  let clock = cache.clock;
+  if let Some(b) = cache.blocks.get_mut(&(server, block)) {
+    b.last_use = clock;
+    return Ok(b.slot);
+  }
+  drop(cache);
+  share(server)?;
+  let slot = loop {
+    if let Some(slot) = CACHE.lock().free.pop() {
+      break slot;
+    }
+    evict()?;
+  };
+  // Note: only the request thread loads blocks, the block cannot show up meanwhile
+  if read {
+    let page = CACHE.lock().page(slot);
+    if let Err(e) = transfer(server, block, page, blk_action::READ) {
+      CACHE.lock().free.push(slot);
+      return Err(e);
+    }
+  }
+  let b = Block {
+    slot,
+    dirty: false,
+    busy: false,
+    last_use: clock,
+  };
+  CACHE.lock().blocks.insert((server, block), b);
+  Ok(slot)
+}
+
+/// Read `block` of `BLOCK_SIZE` bytes of the device served by `server`
+fn read(server: usize, block: usize, buf: &mut [u8]) -> Result<(), &'static str> {
+  let slot = load(server, block, true)?;
+  let mut cache = CACHE.lock();
+  buf.copy_from_slice(cache.page_mut(slot));
+  Ok(())
+}
+
+/// Write `block` in the cache, the device sees it on `flush` or eviction
+fn write(server: usize, block: usize, buf: &[u8]) -> Result<(), &'static str> {
+  // the whole block is overwritten, no need to read it first
+  let slot = load(server, block, false)?;
+  let mut cache = CACHE.lock();
+  cache.page_mut(slot).copy_from_slice(buf);
+  if let Some(b) = cache.blocks.get_mut(&(server, block)) {
+    b.dirty = true;
+  }
+  Ok(())
+}
+
+/// Write back dirty blocks of the device served by `server`, or of every device, then flush device caches
+fn flush(server: Option<usize>) -> Result<(), &'static str> {
+  let _flush = FLUSH.lock();
+  let mut cache = CACHE.lock();
+  let pool = cache.pool;
+  let dirty: Vec<(usize, usize, usize)> = cache
+    .blocks
+    .iter_mut()
+    .filter(|(&(s, _), b)| b.dirty && matches(server, s))
+    .map(|(&(s, block), b)| {
+      b.dirty = false;
+      b.busy = true;
+      (s, block, pool + b.slot * BLOCK_SIZE)
+    })
+    .collect();
+  drop(cache);
+
+  let mut r = Ok(());
+  for (s, block, page) in dirty {
+    let written = transfer(s, block, page, blk_action::WRITE);
+    let mut cache = CACHE.lock();
+    if let Some(b) = cache.blocks.get_mut(&(s, block)) {
+      b.busy = false;
+      // Note: a write meanwhile marked it dirty again, it goes with the next flush
+      if written.is_err() {
+        b.dirty = true;
+      }
+    }
+    match written {
+      Ok(()) => {
+        cache.unflushed.insert(s);
+      }
+      Err(e) => {
+        error!("write back t{} block {} {}", s, block, e);
+        r = Err(e);
+      }
+    }
+  }
+  r?;
+
+  let servers: Vec<usize> = CACHE.lock().unflushed.iter().copied().filter(|&s| matches(server, s)).collect();
+  for s in servers {
+    // Note: taken off first, a write back landing during the call marks the device again
+    CACHE.lock().unflushed.remove(&s);
+    let flushed = match Message::new(0, 0, 0, blk_action::FLUSH).call_tid(s) {
+      Ok(msg) => msg.a == blk_result::OK || msg.a == blk_result::UNSUPPORTED,
+      Err(_) => false,
+    };
+    if !flushed {
+      CACHE.lock().unflushed.insert(s);
+      return Err("device flush failed");
+    }
+  }
+  Ok(())
+}
+
+fn request(asid: u16, msg: &Message) -> usize {
+  // Note: the cache calls and shares its pool with the device, only registered device servers are accepted
+  if !registry::is_server(msg.a) {
+    return result::INVARG;
+  }
+  let r = match msg.d {
+    action::READ => with_client_buffer(asid, msg.c, BLOCK_SIZE, |buf| read(msg.a, msg.b, buf)),
+    action::WRITE => with_client_buffer(asid, msg.c, BLOCK_SIZE, |buf| write(msg.a, msg.b, buf)),
+    action::FLUSH => Some(flush(Some(msg.a))),
+    _ => return result::UNKNOWN_ACTION,
+  };
+  match r {
+    Some(Ok(())) => result::OK,
+    Some(Err(e)) => {
+      error!("t{} block {} {}", msg.a, msg.b, e);
+      result::ERR
+    }
+    None => result::INVARG,
+  }
+}
+
+/// Block cache server
+pub fn server() {
+  info!("server started t{}", get_tid());
+  rpsyscall::server_register(rpabi::server::SERVER_BLK_CACHE).unwrap();
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let r = match get_asid(client_tid) {
+      Ok(asid) => request(asid, &msg),
+      Err(_) => result::ERR,
+    };
+    let _ = Message::new(r, 0, 0, 0).send_to(client_tid);
+  }
+}
+
+/// Write back dirty blocks every `FLUSH_INTERVAL_MS`
+pub fn flusher() {
+  info!("flusher started t{}", get_tid());
+  loop {
+    if let Err(e) = rpsyscall::event_wait(rpabi::event::EVENT_TIMER, FLUSH_INTERVAL_MS) {
+      // Note: the interval is not kept then, write back still happens between yields
+      warn!("flusher sleep {}", e);
+      rpsyscall::thread_yield();
+    }
+    if let Err(e) = flush(None) {
+      error!("periodic flush {}", e);
+    }
+  }
+}
@@ -0,0 +1,157 @@
+//! Client pages shared with block servers for transfers without copying
+//!
+//! Every block server lives in this address space, so one table serves them all. A client in another address space
+//! shares its buffers once with `SHARE`, the pages stay mapped here and the device transfers into them directly.
+//! Grants of a client go away with its address space, see `release`.
+use alloc::collections::BTreeMap;
+
+use rpabi::{CONFIG_USER_LIMIT, PAGE_SIZE};
+use rpservapi::blk::{action, result};
+use rpsyscall::get_asid;
+use rpsyscall::message::Message;
+use spin::Mutex;
+
+use crate::common::foreign_slice::ForeignSlice;
+
+const SECTOR_SIZE: usize = 512;
+// pages of one grant, as many as the block cache of a client
+const GRANT_PAGES_MAX: usize = 1024;
+// grants of one client address space, they are never reclaimed while it lives
+const GRANT_PER_CLIENT_MAX: usize = 16;
+
+struct Grant {
+  slice: ForeignSlice,
+  // requests in flight on its pages
+  pending: usize,
+  // the client is gone, dropped once no request is in flight
+  released: bool,
+}
+
+// (client address space, client address) -> pages mapped here
+static GRANTS: Mutex<BTreeMap<(u16, usize), Grant>> = Mutex::new(BTreeMap::new());
+
+fn local(asid: u16) -> bool {
+  matches!(get_asid(0), Ok(own) if own == asid)
+}
+
+/// Map `pages` at `va` of the client into this address space until `unshare`
+fn share(asid: u16, va: usize, pages: usize) -> usize {
+  if local(asid) {
+    return result::OK;
+  }
+  let len = match pages.checked_mul(PAGE_SIZE) {
+    Some(len) if pages != 0 && pages <= GRANT_PAGES_MAX => len,
+    _ => return result::INVARG,
+  };
+  if va & (PAGE_SIZE - 1) != 0 || !matches!(va.checked_add(len), Some(end) if end <= CONFIG_USER_LIMIT) {
+    return result::INVARG;
+  }
+  let mut grants = GRANTS.lock();
+  if grants.contains_key(&(asid, va)) {
+    return result::INVARG;
+  }
+  if grants.range((asid, 0)..=(asid, usize::MAX)).count() >= GRANT_PER_CLIENT_MAX {
+    return result::ERR;
+  }
+  match ForeignSlice::new(asid, va, len) {
+    Ok(slice) => {
+      grants.insert((asid, va), Grant { slice, pending: 0, released: false });
+      result::OK
+    }
+    Err(_) => result::ERR,
+  }
+}
+
+fn unshare(asid: u16, va: usize) -> usize {
+  if local(asid) {
+    return result::OK;
+  }
+  let mut grants = GRANTS.lock();
+  match grants.get(&(asid, va)) {
+    Some(grant) if grant.released => result::INVARG,
+    Some(grant) if grant.pending != 0 => result::BUSY,
+    Some(_) => {
+      grants.remove(&(asid, va));
+      result::OK
+    }
+    None => result::INVARG,
+  }
+}
+
+/// Drop every grant of `asid` as its address space is destroyed, before the asid is handed out again
+///
+/// Pages of requests in flight stay mapped until they complete.
+pub fn release(asid: u16) {
+  GRANTS.lock().retain(|&(a, _), grant| {
+    if a == asid {
+      grant.released = true;
+    }
+    !grant.released || grant.pending != 0
+  });
+}
+
+/// A client buffer in this address space, the grant holding it is not unshared while this lives
+pub struct Buffer {
+  // `None` for clients of this address space
+  grant: Option<(u16, usize)>,
+  addr: usize,
+}
+
+impl Buffer {
+  pub fn addr(&self) -> usize {
+    self.addr
+  }
+}
+
+impl Drop for Buffer {
+  fn drop(&mut self) {
+    let key = match self.grant {
+      Some(key) => key,
+      None => return,
+    };
+    let mut grants = GRANTS.lock();
+    if let Some(grant) = grants.get_mut(&key) {
+      grant.pending -= 1;
+      if grant.released && grant.pending == 0 {
+        grants.remove(&key);
+      }
+    }
+  }
+}
+
+/// Client buffer at `va`, which lies in a grant unless the client shares this address space
+fn translate(asid: u16, va: usize, len: usize) -> Option<Buffer> {
+  if local(asid) {
+    return Some(Buffer { grant: None, addr: va });
+  }
+  let mut grants = GRANTS.lock();
+  // the grant starting last at or below `va`
+  let (&key, grant) = grants.range_mut((asid, 0)..=(asid, va)).next_back()?;
+  let slice = &grant.slice;
+  let end = va.checked_add(len)?;
+  if grant.released || end > slice.slice_start + slice.slice_len {
+    return None;
+  }
+  let addr = slice.local_start + (va - slice.slice_start);
+  grant.pending += 1;
+  Some(Buffer { grant: Some(key), addr })
+}
+
+/// Result of a `SHARE` or `UNSHARE` request of `client`
+pub fn request(client: usize, msg: &Message) -> usize {
+  let asid = match get_asid(client) {
+    Ok(asid) => asid,
+    Err(_) => return result::ERR,
+  };
+  match msg.d {
+    action::SHARE => share(asid, msg.a, msg.b),
+    action::UNSHARE => unshare(asid, msg.a),
+    _ => result::INVARG,
+  }
+}
+
+/// Buffer of a `READ` or `WRITE` request of `client` in this address space, to be kept until the request completes
+pub fn buffer(client: usize, msg: &Message) -> Option<Buffer> {
+  let asid = get_asid(client).ok()?;
+  translate(asid, msg.c, msg.b.checked_mul(SECTOR_SIZE)?)
+}
@@ -33,7 +33,7 @@ pub fn server() {
   info!("{:?}", info);
   let blk_size = info.CardCapacity as usize;
   info!("server started t{}", get_tid());
-  rpsyscall::server_register(rpabi::server::SERVER_BLK).unwrap();
+  crate::blk::registry::attach("mmcblk0");
 
   loop {
     let (client_tid, msg) = rpsyscall::message::Message::receive().unwrap();
@@ -41,7 +41,16 @@ pub fn server() {
     if msg.d == 0 || msg.d == 1 {
       let sector = msg.a;
       let count = msg.b;
-      let buf = msg.c;
+      let buffer = match crate::blk::grant::buffer(client_tid, &msg) {
+        Some(buffer) => buffer,
+        None => {
+          let mut msg = rpsyscall::message::Message::default();
+          msg.a = rpservapi::blk::result::INVARG;
+          let _ = msg.send_to(client_tid);
+          continue;
+        }
+      };
+      let buf = buffer.addr();
       if msg.d == 0 {
         // Operation::Read
         let buf = unsafe {
@@ -71,6 +80,19 @@ pub fn server() {
       let mut msg = rpsyscall::message::Message::default();
       msg.a = blk_size;
       let _ = msg.send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::FLUSH {
+      let _ = rpsyscall::message::Message::default().send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::INFO {
+      let msg = rpsyscall::message::Message::new(rpservapi::blk::result::OK, blk_size / 512, 512, 0);
+      let _ = msg.send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::SHARE || msg.d == rpservapi::blk::action::UNSHARE {
+      let mut reply = rpsyscall::message::Message::default();
+      reply.a = crate::blk::grant::request(client_tid, &msg);
+      let _ = reply.send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::DISCARD {
+      let mut msg = rpsyscall::message::Message::default();
+      msg.a = rpservapi::blk::result::UNSUPPORTED;
+      let _ = msg.send_to(client_tid);
     } else {
       let mut msg = rpsyscall::message::Message::default();
       msg.a = 1;
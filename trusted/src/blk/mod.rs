@@ -1,7 +1,11 @@
+pub mod cache;
+pub mod grant;
+pub mod partition;
+pub mod registry;
 pub mod virtio_blk;
 
 #[cfg(feature = "k210")]
 pub mod k210_sdcard;
 
 #[cfg(target_arch = "x86_64")]
-pub mod ramdisk;
\ No newline at end of file
+pub mod ramdisk;
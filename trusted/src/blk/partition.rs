@@ -0,0 +1,413 @@
+//! Partitions of MBR and GPT disks, each served as a block device of its own
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use rpabi::PAGE_SIZE;
+use rpservapi::blk::{action, result};
+use rpsyscall::get_tid;
+use rpsyscall::message::Message;
+
+use crate::blk::{grant, registry};
+use crate::common::mm::{virtual_alloc, virtual_free};
+use crate::common::thread;
+
+const SECTOR_SIZE: usize = 512;
+// bytes of GPT partition entries read, 128 entries of 128 bytes as created by common tools
+const GPT_ENTRIES_SIZE_MAX: usize = 4 * PAGE_SIZE;
+
+const MBR_SIGNATURE_OFFSET: usize = 510;
+const MBR_DISK_SIGNATURE_OFFSET: usize = 440;
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_TYPE_EMPTY: u8 = 0;
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+const GPT_HEADER_LBA: usize = 1;
+
+struct Partition {
+  // 1-based as in device names
+  index: usize,
+  start: usize,
+  length: usize,
+  uuid: String,
+  label: Option<String>,
+}
+
+impl Partition {
+  fn fits(&self, capacity: usize) -> bool {
+    self.length != 0 && matches!(self.start.checked_add(self.length), Some(end) if end <= capacity)
+  }
+}
+
+// fields of a GPT header locating its partition entries
+struct GptHeader {
+  entries_lba: usize,
+  entry_num: usize,
+  entry_size: usize,
+  entries_crc: u32,
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+  u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+  u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+// CRC-32 (IEEE 802.3) used by GPT
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for &b in data {
+    crc ^= b as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+// Note: the first three fields of a GUID are stored little endian
+fn guid(b: &[u8]) -> String {
+  format!(
+    "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+    u32_at(b, 0),
+    u16_at(b, 4),
+    u16_at(b, 6),
+    b[8],
+    b[9],
+    b[10],
+    b[11],
+    b[12],
+    b[13],
+    b[14],
+    b[15]
+  )
+}
+
+// Linux style, `vda1` but `ramdisk0p1`
+fn partition_name(disk: &str, index: usize) -> String {
+  if disk.ends_with(|c: char| c.is_ascii_digit()) {
+    format!("{}p{}", disk, index)
+  } else {
+    format!("{}{}", disk, index)
+  }
+}
+
+fn read(server: usize, sector: usize, buf: &mut [u8]) -> Result<(), &'static str> {
+  let msg = Message::new(sector, buf.len() / SECTOR_SIZE, buf.as_mut_ptr() as usize, action::READ)
+    .call_tid(server)
+    .map_err(|_| "server call failed")?;
+  match msg.a {
+    result::OK => Ok(()),
+    _ => Err("read failed"),
+  }
+}
+
+// A protective MBR covers the disk with a single GPT partition
+fn protective(mbr: &[u8]) -> bool {
+  (0..4).any(|i| mbr[MBR_ENTRIES_OFFSET + i * 16 + 4] == MBR_TYPE_GPT_PROTECTIVE)
+}
+
+fn mbr_partitions(mbr: &[u8]) -> Vec<Partition> {
+  let disk_signature = u32_at(mbr, MBR_DISK_SIGNATURE_OFFSET);
+  let mut partitions = Vec::new();
+  for i in 0..4 {
+    let entry = &mbr[MBR_ENTRIES_OFFSET + i * 16..MBR_ENTRIES_OFFSET + (i + 1) * 16];
+    let kind = entry[4];
+    if kind == MBR_TYPE_EMPTY {
+      continue;
+    }
+    if MBR_TYPE_EXTENDED.contains(&kind) {
+      warn!("logical partitions in mbr slot {} are not supported", i + 1);
+      continue;
+    }
+    partitions.push(Partition {
+      index: i + 1,
+      start: u32_at(entry, 8) as usize,
+      length: u32_at(entry, 12) as usize,
+      uuid: format!("{:08x}-{:02x}", disk_signature, i + 1),
+      label: None,
+    });
+  }
+  partitions
+}
+
+// Note: the checksum field of `header` is cleared as it is checked
+fn gpt_header(header: &mut [u8]) -> Result<GptHeader, &'static str> {
+  let header_size = u32_at(header, 12) as usize;
+  if &header[..8] != GPT_SIGNATURE || !(92..=SECTOR_SIZE).contains(&header_size) {
+    return Err("gpt header invalid");
+  }
+  let header_crc = u32_at(header, 16);
+  header[16..20].fill(0);
+  if crc32(&header[..header_size]) != header_crc {
+    return Err("gpt header checksum mismatch");
+  }
+  let gpt = GptHeader {
+    entries_lba: u64_at(header, 72) as usize,
+    entry_num: u32_at(header, 80) as usize,
+    entry_size: u32_at(header, 84) as usize,
+    entries_crc: u32_at(header, 88),
+  };
+  if gpt.entry_size < 128 || gpt.entry_num * gpt.entry_size > GPT_ENTRIES_SIZE_MAX {
+    return Err("gpt entries unsupported");
+  }
+  Ok(gpt)
+}
+
+fn gpt_partitions(server: usize, buf: &mut [u8]) -> Result<Vec<Partition>, &'static str> {
+  read(server, GPT_HEADER_LBA, &mut buf[..SECTOR_SIZE])?;
+  let gpt = gpt_header(&mut buf[..SECTOR_SIZE])?;
+  let sectors = (gpt.entry_num * gpt.entry_size).div_ceil(SECTOR_SIZE);
+  read(server, gpt.entries_lba, &mut buf[..sectors * SECTOR_SIZE])?;
+  gpt_entries(&gpt, buf)
+}
+
+fn gpt_entries(gpt: &GptHeader, buf: &[u8]) -> Result<Vec<Partition>, &'static str> {
+  let entry_size = gpt.entry_size;
+  if crc32(&buf[..gpt.entry_num * entry_size]) != gpt.entries_crc {
+    return Err("gpt entries checksum mismatch");
+  }
+  let mut partitions = Vec::new();
+  for i in 0..gpt.entry_num {
+    let entry = &buf[i * entry_size..(i + 1) * entry_size];
+    // unused entries have a zero type
+    if entry[..16].iter().all(|&b| b == 0) {
+      continue;
+    }
+    let first = u64_at(entry, 32) as usize;
+    let last = u64_at(entry, 40) as usize;
+    if last < first {
+      continue;
+    }
+    let name: Vec<u16> = (0..36).map(|j| u16_at(entry, 56 + j * 2)).take_while(|&c| c != 0).collect();
+    let label: String = char::decode_utf16(name).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect();
+    partitions.push(Partition {
+      index: i + 1,
+      start: first,
+      length: last - first + 1,
+      uuid: guid(&entry[16..32]),
+      label: if label.is_empty() { None } else { Some(label) },
+    });
+  }
+  Ok(partitions)
+}
+
+fn partitions(server: usize) -> Result<Vec<Partition>, &'static str> {
+  let pages = GPT_ENTRIES_SIZE_MAX / PAGE_SIZE;
+  let va = virtual_alloc(pages, true).ok_or("out of memory")?;
+  let buf = unsafe { core::slice::from_raw_parts_mut(va as *mut u8, GPT_ENTRIES_SIZE_MAX) };
+  let r = read(server, 0, &mut buf[..SECTOR_SIZE]).and_then(|_| {
+    let mbr = &buf[..SECTOR_SIZE];
+    if u16_at(mbr, MBR_SIGNATURE_OFFSET) != 0xaa55 {
+      return Ok(Vec::new());
+    }
+    if protective(mbr) {
+      gpt_partitions(server, buf)
+    } else {
+      Ok(mbr_partitions(mbr))
+    }
+  });
+  virtual_free(va, pages);
+  r
+}
+
+// Note: requests are forwarded one at a time, a partition does not queue on the disk concurrently
+fn serve(parent: usize, start: usize, length: usize, name: String) {
+  registry::attach(&name);
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let reply = match msg.d {
+      action::READ | action::WRITE | action::DISCARD => {
+        // Note: the disk is served in this address space, buffers are passed on translated
+        let buffer = match msg.d {
+          action::DISCARD => None,
+          _ => grant::buffer(client_tid, &msg),
+        };
+        let buf = match msg.d {
+          action::DISCARD => Some(0),
+          _ => buffer.as_ref().map(grant::Buffer::addr),
+        };
+        match buf {
+          Some(buf) if msg.b != 0 && matches!(msg.a.checked_add(msg.b), Some(end) if end <= length) => {
+            Message::new(start + msg.a, msg.b, buf, msg.d)
+              .call_tid(parent)
+              .unwrap_or(Message::new(result::ERR, 0, 0, 0))
+          }
+          _ => Message::new(result::INVARG, 0, 0, 0),
+        }
+      }
+      action::SHARE | action::UNSHARE => Message::new(grant::request(client_tid, &msg), 0, 0, 0),
+      action::FLUSH => Message::new(0, 0, 0, msg.d).call_tid(parent).unwrap_or(Message::new(result::ERR, 0, 0, 0)),
+      action::SIZE => Message::new(length * SECTOR_SIZE, 0, 0, 0),
+      action::INFO => match Message::new(0, 0, 0, msg.d).call_tid(parent) {
+        Ok(info) if info.a == result::OK => Message::new(result::OK, length, info.c, info.d),
+        _ => Message::new(result::ERR, 0, 0, 0),
+      },
+      _ => Message::new(result::INVARG, 0, 0, 0),
+    };
+    let _ = reply.send_to(client_tid);
+  }
+}
+
+/// Register every partition of `disk` as a device, named `vda1` and so on
+///
+/// Called by root for each disk, `registry::scan_begin` is to be called before. Opening names not registered yet holds
+/// on until all scans end.
+pub fn scan(disk: String) {
+  info!("scan {} t{}", disk, get_tid());
+  let found = registry::wait(&disk).ok_or("disk removed").and_then(|server| {
+    let capacity = match Message::new(0, 0, 0, action::INFO).call_tid(server) {
+      Ok(info) if info.a == result::OK => info.b,
+      _ => return Err("disk info failed"),
+    };
+    partitions(server).map(|partitions| (server, capacity, partitions))
+  });
+  let mut handlers = Vec::new();
+  match found {
+    Ok((server, capacity, partitions)) => {
+      for p in partitions {
+        let name = partition_name(&disk, p.index);
+        if !p.fits(capacity) {
+          warn!("{} beyond the end of {}", name, disk);
+          continue;
+        }
+        info!("{} start {} sectors {} uuid {} label {:?}", name, p.start, p.length, p.uuid, p.label);
+        registry::add_partition(&name, Some(p.uuid), p.label);
+        let (start, length) = (p.start, p.length);
+        handlers.push(thread::spawn(move || serve(server, start, length, name)));
+      }
+    }
+    Err(e) => warn!("{} partitions {}", disk, e),
+  }
+  registry::scan_end();
+  for handler in handlers {
+    let _ = handler.join();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate std;
+
+  use std::vec::Vec;
+
+  use super::*;
+
+  const DISK_SECTORS: usize = 8192;
+
+  fn mbr(entries: &[(u8, u32, u32)]) -> Vec<u8> {
+    let mut mbr = vec![0u8; SECTOR_SIZE];
+    mbr[MBR_DISK_SIGNATURE_OFFSET..MBR_DISK_SIGNATURE_OFFSET + 4].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    for (i, &(kind, start, length)) in entries.iter().enumerate() {
+      let entry = &mut mbr[MBR_ENTRIES_OFFSET + i * 16..MBR_ENTRIES_OFFSET + (i + 1) * 16];
+      entry[4] = kind;
+      entry[8..12].copy_from_slice(&start.to_le_bytes());
+      entry[12..16].copy_from_slice(&length.to_le_bytes());
+    }
+    mbr[MBR_SIGNATURE_OFFSET..].copy_from_slice(&0xaa55u16.to_le_bytes());
+    mbr
+  }
+
+  // 4 entries of 128 bytes at LBA 2, the second one unused
+  fn gpt_disk(first: u64, last: u64) -> (Vec<u8>, Vec<u8>) {
+    let mut entries = vec![0u8; 4 * 128];
+    let entry = &mut entries[..128];
+    entry[..16].fill(0xaf);
+    entry[16..32].copy_from_slice(&(0..16).collect::<Vec<u8>>());
+    entry[32..40].copy_from_slice(&first.to_le_bytes());
+    entry[40..48].copy_from_slice(&last.to_le_bytes());
+    for (j, c) in "root".encode_utf16().enumerate() {
+      entry[56 + j * 2..58 + j * 2].copy_from_slice(&c.to_le_bytes());
+    }
+    let entry = &mut entries[256..384];
+    entry[..16].fill(0xaf);
+    entry[32..40].copy_from_slice(&4096u64.to_le_bytes());
+    entry[40..48].copy_from_slice(&4096u64.to_le_bytes());
+
+    let mut header = vec![0u8; SECTOR_SIZE];
+    header[..8].copy_from_slice(GPT_SIGNATURE);
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&4u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    (header, entries)
+  }
+
+  #[test]
+  fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(b""), 0);
+  }
+
+  #[test]
+  fn guid_mixed_endian() {
+    let bytes: Vec<u8> = (0..16).collect();
+    assert_eq!(guid(&bytes), "03020100-0504-0706-0809-0a0b0c0d0e0f");
+  }
+
+  #[test]
+  fn mbr_primary_partitions() {
+    let mbr = mbr(&[(0x83, 2048, 4096), (MBR_TYPE_EMPTY, 0, 0), (0x0c, 6144, 2048)]);
+    assert!(!protective(&mbr));
+    let partitions = mbr_partitions(&mbr);
+    assert_eq!(partitions.len(), 2);
+    assert_eq!((partitions[0].index, partitions[0].start, partitions[0].length), (1, 2048, 4096));
+    assert_eq!(partitions[0].uuid, "12345678-01");
+    assert_eq!((partitions[1].index, partitions[1].start, partitions[1].length), (3, 6144, 2048));
+    assert_eq!(partitions[1].uuid, "12345678-03");
+    assert!(partitions.iter().all(|p| p.label.is_none() && p.fits(DISK_SECTORS)));
+  }
+
+  #[test]
+  fn gpt_partitions_of_protective_mbr() {
+    assert!(protective(&mbr(&[(MBR_TYPE_GPT_PROTECTIVE, 1, DISK_SECTORS as u32 - 1)])));
+    let (mut header, entries) = gpt_disk(34, 2081);
+    let gpt = gpt_header(&mut header).unwrap();
+    assert_eq!((gpt.entries_lba, gpt.entry_num, gpt.entry_size), (2, 4, 128));
+    let partitions = gpt_entries(&gpt, &entries).unwrap();
+    assert_eq!(partitions.len(), 2);
+    assert_eq!((partitions[0].index, partitions[0].start, partitions[0].length), (1, 34, 2048));
+    assert_eq!(partitions[0].uuid, "03020100-0504-0706-0809-0a0b0c0d0e0f");
+    assert_eq!(partitions[0].label.as_deref(), Some("root"));
+    assert_eq!((partitions[1].index, partitions[1].start, partitions[1].length), (3, 4096, 1));
+    assert_eq!(partitions[1].label, None);
+  }
+
+  #[test]
+  fn gpt_checksum_mismatch() {
+    let (mut header, _) = gpt_disk(34, 2081);
+    header[72] = 3;
+    assert_eq!(gpt_header(&mut header).err(), Some("gpt header checksum mismatch"));
+
+    let (mut header, mut entries) = gpt_disk(34, 2081);
+    let gpt = gpt_header(&mut header).unwrap();
+    entries[56] = b'R';
+    assert_eq!(gpt_entries(&gpt, &entries).err(), Some("gpt entries checksum mismatch"));
+
+    let (mut header, _) = gpt_disk(34, 2081);
+    header[0] = 0;
+    assert_eq!(gpt_header(&mut header).err(), Some("gpt header invalid"));
+  }
+
+  #[test]
+  fn partition_past_capacity() {
+    let (mut header, entries) = gpt_disk(6144, DISK_SECTORS as u64);
+    let partitions = gpt_entries(&gpt_header(&mut header).unwrap(), &entries).unwrap();
+    assert!(!partitions[0].fits(DISK_SECTORS));
+    assert!(partitions[0].fits(DISK_SECTORS + 1));
+
+    let partitions = mbr_partitions(&mbr(&[(0x83, u32::MAX, u32::MAX), (0x83, 2048, 0)]));
+    assert!(partitions.iter().all(|p| !p.fits(DISK_SECTORS)));
+  }
+}
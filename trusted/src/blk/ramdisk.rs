@@ -19,31 +19,48 @@ macro_rules! include_bytes_align_as {
 struct Align4096;
 static RAMDISK: &'static [u8] = include_bytes_align_as!(Align4096, "../../../ramdisk.img");
 
-pub fn server() {
+/// Block server of the ramdisk image linked into trusted, registered as `name`
+pub fn server(name: &str) {
   let ramdisk = unsafe { core::slice::from_raw_parts_mut(RAMDISK.as_ptr() as usize as *mut u8, RAMDISK.len()) };
   let ramdisk_addr = ramdisk.as_ptr() as usize;
   info!("server started t{}",  rpsyscall::get_tid());
-  rpsyscall::server_register(rpabi::server::SERVER_BLK).unwrap();
+  crate::blk::registry::attach(name);
 
   loop {
     let (client_tid, msg) = rpsyscall::message::Message::receive().unwrap();
     if msg.d == rpservapi::blk::action::READ || msg.d == rpservapi::blk::action::WRITE {
       let sector = msg.a;
       let count = msg.b;
-      let buf = msg.c;
+      let buffer = match crate::blk::grant::buffer(client_tid, &msg) {
+        Some(buffer) => buffer,
+        None => {
+          let mut msg = rpsyscall::message::Message::default();
+          msg.a = rpservapi::blk::result::INVARG;
+          let _ = msg.send_to(client_tid);
+          continue;
+        }
+      };
 
-      let start = sector * 512;
-      let end = (sector + count) * 512;
+      let buf = buffer.addr();
+      let range = sector.checked_add(count).and_then(|end| Some((sector.checked_mul(512)?, end.checked_mul(512)?)));
+      let (start, end) = match range {
+        Some((start, end)) if count != 0 && end <= ramdisk.len() => (start, end),
+        _ => {
+          let msg = rpsyscall::message::Message::new(rpservapi::blk::result::INVARG, 0, 0, 0);
+          let _ = msg.send_to(client_tid);
+          continue;
+        }
+      };
       if msg.d == rpservapi::blk::action::READ {
         // Operation::Read
         let buf = unsafe {
-          core::slice::from_raw_parts_mut(buf as *mut u8, count * 512)
+          core::slice::from_raw_parts_mut(buf as *mut u8, end - start)
         };
         buf.copy_from_slice(&ramdisk[start..end]);
       } else {
         // Operation::Write
         let buf = unsafe {
-          core::slice::from_raw_parts(buf as *const u8, count * 512)
+          core::slice::from_raw_parts(buf as *const u8, end - start)
         };
         // ramdisk[start..end].copy_from_slice(buf);
         for i in start..end {
@@ -59,6 +76,19 @@ pub fn server() {
       let mut msg = rpsyscall::message::Message::default();
       msg.a = ramdisk.len();
       let _ = msg.send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::FLUSH {
+      let _ = rpsyscall::message::Message::default().send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::INFO {
+      let msg = rpsyscall::message::Message::new(rpservapi::blk::result::OK, ramdisk.len() / 512, 512, 0);
+      let _ = msg.send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::SHARE || msg.d == rpservapi::blk::action::UNSHARE {
+      let mut reply = rpsyscall::message::Message::default();
+      reply.a = crate::blk::grant::request(client_tid, &msg);
+      let _ = reply.send_to(client_tid);
+    } else if msg.d == rpservapi::blk::action::DISCARD {
+      let mut msg = rpsyscall::message::Message::default();
+      msg.a = rpservapi::blk::result::UNSUPPORTED;
+      let _ = msg.send_to(client_tid);
     } else {
       let mut msg = rpsyscall::message::Message::default();
       msg.a = rpservapi::blk::result::ERR;
@@ -0,0 +1,135 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rpservapi::blk::{action, result};
+use rpsyscall::message::Message;
+use rpsyscall::{get_asid, get_tid};
+use spin::Mutex;
+
+use crate::common::foreign_slice::with_client_buffer;
+
+const NAME_MAX: usize = 128;
+
+struct Entry {
+  // thread serving the device, `None` until its driver is ready
+  server: Option<usize>,
+  part_uuid: Option<String>,
+  part_label: Option<String>,
+}
+
+static DEVICES: Mutex<BTreeMap<String, Entry>> = Mutex::new(BTreeMap::new());
+// partition tables being read, names not found yet may still appear
+static SCANNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Reserve `name` for a device found by root, opening it holds on until `attach`
+pub fn add(name: &str) {
+  add_partition(name, None, None);
+}
+
+/// Reserve `name` for a partition, also found by `PARTUUID=uuid` or `PARTLABEL=label`
+pub fn add_partition(name: &str, part_uuid: Option<String>, part_label: Option<String>) {
+  let entry = Entry {
+    server: None,
+    part_uuid,
+    part_label,
+  };
+  DEVICES.lock().insert(String::from(name), entry);
+}
+
+/// Called by the driver once the current thread serves requests of `name`
+pub fn attach(name: &str) {
+  let mut devices = DEVICES.lock();
+  match devices.get_mut(name) {
+    Some(entry) => entry.server = Some(get_tid()),
+    None => {
+      devices.insert(
+        String::from(name),
+        Entry {
+          server: Some(get_tid()),
+          part_uuid: None,
+          part_label: None,
+        },
+      );
+    }
+  }
+}
+
+/// Called by the driver when the device cannot be used
+pub fn remove(name: &str) {
+  DEVICES.lock().remove(name);
+}
+
+/// Thread serving `name` once its driver is ready, for clients in this address space
+pub fn wait(name: &str) -> Option<usize> {
+  loop {
+    match DEVICES.lock().get(name) {
+      Some(Entry { server: Some(server), .. }) => return Some(*server),
+      Some(_) => {}
+      None => return None,
+    }
+    rpsyscall::thread_yield();
+  }
+}
+
+/// Whether `tid` serves a registered device
+pub fn is_server(tid: usize) -> bool {
+  DEVICES.lock().values().any(|e| e.server == Some(tid))
+}
+
+pub fn scan_begin() {
+  SCANNING.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn scan_end() {
+  SCANNING.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Device named by `spec`, a device name, `PARTUUID=uuid` or `PARTLABEL=label` as on Linux
+fn lookup(spec: &str) -> (usize, usize) {
+  // Note: the counter is read before the table, a scan finishing in between is seen as still running
+  let scanning = SCANNING.load(Ordering::SeqCst) != 0;
+  let devices = DEVICES.lock();
+  let entry = if let Some(uuid) = spec.strip_prefix("PARTUUID=") {
+    devices.values().find(|e| matches!(&e.part_uuid, Some(u) if u.eq_ignore_ascii_case(uuid)))
+  } else if let Some(label) = spec.strip_prefix("PARTLABEL=") {
+    devices.values().find(|e| e.part_label.as_deref() == Some(label))
+  } else {
+    devices.get(spec)
+  };
+  match entry {
+    Some(Entry { server: Some(server), .. }) => (result::OK, *server),
+    Some(_) => (result::HOLD_ON, 0),
+    None if scanning => (result::HOLD_ON, 0),
+    None => (result::NOT_FOUND, 0),
+  }
+}
+
+fn open(msg: Message, tid: usize) -> (usize, usize) {
+  let asid = match get_asid(tid) {
+    Ok(asid) => asid,
+    Err(_) => return (result::ERR, 0),
+  };
+  if msg.b == 0 || msg.b > NAME_MAX {
+    return (result::INVARG, 0);
+  }
+  let spec = match with_client_buffer(asid, msg.a, msg.b, |spec| core::str::from_utf8(spec).ok().map(String::from)) {
+    Some(Some(spec)) => spec,
+    _ => return (result::INVARG, 0),
+  };
+  lookup(&spec)
+}
+
+/// Block device registry
+pub fn server() {
+  info!("server started t{}", get_tid());
+  rpsyscall::server_register(rpabi::server::SERVER_BLK).unwrap();
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let (a, b) = match msg.d {
+      action::OPEN => open(msg, client_tid),
+      _ => (result::INVARG, 0),
+    };
+    let _ = Message::new(a, b, 0, 0).send_to(client_tid);
+  }
+}
@@ -1,437 +1,393 @@
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::mem::size_of;
 
-use tock_registers::interfaces::{Readable, Writeable};
+use rpabi::platform::Device;
+use rpabi::PAGE_SIZE;
+use rpservapi::blk::{action, info, result};
+use rpsyscall::get_tid;
+use rpsyscall::message::Message;
+use spin::Mutex;
 
+use crate::blk::{grant, registry};
 use crate::common::mm::virt_to_phys;
-use hardware::virtio_mmio::*;
-use rpsyscall::get_tid;
+use crate::common::thread;
+use crate::common::virtio::{self, Transport, VirtQueue};
+
+const VIRTIO_ID_BLOCK: u32 = 2;
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_BLK_F_MQ: u64 = 1 << 12;
+const VIRTIO_BLK_F_DISCARD: u64 = 1 << 13;
+
+// offsets in `struct virtio_blk_config`
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+const CONFIG_NUM_QUEUES: usize = 34;
+const CONFIG_MAX_DISCARD_SECTORS: usize = 36;
+const CONFIG_DISCARD_SECTOR_ALIGNMENT: usize = 44;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_DISCARD: u32 = 11;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+const SECTOR_SIZE: usize = 512;
+const QUEUE_SIZE: usize = 128;
+const QUEUE_MAX: usize = 4;
+// pages of a buffer passed in one request, each takes a descriptor
+const SEGMENT_MAX: usize = 32;
 
-struct VirtioBlkInnerMut {
-  ring: VirtioRing,
-  last_used: u16,
-  queue: [Option<DiskRequest>; DRIVER_QUEUE_SIZE], // head desc -> disk_request
-  desc_free: [bool; DRIVER_QUEUE_SIZE],
+#[repr(C)]
+struct Header {
+  t: u32,
+  reserved: u32,
+  sector: u64,
 }
 
-struct VirtioBlk {
-  mmio: VirtioMmio,
-  irq: usize,
-  size_in_sector: usize,
-  mutable: VirtioBlkInnerMut, /* > */
+// `struct virtio_blk_discard_write_zeroes`
+#[repr(C)]
+struct DiscardRange {
+  sector: u64,
+  num_sectors: u32,
+  flags: u32,
 }
 
-trait BaseAddr {
-  fn base_addr_u64(&self) -> u64;
-  fn base_addr_usize(&self) -> usize;
+// Note: the alignment keeps the parts read and written by the device in one page so that they are physically contiguous
+#[repr(C, align(64))]
+struct RequestBlock {
+  header: Header,
+  discard: DiscardRange,
+  status: u8,
 }
 
-impl<T> BaseAddr for T {
-  fn base_addr_u64(&self) -> u64 {
-    self as *const T as u64
-  }
-  fn base_addr_usize(&self) -> usize {
-    self as *const T as usize
-  }
+struct Request {
+  block: Box<RequestBlock>,
+  client: usize,
+  // keeps the grant of the client buffer until the device is done with it
+  _buffer: Option<grant::Buffer>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Operation {
+  Read,
+  Write,
+  Flush,
+  Discard,
 }
 
-const DRIVER_QUEUE_SIZE: usize = 16;
-const VRING_DESC_F_NEXT: u16 = 1;
-const VRING_DESC_F_WRITE: u16 = 2;
+enum Submitted {
+  Queued,
+  // no descriptors left until some requests complete
+  Full,
+  // the request is answered without the device
+  Reply(usize),
+}
+
+struct VirtioBlk {
+  transport: Box<dyn Transport>,
+  queues: Vec<VirtQueue<QUEUE_SIZE>>,
+  // per queue, head descriptor id -> request in flight
+  pending: Vec<Vec<Option<Request>>>,
+  capacity: usize,
+  block_size: usize,
+  // sectors of one discard and alignment of its range, in sectors
+  max_discard: usize,
+  discard_alignment: usize,
+  features: u64,
+}
 
 impl VirtioBlk {
-  fn new(base_addr: usize, irq_num: usize) -> Self {
-    VirtioBlk {
-      mmio: VirtioMmio::new(base_addr),
-      irq: irq_num,
-      size_in_sector: 0,
-      mutable: /* Mutex::new( */VirtioBlkInnerMut {ring: VirtioRing {
-        desc: [VirtioRingDesc {
-          addr: 0,
-          len: 0,
-          flags: 0,
-          next: 0,
-        }; DRIVER_QUEUE_SIZE],
-        driver: VirtioRingDriver {
-          flags: 0,
-          idx: 0,
-          ring: [0xffff; DRIVER_QUEUE_SIZE],
-        },
-        device: VirtioRingDevice {
-          flags: 0,
-          idx: 0,
-          ring: [VirtioRingDeviceElement { id: 0, len: 0 }; DRIVER_QUEUE_SIZE],
-        },
-      },
-        last_used: 0,
-        queue: [NONE_DISK_REQUEST; DRIVER_QUEUE_SIZE],
-        desc_free: [true; DRIVER_QUEUE_SIZE],
-      }/* ) */
+  fn new(transport: Box<dyn Transport>) -> Result<Self, &'static str> {
+    if transport.device_id() != VIRTIO_ID_BLOCK {
+      return Err("not a block device");
     }
-  }
-
-  fn init(&mut self) {
-    const MMIO_MAGIC: u32 = 0x74726976;
-    let mmio = &self.mmio;
-    if mmio.MagicValue.get() != MMIO_MAGIC || mmio.Version.get() != 2 || mmio.DeviceID.get() != 2 {
-      panic!("Unsupported magic/version/device id");
+    let features = virtio::negotiate(
+      transport.as_ref(),
+      VIRTIO_BLK_F_RO | VIRTIO_BLK_F_BLK_SIZE | VIRTIO_BLK_F_FLUSH | VIRTIO_BLK_F_MQ | VIRTIO_BLK_F_DISCARD,
+    )?;
+    let queue_num = if features & VIRTIO_BLK_F_MQ != 0 {
+      (transport.config::<u16>(CONFIG_NUM_QUEUES) as usize).clamp(1, QUEUE_MAX)
+    } else {
+      1
+    };
+    let mut queues = Vec::new();
+    for i in 0..queue_num {
+      queues.push(VirtQueue::new(transport.as_ref(), i as u16)?);
     }
-    mmio.Status.set(0);
-    let mut status = mmio.Status.extract();
-    status.modify(VirtioConfigStatus::ACKNOWLEDGE::SET);
-    mmio.Status.set(status.get());
-    status.modify(VirtioConfigStatus::DRIVER::SET);
-    mmio.Status.set(status.get());
-
-    mmio.DeviceFeaturesSel.set(0);
-    trace!("device feature low  {:08x}", mmio.DeviceFeatures.get());
-    mmio.DeviceFeaturesSel.set(1);
-    trace!("device feature high {:08x}", mmio.DeviceFeatures.get());
-
-    mmio.DriverFeaturesSel.set(0);
-    mmio.DriverFeatures.write(
-      VirtioBlkFeature::SEG_MAX::SET
-        + VirtioBlkFeature::GEOMETRY::SET
-        + VirtioBlkFeature::BLK_SIZE::SET
-        + VirtioBlkFeature::TOPOLOGY::SET,
-    );
-    mmio.DriverFeaturesSel.set(1);
-    mmio
-      .DriverFeatures
-      .write(VirtioBlkFeature::HIGH_VERSION_1::SET);
-
-    status.modify(VirtioConfigStatus::DRIVER_OK::SET);
-    mmio.Status.set(status.get());
-
-    status.modify(VirtioConfigStatus::FEATURES_OK::SET);
-    mmio.Status.set(status.get());
+    let block_size = if features & VIRTIO_BLK_F_BLK_SIZE != 0 {
+      transport.config::<u32>(CONFIG_BLK_SIZE) as usize
+    } else {
+      SECTOR_SIZE
+    };
+    // Note: the 64-bit capacity is read as two halves, the device may not allow wider accesses
+    let capacity = transport.config::<u32>(CONFIG_CAPACITY) as usize
+      | (transport.config::<u32>(CONFIG_CAPACITY + 4) as usize) << 32;
+    let (max_discard, discard_alignment) = if features & VIRTIO_BLK_F_DISCARD != 0 {
+      (
+        transport.config::<u32>(CONFIG_MAX_DISCARD_SECTORS) as usize,
+        (transport.config::<u32>(CONFIG_DISCARD_SECTOR_ALIGNMENT) as usize).max(1),
+      )
+    } else {
+      (0, 1)
+    };
+    virtio::driver_ok(transport.as_ref());
+    Ok(VirtioBlk {
+      transport,
+      pending: (0..queue_num).map(|_| (0..QUEUE_SIZE).map(|_| None).collect()).collect(),
+      queues,
+      capacity,
+      block_size,
+      max_discard,
+      discard_alignment,
+      features,
+    })
+  }
 
-    self.setup_queue(0);
-    trace!(
-      "probe disk size lo{} / hi{} sectors",
-      mmio.CapacityLow.get(),
-      mmio.CapacityHigh.get()
-    );
-    let size = ((mmio.CapacityHigh.get() as usize) << 32) | mmio.CapacityLow.get() as usize;
-    self.size_in_sector = size;
+  fn has(&self, feature: u64) -> bool {
+    self.features & feature != 0
   }
 
-  fn setup_queue(&self, idx: u32) {
-    let mmio = &self.mmio;
-    mmio.QueueSel.set(idx);
-    let num = mmio.QueueNumMax.get();
-    if num == 0 {
-      panic!("queue num max is zero");
+  fn info(&self) -> usize {
+    let mut flags = 0;
+    if self.has(VIRTIO_BLK_F_RO) {
+      flags |= info::READ_ONLY;
     }
-    if num < DRIVER_QUEUE_SIZE as u32 {
-      panic!("queue size not supported");
+    if self.has(VIRTIO_BLK_F_FLUSH) {
+      flags |= info::FLUSH;
     }
-    mmio.QueueNum.set(DRIVER_QUEUE_SIZE as u32);
-
-    let ring = &self.mutable.ring;
-
-    mmio
-      .QueueDescLow
-      .set(virt_to_phys(ring.desc.base_addr_usize()) as u32);
-    mmio
-      .QueueDescHigh
-      .set((virt_to_phys(ring.desc.base_addr_usize()) >> 32) as u32);
-    mmio
-      .QueueDriverLow
-      .set(virt_to_phys(ring.driver.base_addr_usize()) as u32);
-    mmio
-      .QueueDriverHigh
-      .set((virt_to_phys(ring.driver.base_addr_usize()) >> 32) as u32);
-    mmio
-      .QueueDeviceLow
-      .set(virt_to_phys(ring.device.base_addr_usize()) as u32);
-    mmio
-      .QueueDeviceHigh
-      .set((virt_to_phys(ring.device.base_addr_usize()) >> 32) as u32);
-
-    mmio.QueueReady.set(1);
+    if self.has(VIRTIO_BLK_F_DISCARD) {
+      flags |= info::DISCARD;
+    }
+    flags
   }
 
-  fn submit_io(
+  /// Queue a request, its completion is replied to `client` by `complete`
+  ///
+  /// `buffer` is taken along once the request is queued.
+  fn submit(
     &mut self,
+    op: Operation,
     sector: usize,
     count: usize,
-    buf: usize,
-    op: Operation,
-    tid: usize,
-  ) -> Result<(), &'static str> {
-    if sector >= self.size_in_sector || sector + count >= self.size_in_sector {
-      return Err("invalid sector");
+    buffer: &mut Option<grant::Buffer>,
+    client: usize,
+  ) -> Submitted {
+    let buf = buffer.as_ref().map_or(0, grant::Buffer::addr);
+    match op {
+      Operation::Read | Operation::Write | Operation::Discard => {
+        if count == 0 || !matches!(sector.checked_add(count), Some(end) if end <= self.capacity) {
+          return Submitted::Reply(result::INVARG);
+        }
+      }
+      Operation::Flush => {}
     }
-    let hdr = Box::new(VirtioBlkOutHdr {
-      t: match op {
-        Operation::Read => 0,
-        Operation::Write => 1,
-      },
-      priority: 0,
-      sector: sector as u64,
-    });
-    let status = Box::new(255u8);
-    let mutable = &mut self.mutable;
-    if let Some((ia, ib, ic)) = mutable.alloc_desc() {
-      mutable.ring.desc[ia].addr = virt_to_phys(hdr.as_ref() as *const _ as usize) as u64;
-      mutable.ring.desc[ia].len = size_of::<VirtioBlkOutHdr>() as u32;
-      mutable.ring.desc[ia].flags = VRING_DESC_F_NEXT;
-      mutable.ring.desc[ia].next = ib as u16;
-
-      mutable.ring.desc[ib].addr = virt_to_phys(buf) as u64;
-      mutable.ring.desc[ib].len = (512 * count) as u32;
-      mutable.ring.desc[ib].flags = match op {
-        Operation::Read => VRING_DESC_F_WRITE,
-        Operation::Write => 0,
-      } | VRING_DESC_F_NEXT;
-      mutable.ring.desc[ib].next = ic as u16;
-
-      mutable.ring.desc[ic].addr = virt_to_phys(status.as_ref() as *const u8 as usize) as u64;
-      mutable.ring.desc[ic].len = 1;
-      mutable.ring.desc[ic].flags = VRING_DESC_F_WRITE;
-      mutable.ring.desc[ic].next = 0;
-
-      let avail_idx = mutable.ring.driver.idx;
-      mutable.ring.driver.ring[(avail_idx as usize) % DRIVER_QUEUE_SIZE] = ia as u16;
-
-      mutable.queue[ia] = Some(DiskRequest {
-        sector,
-        count,
-        buf,
-        imp: hdr,
-        status,
-        src: tid,
-      });
-      let last_avail_idx = mutable.ring.driver.idx;
-      mutable.ring.driver.idx = last_avail_idx.wrapping_add(1);
-
-      let mmio = &self.mmio;
-      mmio.QueueNotify.set(0); // queue num #0
-    } else {
-      return Err("queue full. no desc available");
+    match op {
+      Operation::Write | Operation::Discard if self.has(VIRTIO_BLK_F_RO) => {
+        return Submitted::Reply(result::READ_ONLY)
+      }
+      Operation::Discard if !self.has(VIRTIO_BLK_F_DISCARD) => return Submitted::Reply(result::UNSUPPORTED),
+      // Note: one range per request, a discard past the limits of the device is left to the client to split
+      Operation::Discard
+        if count > self.max_discard
+          || count > u32::MAX as usize
+          || !sector.is_multiple_of(self.discard_alignment)
+          || !count.is_multiple_of(self.discard_alignment) =>
+      {
+        return Submitted::Reply(result::INVARG)
+      }
+      // Note: without the feature the device has no volatile write cache, writes are already durable
+      Operation::Flush if !self.has(VIRTIO_BLK_F_FLUSH) => return Submitted::Reply(result::OK),
+      _ => {}
     }
 
-    Ok(())
-  }
+    let mut block = Box::new(RequestBlock {
+      header: Header {
+        t: match op {
+          Operation::Read => VIRTIO_BLK_T_IN,
+          Operation::Write => VIRTIO_BLK_T_OUT,
+          Operation::Flush => VIRTIO_BLK_T_FLUSH,
+          Operation::Discard => VIRTIO_BLK_T_DISCARD,
+        },
+        reserved: 0,
+        sector: match op {
+          Operation::Read | Operation::Write => sector as u64,
+          _ => 0,
+        },
+      },
+      discard: DiscardRange {
+        sector: sector as u64,
+        num_sectors: count as u32,
+        flags: 0,
+      },
+      status: 0xff,
+    });
 
-  fn complete_irq(&mut self) {
-    const VIRTIO_BLK_S_OK: u8 = 0;
-    const VIRTIO_BLK_S_IOERR: u8 = 1;
-    const VIRTIO_BLK_S_UNSUPP: u8 = 2;
-    let status = self.mmio.InterruptStatus.get();
-    if status & 0b01 != 0 {
-      // Used Buffer Notification: the device has used a buffer in at least one of the active virtual queues.
-      loop {
-        let mutable = &mut self.mutable;
-        if mutable.last_used == mutable.ring.device.idx {
-          break;
+    let mut chain = Vec::with_capacity(SEGMENT_MAX + 2);
+    chain.push((virt_to_phys(&block.header as *const Header as usize), size_of::<Header>(), false));
+    match op {
+      Operation::Read | Operation::Write => {
+        // Note: the buffer is virtually contiguous only, each page is a segment of its own
+        let end = buf + count * SECTOR_SIZE;
+        let mut va = buf;
+        while va < end {
+          let len = (PAGE_SIZE - va % PAGE_SIZE).min(end - va);
+          chain.push((virt_to_phys(va), len, matches!(op, Operation::Read)));
+          va += len;
         }
-        let comp_head =
-          mutable.ring.device.ring[(mutable.last_used as usize) % DRIVER_QUEUE_SIZE].id as usize;
-        if let Some(req) = mutable.queue[comp_head].take() {
-          match *req.status {
-            VIRTIO_BLK_S_OK => {}
-            VIRTIO_BLK_S_IOERR => {
-              error!("irq status io err {:#x?}", req);
-            }
-            VIRTIO_BLK_S_UNSUPP => {
-              error!("irq status unsupported {:#x?}", req);
-            }
-            x => {
-              error!("irq unknown status {}", x);
-            }
-          }
-          let mut msg = rpsyscall::message::Message::default();
-          msg.a = *req.status as usize;
-          let _ = msg.send_to(req.src);
-          mutable.free_desc(comp_head);
-        } else {
-          error!("head desc doesn't have corresponding DiskRequest");
+        if chain.len() > SEGMENT_MAX + 1 {
+          return Submitted::Reply(result::INVARG);
         }
-        mutable.last_used = mutable.last_used.wrapping_add(1);
       }
+      Operation::Discard => {
+        chain.push((virt_to_phys(&block.discard as *const DiscardRange as usize), size_of::<DiscardRange>(), false));
+      }
+      Operation::Flush => {}
     }
-    if status & 0b10 != 0 {
-      // Configuration Change Notification
-      error!("irq Configuration Change Notification not handled!");
-    }
-    self.mmio.InterruptACK.set(status);
-  }
-
-  // fn irq_thread(&mut self) {
-  //   loop {
-  //     self.poll_irq();
-  //   }
-  // }
-
-  fn poll_irq(&mut self) {
-    let _ = rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, self.irq);
-    self.complete_irq();
+    chain.push((virt_to_phys(&mut block.status as *mut u8 as usize), 1, true));
+
+    // spread clients over the queues
+    let queue = client % self.queues.len();
+    let head = match self.queues[queue].push(&chain) {
+      Some(head) => head,
+      None => return Submitted::Full,
+    };
+    self.pending[queue][head as usize] = Some(Request { block, client, _buffer: buffer.take() });
+    self.queues[queue].notify(self.transport.as_ref());
+    Submitted::Queued
   }
-}
 
-impl VirtioBlkInnerMut {
-  fn alloc_desc(&mut self) -> Option<(usize, usize, usize)> {
-    for i in 0..DRIVER_QUEUE_SIZE {
-      if self.desc_free[i] {
-        for j in (i + 1)..DRIVER_QUEUE_SIZE {
-          if self.desc_free[j] {
-            for k in (j + 1)..DRIVER_QUEUE_SIZE {
-              if self.desc_free[k] {
-                self.desc_free[i] = false;
-                self.desc_free[j] = false;
-                self.desc_free[k] = false;
-                return Some((i, j, k));
-              }
+  /// Reply to the clients of every finished request
+  fn complete(&mut self) {
+    for (queue, pending) in self.queues.iter_mut().zip(self.pending.iter_mut()) {
+      while let Some((head, _)) = queue.pop_used() {
+        let request = match pending[head as usize].take() {
+          Some(request) => request,
+          None => {
+            error!("head desc {} doesn't have corresponding request", head);
+            continue;
+          }
+        };
+        let r = match request.block.status {
+          VIRTIO_BLK_S_OK => result::OK,
+          VIRTIO_BLK_S_UNSUPP => result::UNSUPPORTED,
+          status => {
+            if status != VIRTIO_BLK_S_IOERR {
+              error!("unknown status {}", status);
             }
+            error!("request type {} sector {} failed", request.block.header.t, request.block.header.sector);
+            result::ERR
           }
-        }
+        };
+        let _ = Message::new(r, 0, 0, 0).send_to(request.client);
       }
     }
-    None
   }
+}
 
-  fn free_desc(&mut self, head_idx: usize) {
-    let a = &self.ring.desc[head_idx];
-    let i = head_idx;
-    if a.flags & VRING_DESC_F_NEXT != 0 {
-      let j = a.next as usize;
-      let b = &self.ring.desc[j];
-      if b.flags & VRING_DESC_F_NEXT != 0 {
-        let k = b.next as usize;
-        let c = &self.ring.desc[k];
-        assert_eq!(c.flags & VRING_DESC_F_NEXT, 0);
-        self.desc_free[i] = true;
-        self.desc_free[j] = true;
-        self.desc_free[k] = true;
-        return;
+fn requests(blk: &Mutex<VirtioBlk>, name: &str) {
+  registry::attach(name);
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let op = match msg.d {
+      action::READ => Operation::Read,
+      action::WRITE => Operation::Write,
+      action::FLUSH => Operation::Flush,
+      action::DISCARD => Operation::Discard,
+      action::SIZE => {
+        let blk = blk.lock();
+        let _ = Message::new(blk.capacity * SECTOR_SIZE, 0, 0, 0).send_to(client_tid);
+        continue;
+      }
+      action::SHARE | action::UNSHARE => {
+        let _ = Message::new(grant::request(client_tid, &msg), 0, 0, 0).send_to(client_tid);
+        continue;
+      }
+      action::INFO => {
+        let blk = blk.lock();
+        let _ = Message::new(result::OK, blk.capacity, blk.block_size, blk.info()).send_to(client_tid);
+        continue;
+      }
+      _ => {
+        error!("unknown action {}", msg.d);
+        let _ = Message::new(result::INVARG, 0, 0, 0).send_to(client_tid);
+        continue;
+      }
+    };
+    let mut buffer = match op {
+      Operation::Read | Operation::Write => match grant::buffer(client_tid, &msg) {
+        Some(buffer) => Some(buffer),
+        None => {
+          let _ = Message::new(result::INVARG, 0, 0, 0).send_to(client_tid);
+          continue;
+        }
+      },
+      _ => None,
+    };
+    loop {
+      let submitted = blk.lock().submit(op, msg.a, msg.b, &mut buffer, client_tid);
+      match submitted {
+        Submitted::Queued => break,
+        Submitted::Reply(r) => {
+          let _ = Message::new(r, 0, 0, 0).send_to(client_tid);
+          break;
+        }
+        // Note: descriptors are freed by the interrupt thread
+        Submitted::Full => rpsyscall::thread_yield(),
       }
     }
-    panic!("inconsistent desc usage");
   }
 }
 
-#[repr(C)]
-#[repr(align(4096))]
-#[derive(Debug)]
-struct VirtioRing {
-  desc: [VirtioRingDesc; DRIVER_QUEUE_SIZE],
-  driver: VirtioRingDriver,
-  device: VirtioRingDevice,
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-struct VirtioRingDesc {
-  addr: u64,
-  len: u32,
-  flags: u16,
-  next: u16,
-}
-
-#[repr(C)]
-#[derive(Debug)]
-struct VirtioRingDriver {
-  flags: u16,
-  idx: u16,
-  ring: [u16; DRIVER_QUEUE_SIZE],
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-struct VirtioRingDeviceElement {
-  id: u32,
-  len: u32,
-}
-
-#[repr(C)]
-#[repr(align(4096))]
-#[derive(Debug)]
-struct VirtioRingDevice {
-  flags: u16,
-  idx: u16,
-  ring: [VirtioRingDeviceElement; DRIVER_QUEUE_SIZE],
-}
-
-#[repr(C)]
-#[derive(Debug)]
-pub struct VirtioBlkOutHdr {
-  t: u32,
-  priority: u32,
-  sector: u64,
-}
-
-#[derive(Debug, Copy, Clone)]
-pub enum Operation {
-  Read,
-  Write,
-}
-
-#[derive(Debug)]
-#[allow(dead_code)]
-pub struct DiskRequest {
-  sector: usize,
-  count: usize,
-  buf: usize,
-  imp: Box<VirtioBlkOutHdr>,
-  status: Box<u8>,
-  src: usize,
-}
-const NONE_DISK_REQUEST: Option<DiskRequest> = None;
-
-// static VIRTIO_BLK: Once<VirtioBlk> = Once::new();
-
-pub fn server(base_addr: usize, irq_num: usize) {
+/// Block server of a virtio block device, registered as `name`
+///
+/// Requests are submitted by another thread as soon as they arrive and replied once the device completes
+/// them, the calling thread services device interrupts. Buffers of other address spaces are to be shared first.
+pub fn server(dev: Device<'static>, name: String) {
   info!("server started t{}", get_tid());
-  let base_addr = base_addr + rpabi::platform::USER_SPACE_DRIVER_MMIO_OFFSET;
-  rpsyscall::server_register(rpabi::server::SERVER_BLK).unwrap();
-  // VIRTIO_BLK.call_once(|| {
-  //   let virtio_blk = VirtioBlk::new(base_addr, irq_num);
-  //   virtio_blk.init();
-  //   virtio_blk
-  // });
-
-  let mut virtio_blk = VirtioBlk::new(base_addr, irq_num);
-  virtio_blk.init();
-
-  // let irq_thread = crate::common::thread::spawn(|| {
-  //   VIRTIO_BLK.get().unwrap().irq_thread();
-  // });
+  let blk = match crate::common::virtio::transport(&dev).and_then(VirtioBlk::new) {
+    Ok(blk) => blk,
+    Err(e) => {
+      error!("{} {}", dev.name, e);
+      registry::remove(&name);
+      return;
+    }
+  };
+  info!(
+    "{} {}: {} sectors, block size {}, {} queues, info {:#x}",
+    name,
+    dev.name,
+    blk.capacity,
+    blk.block_size,
+    blk.queues.len(),
+    blk.info()
+  );
+  let blk = Arc::new(Mutex::new(blk));
+  let requests_blk = blk.clone();
+  let _requests = thread::spawn(move || requests(&requests_blk, &name));
+
+  let irq = dev.interrupt();
   loop {
-    let (client_tid, msg) = rpsyscall::message::Message::receive().unwrap();
-    // let virtio_blk = VIRTIO_BLK.get().unwrap();
-    match msg.d {
-      rpservapi::blk::action::READ | rpservapi::blk::action::WRITE => {
-        let sector = msg.a;
-        let count = msg.b;
-        let buf = msg.c;
-        let op = if msg.d == rpservapi::blk::action::READ {
-          Operation::Read
-        } else {
-          Operation::Write
-        };
-        match virtio_blk.submit_io(sector, count, buf, op, client_tid) {
-          Ok(_) => {
-            // will reply in irq thread
-            virtio_blk.poll_irq();
-          }
-          Err(_) => {
-            let mut msg = rpsyscall::message::Message::default();
-            msg.a = 0xff; // submit error
-            let _ = msg.send_to(client_tid);
-          }
-        }
-      }
-      rpservapi::blk::action::SIZE => {
-        let mut msg = rpsyscall::message::Message::default();
-        msg.a = virtio_blk.size_in_sector * 512;
-        let _ = msg.send_to(client_tid);
+    match irq {
+      Some(irq) if rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, irq).is_ok() => {
+        let mut blk = blk.lock();
+        blk.transport.interrupt_ack();
+        blk.complete();
+        drop(blk);
+        let _ = rpsyscall::irq_ack(irq);
       }
       _ => {
-        error!("unknown action {}", msg.d);
+        blk.lock().complete();
+        rpsyscall::thread_yield();
       }
     }
   }
-  // let _ = irq_thread.join();
 }
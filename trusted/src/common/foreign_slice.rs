@@ -1,6 +1,6 @@
-use rpabi::PAGE_SIZE;
+use rpabi::{CONFIG_USER_LIMIT, PAGE_SIZE};
 
-use rpsyscall::mem_map;
+use rpsyscall::{get_asid, mem_map};
 use crate::common::loader::round_up;
 use crate::common::loader::round_down;
 use crate::common::mm::{default_page_attribute, virtual_alloc, virtual_free};
@@ -14,27 +14,33 @@ pub struct ForeignSlice {
   local_buf: usize,
 }
 
+// Note: local virtual space is not reused, the bound keeps clients from exhausting it
+const SLICE_LEN_MAX: usize = 16 * 1024 * 1024;
+
 impl ForeignSlice {
   pub fn new(asid: u16, slice_start: usize, slice_len: usize) -> Result<Self, isize> {
-    let page_num = (round_up(slice_start + slice_len, PAGE_SIZE)
-      - round_down(slice_start, PAGE_SIZE)) / PAGE_SIZE;
-    let local_buf = virtual_alloc(page_num, false).unwrap() as usize;
-    let local_start = slice_start - round_down(slice_start, PAGE_SIZE) + local_buf;
-
-    for i in 0..page_num {
-      let src_va = round_down(slice_start, PAGE_SIZE) + i * PAGE_SIZE;
-      let dst_va = local_buf + i * PAGE_SIZE;
-      mem_map(asid, src_va, 0, dst_va, default_page_attribute()).map_err(|_e| -1isize)?;
+    match slice_start.checked_add(slice_len) {
+      Some(end) if slice_len <= SLICE_LEN_MAX && end <= CONFIG_USER_LIMIT => {}
+      _ => return Err(-1),
     }
-
-    Ok(ForeignSlice {
+    let start = round_down(slice_start, PAGE_SIZE);
+    let page_num = (round_up(slice_start + slice_len, PAGE_SIZE) - start) / PAGE_SIZE;
+    let local_buf = virtual_alloc(page_num, false).ok_or(-1isize)?;
+    // Note: built before mapping, so that dropping it on failure unmaps the pages mapped so far
+    let slice = ForeignSlice {
       asid,
       slice_start,
       slice_len,
-      local_start,
+      local_start: slice_start - start + local_buf,
       page_num,
       local_buf,
-    })
+    };
+    for i in 0..page_num {
+      let src_va = start + i * PAGE_SIZE;
+      let dst_va = local_buf + i * PAGE_SIZE;
+      mem_map(asid, src_va, 0, dst_va, default_page_attribute()).map_err(|_e| -1isize)?;
+    }
+    Ok(slice)
   }
 
   pub fn local_slice(&self) -> &[u8] {
@@ -56,3 +62,16 @@ impl Drop for ForeignSlice {
     virtual_free(self.local_buf, self.page_num);
   }
 }
+
+/// Run `f` on a buffer of a client, mapped into this address space unless the client shares it
+pub fn with_client_buffer<R, F: FnOnce(&mut [u8]) -> R>(asid: u16, addr: usize, len: usize, f: F) -> Option<R> {
+  if len == 0 {
+    return Some(f(&mut []));
+  }
+  addr.checked_add(len)?;
+  if asid == get_asid(0).ok()? {
+    return Some(f(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) }));
+  }
+  let slice = ForeignSlice::new(asid, addr, len).ok()?;
+  Some(f(unsafe { core::slice::from_raw_parts_mut(slice.local_start as *mut u8, len) }))
+}
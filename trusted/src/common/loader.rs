@@ -6,6 +6,9 @@ use crate::fs::client::SeekFrom;
 
 use crate::common::mm::{default_page_attribute, virtual_alloc, virtual_free};
 
+// page budget of a spawned program (64 MB), so it cannot starve trusted servers
+const PROCESS_PAGE_LIMIT: usize = 16384;
+
 #[inline(always)]
 pub fn round_up(addr: usize, n: usize) -> usize {
   (addr + n - 1) & !(n - 1)
@@ -20,6 +23,7 @@ pub fn spawn<P: AsRef<str>>(cmd: P) -> Result<(u16, usize), &'static str> {
   let mut iter = cmd.as_ref().trim().split_ascii_whitespace();
   if let Some(bin) = iter.next() {
     let asid = rpsyscall::address_space_alloc().map_err(|_e| "address_space_alloc failed")?;
+    rpsyscall::set_page_limit(asid, PROCESS_PAGE_LIMIT).map_err(|_e| "set_page_limit failed")?;
     let mut f = File::open(bin).map_err(|e| {
       error!("spawn open file \"{bin}\" failed");
       e.text()
@@ -105,6 +109,9 @@ pub fn spawn<P: AsRef<str>>(cmd: P) -> Result<(u16, usize), &'static str> {
     }
     virtual_free(va_tmp, 1);
 
+    // stack growth is left to mm server
+    rpsyscall::set_pager(asid, rpsyscall::server_tid_wait(rpabi::server::SERVER_MM)).map_err(|_e| "set_pager failed")?;
+
     let tid = rpsyscall::thread_alloc(asid, entry_point, rpabi::CONFIG_USER_STACK_TOP - round_up(index, 16), rpabi::CONFIG_USER_STACK_TOP - index).map_err(|_e| "thread alloc failed")?;
     trace!("spawn asid {} tid {}", asid, tid);
 
@@ -20,3 +20,4 @@ pub mod loader;
 pub mod foreign_slice;
 pub mod wrapper;
 pub mod exception;
+pub mod virtio;
@@ -0,0 +1,85 @@
+use hardware::virtio_mmio::VirtioMmio;
+use tock_registers::interfaces::{Readable, Writeable};
+
+use super::Transport;
+
+const MMIO_MAGIC: u32 = 0x74726976;
+const MMIO_VERSION_MODERN: u32 = 2;
+
+pub struct MmioTransport {
+  mmio: VirtioMmio,
+}
+
+impl MmioTransport {
+  pub fn new(base_addr: usize) -> Result<Self, &'static str> {
+    let mmio = VirtioMmio::new(base_addr);
+    if mmio.MagicValue.get() != MMIO_MAGIC {
+      return Err("bad virtio-mmio magic");
+    }
+    if mmio.Version.get() != MMIO_VERSION_MODERN {
+      return Err("legacy virtio-mmio not supported");
+    }
+    Ok(MmioTransport { mmio })
+  }
+}
+
+impl Transport for MmioTransport {
+  fn device_id(&self) -> u32 {
+    self.mmio.DeviceID.get()
+  }
+
+  fn status(&self) -> u8 {
+    self.mmio.Status.get() as u8
+  }
+
+  fn set_status(&self, status: u8) {
+    self.mmio.Status.set(status as u32);
+  }
+
+  fn device_features(&self) -> u64 {
+    self.mmio.DeviceFeaturesSel.set(0);
+    let low = self.mmio.DeviceFeatures.get() as u64;
+    self.mmio.DeviceFeaturesSel.set(1);
+    let high = self.mmio.DeviceFeatures.get() as u64;
+    high << 32 | low
+  }
+
+  fn set_driver_features(&self, features: u64) {
+    self.mmio.DriverFeaturesSel.set(0);
+    self.mmio.DriverFeatures.set(features as u32);
+    self.mmio.DriverFeaturesSel.set(1);
+    self.mmio.DriverFeatures.set((features >> 32) as u32);
+  }
+
+  fn queue_size_max(&self, queue: u16) -> u16 {
+    self.mmio.QueueSel.set(queue as u32);
+    self.mmio.QueueNumMax.get().min(u16::MAX as u32) as u16
+  }
+
+  fn queue_setup(&self, queue: u16, size: u16, desc: usize, driver: usize, device: usize) {
+    let mmio = &self.mmio;
+    mmio.QueueSel.set(queue as u32);
+    mmio.QueueNum.set(size as u32);
+    mmio.QueueDescLow.set(desc as u32);
+    mmio.QueueDescHigh.set((desc >> 32) as u32);
+    mmio.QueueDriverLow.set(driver as u32);
+    mmio.QueueDriverHigh.set((driver >> 32) as u32);
+    mmio.QueueDeviceLow.set(device as u32);
+    mmio.QueueDeviceHigh.set((device >> 32) as u32);
+    mmio.QueueReady.set(1);
+  }
+
+  fn notify(&self, queue: u16) {
+    self.mmio.QueueNotify.set(queue as u32);
+  }
+
+  fn interrupt_ack(&self) -> u32 {
+    let status = self.mmio.InterruptStatus.get();
+    self.mmio.InterruptACK.set(status);
+    status
+  }
+
+  fn config_addr(&self) -> usize {
+    self.mmio.config_addr()
+  }
+}
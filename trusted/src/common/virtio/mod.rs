@@ -0,0 +1,82 @@
+//! Transport-independent parts of virtio drivers: feature negotiation and split virtqueues
+//!
+//! Devices are reached either through virtio-mmio or through the modern virtio-pci transport.
+
+use alloc::boxed::Box;
+
+use rpabi::platform::Device;
+
+mod mmio;
+mod pci;
+mod queue;
+
+pub use mmio::MmioTransport;
+pub use pci::PciTransport;
+pub use queue::{BufferedQueue, VirtQueue};
+
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FEATURES_OK: u8 = 8;
+pub const STATUS_FAILED: u8 = 0x80;
+
+pub const F_VERSION_1: u64 = 1 << 32;
+
+pub trait Transport: Send + Sync {
+  /// Virtio device id, e.g., 1 for network card
+  fn device_id(&self) -> u32;
+  fn status(&self) -> u8;
+  fn set_status(&self, status: u8);
+  fn device_features(&self) -> u64;
+  fn set_driver_features(&self, features: u64);
+  fn queue_size_max(&self, queue: u16) -> u16;
+  /// Hand the physical addresses of the three parts of a split virtqueue to the device and enable it
+  fn queue_setup(&self, queue: u16, size: u16, desc: usize, driver: usize, device: usize);
+  fn notify(&self, queue: u16);
+  /// Read and clear pending interrupt causes, bit 0 for used buffers and bit 1 for configuration change
+  fn interrupt_ack(&self) -> u32;
+  /// Virtual address of the device-specific configuration space
+  fn config_addr(&self) -> usize;
+}
+
+impl dyn Transport {
+  pub fn config<T: Copy>(&self, offset: usize) -> T {
+    unsafe { ((self.config_addr() + offset) as *const T).read_volatile() }
+  }
+}
+
+/// Transport of a virtio device reported in platform info
+pub fn transport(dev: &Device) -> Result<Box<dyn Transport>, &'static str> {
+  if dev.pci.is_some() {
+    Ok(Box::new(PciTransport::new(dev)?))
+  } else {
+    let base = dev.register().ok_or("device has no register")?.start;
+    Ok(Box::new(MmioTransport::new(base + rpabi::platform::USER_SPACE_DRIVER_MMIO_OFFSET)?))
+  }
+}
+
+/// Reset the device and accept the subset of `features` it offers
+///
+/// Only modern devices (`F_VERSION_1`) are driven. Returns the negotiated features.
+pub fn negotiate(transport: &dyn Transport, features: u64) -> Result<u64, &'static str> {
+  transport.set_status(0);
+  transport.set_status(STATUS_ACKNOWLEDGE);
+  transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+  let features = transport.device_features() & (features | F_VERSION_1);
+  if features & F_VERSION_1 == 0 {
+    transport.set_status(STATUS_FAILED);
+    return Err("legacy device not supported");
+  }
+  transport.set_driver_features(features);
+  transport.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+  if transport.status() & STATUS_FEATURES_OK == 0 {
+    transport.set_status(STATUS_FAILED);
+    return Err("features rejected by device");
+  }
+  Ok(features)
+}
+
+/// Tell the device the driver is ready, after its queues are set up
+pub fn driver_ok(transport: &dyn Transport) {
+  transport.set_status(transport.status() | STATUS_DRIVER_OK);
+}
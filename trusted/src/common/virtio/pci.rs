@@ -0,0 +1,145 @@
+use hardware::virtio_pci::{cap, VirtioPciCommonCfg};
+use rpabi::platform::{Device, USER_SPACE_DRIVER_MMIO_OFFSET};
+use tock_registers::interfaces::{Readable, Writeable};
+
+use super::Transport;
+
+const PCI_STATUS: usize = 0x06;
+const PCI_SUBSYSTEM_ID: usize = 0x2e;
+const PCI_CAPABILITY_LIST: usize = 0x34;
+const PCI_STATUS_CAP_LIST: u16 = 1 << 4;
+
+// modern devices use id 0x1040 + virtio device id, transitional ones keep it in subsystem id
+const PCI_DEVICE_ID_MODERN_BASE: u16 = 0x1040;
+
+/// Modern virtio-pci transport, located by the vendor capabilities of the function
+///
+/// The configuration space and memory BARs are mapped by the kernel at `USER_SPACE_DRIVER_MMIO_OFFSET + pa`.
+pub struct PciTransport {
+  device_id: u32,
+  common: VirtioPciCommonCfg,
+  notify: usize,
+  notify_off_multiplier: u32,
+  isr: usize,
+  device: usize,
+}
+
+struct Config(usize);
+
+impl Config {
+  fn read<T: Copy>(&self, offset: usize) -> T {
+    unsafe { ((self.0 + offset) as *const T).read_volatile() }
+  }
+}
+
+impl PciTransport {
+  pub fn new(dev: &Device) -> Result<Self, &'static str> {
+    let pci = dev.pci.as_ref().ok_or("not a pci function")?;
+    let config = Config(USER_SPACE_DRIVER_MMIO_OFFSET + dev.register().ok_or("no configuration space")?.start);
+    if config.read::<u16>(PCI_STATUS) & PCI_STATUS_CAP_LIST == 0 {
+      return Err("no capability list");
+    }
+    let mut common = None;
+    let mut notify = None;
+    let mut isr = None;
+    let mut device = None;
+    let mut ptr = (config.read::<u8>(PCI_CAPABILITY_LIST) & !0b11) as usize;
+    while ptr != 0 {
+      if config.read::<u8>(ptr) == cap::VENDOR_ID {
+        let bar = config.read::<u8>(ptr + cap::OFFSET_BAR) as usize;
+        let offset = config.read::<u32>(ptr + cap::OFFSET_OFFSET) as usize;
+        // Note: the first capability of each type is preferred, as the specification suggests
+        if let Some(range) = pci.bar(bar) {
+          let va = USER_SPACE_DRIVER_MMIO_OFFSET + range.start + offset;
+          match config.read::<u8>(ptr + cap::OFFSET_CFG_TYPE) {
+            cap::COMMON_CFG => { common.get_or_insert(va); }
+            cap::NOTIFY_CFG => {
+              notify.get_or_insert((va, config.read::<u32>(ptr + cap::OFFSET_NOTIFY_OFF_MULTIPLIER)));
+            }
+            cap::ISR_CFG => { isr.get_or_insert(va); }
+            cap::DEVICE_CFG => { device.get_or_insert(va); }
+            _ => {}
+          }
+        }
+      }
+      ptr = (config.read::<u8>(ptr + 1) & !0b11) as usize;
+    }
+    let (notify, notify_off_multiplier) = notify.ok_or("no notify capability")?;
+    let device_id = if pci.device_id >= PCI_DEVICE_ID_MODERN_BASE {
+      pci.device_id - PCI_DEVICE_ID_MODERN_BASE
+    } else {
+      config.read::<u16>(PCI_SUBSYSTEM_ID)
+    };
+    Ok(PciTransport {
+      device_id: device_id as u32,
+      common: VirtioPciCommonCfg::new(common.ok_or("no common capability")?),
+      notify,
+      notify_off_multiplier,
+      isr: isr.ok_or("no isr capability")?,
+      device: device.unwrap_or(0),
+    })
+  }
+}
+
+impl Transport for PciTransport {
+  fn device_id(&self) -> u32 {
+    self.device_id
+  }
+
+  fn status(&self) -> u8 {
+    self.common.DeviceStatus.get()
+  }
+
+  fn set_status(&self, status: u8) {
+    self.common.DeviceStatus.set(status);
+  }
+
+  fn device_features(&self) -> u64 {
+    self.common.DeviceFeatureSelect.set(0);
+    let low = self.common.DeviceFeature.get() as u64;
+    self.common.DeviceFeatureSelect.set(1);
+    let high = self.common.DeviceFeature.get() as u64;
+    high << 32 | low
+  }
+
+  fn set_driver_features(&self, features: u64) {
+    self.common.DriverFeatureSelect.set(0);
+    self.common.DriverFeature.set(features as u32);
+    self.common.DriverFeatureSelect.set(1);
+    self.common.DriverFeature.set((features >> 32) as u32);
+  }
+
+  fn queue_size_max(&self, queue: u16) -> u16 {
+    self.common.QueueSelect.set(queue);
+    self.common.QueueSize.get()
+  }
+
+  fn queue_setup(&self, queue: u16, size: u16, desc: usize, driver: usize, device: usize) {
+    let common = &self.common;
+    common.QueueSelect.set(queue);
+    common.QueueSize.set(size);
+    common.QueueDescLow.set(desc as u32);
+    common.QueueDescHigh.set((desc >> 32) as u32);
+    common.QueueDriverLow.set(driver as u32);
+    common.QueueDriverHigh.set((driver >> 32) as u32);
+    common.QueueDeviceLow.set(device as u32);
+    common.QueueDeviceHigh.set((device >> 32) as u32);
+    common.QueueEnable.set(1);
+  }
+
+  // Note: queue selection is shared state, a device is driven by one thread at a time
+  fn notify(&self, queue: u16) {
+    self.common.QueueSelect.set(queue);
+    let offset = self.common.QueueNotifyOff.get() as usize * self.notify_off_multiplier as usize;
+    unsafe { ((self.notify + offset) as *mut u16).write_volatile(queue) }
+  }
+
+  fn interrupt_ack(&self) -> u32 {
+    // reading ISR status clears it
+    unsafe { (self.isr as *const u8).read_volatile() as u32 }
+  }
+
+  fn config_addr(&self) -> usize {
+    self.device
+  }
+}
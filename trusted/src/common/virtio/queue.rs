@@ -0,0 +1,235 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+
+use rpabi::PAGE_SIZE;
+
+use crate::common::mm::{virt_to_phys, virtual_alloc};
+
+use super::Transport;
+
+pub const DESC_F_NEXT: u16 = 1;
+pub const DESC_F_WRITE: u16 = 2;
+pub const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct Descriptor {
+  addr: u64,
+  len: u32,
+  flags: u16,
+  next: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct UsedElement {
+  id: u32,
+  len: u32,
+}
+
+// Note: each area fits in one page so that it is physically contiguous, limiting the queue size to 128
+#[repr(C, align(4096))]
+struct DriverArea<const N: usize> {
+  desc: [Descriptor; N],
+  flags: u16,
+  idx: u16,
+  ring: [u16; N],
+}
+
+#[repr(C, align(4096))]
+struct DeviceArea<const N: usize> {
+  flags: u16,
+  idx: u16,
+  ring: [UsedElement; N],
+}
+
+/// Split virtqueue of `N` descriptors
+pub struct VirtQueue<const N: usize> {
+  index: u16,
+  driver: Box<DriverArea<N>>,
+  device: Box<DeviceArea<N>>,
+  free: Vec<u16>,
+  last_used: u16,
+}
+
+impl<const N: usize> VirtQueue<N> {
+  pub fn new(transport: &dyn Transport, index: u16) -> Result<Self, &'static str> {
+    if (transport.queue_size_max(index) as usize) < N {
+      return Err("queue size not supported");
+    }
+    let driver = Box::new(DriverArea {
+      desc: [Descriptor::default(); N],
+      flags: 0,
+      idx: 0,
+      ring: [0; N],
+    });
+    let device = Box::new(DeviceArea {
+      flags: 0,
+      idx: 0,
+      ring: [UsedElement::default(); N],
+    });
+    transport.queue_setup(
+      index,
+      N as u16,
+      virt_to_phys(driver.desc.as_ptr() as usize),
+      virt_to_phys(&driver.flags as *const u16 as usize),
+      virt_to_phys(device.as_ref() as *const DeviceArea<N> as usize),
+    );
+    Ok(VirtQueue {
+      index,
+      driver,
+      device,
+      free: (0..N as u16).rev().collect(),
+      last_used: 0,
+    })
+  }
+
+  /// Chain buffers of (physical address, length, device writable) and make the chain available
+  ///
+  /// Returns the head descriptor id, which `pop_used` reports once the device is done.
+  pub fn push(&mut self, buffers: &[(usize, usize, bool)]) -> Option<u16> {
+    if buffers.is_empty() || buffers.len() > self.free.len() {
+      return None;
+    }
+    let ids: Vec<u16> = (0..buffers.len()).map(|_| self.free.pop().unwrap()).collect();
+    for (i, &(addr, len, writable)) in buffers.iter().enumerate() {
+      let desc = &mut self.driver.desc[ids[i] as usize];
+      desc.addr = addr as u64;
+      desc.len = len as u32;
+      desc.flags = if writable { DESC_F_WRITE } else { 0 };
+      desc.next = 0;
+      if i + 1 < ids.len() {
+        desc.flags |= DESC_F_NEXT;
+        desc.next = ids[i + 1];
+      }
+    }
+    let idx = self.driver.idx;
+    self.driver.ring[idx as usize % N] = ids[0];
+    // descriptors and ring entry must be visible before the index
+    fence(Ordering::SeqCst);
+    self.driver.idx = idx.wrapping_add(1);
+    fence(Ordering::SeqCst);
+    Some(ids[0])
+  }
+
+  /// Ask the device not to interrupt on used buffers, for queues that are polled
+  pub fn suppress_interrupt(&mut self) {
+    self.driver.flags = AVAIL_F_NO_INTERRUPT;
+  }
+
+  pub fn notify(&self, transport: &dyn Transport) {
+    transport.notify(self.index);
+  }
+
+  /// Take the next chain used by the device and free its descriptors
+  ///
+  /// Returns (head descriptor id, bytes written by the device).
+  pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+    let device_idx = unsafe { (&self.device.idx as *const u16).read_volatile() };
+    if self.last_used == device_idx {
+      return None;
+    }
+    fence(Ordering::SeqCst);
+    let elem = unsafe { (&self.device.ring[self.last_used as usize % N] as *const UsedElement).read_volatile() };
+    self.last_used = self.last_used.wrapping_add(1);
+    let head = elem.id as u16;
+    let mut id = head;
+    loop {
+      self.free.push(id);
+      let desc = &self.driver.desc[id as usize];
+      if desc.flags & DESC_F_NEXT == 0 {
+        break;
+      }
+      id = desc.next;
+    }
+    Some((head, elem.len))
+  }
+}
+
+/// Virtqueue of `N` descriptors, each pointing at a buffer of `SIZE` bytes of its own
+///
+/// Data is copied between clients and the buffers, which suits byte streams and packets. A receive queue keeps
+/// every buffer with the device, a transmit queue hands them out until the device is done with them.
+pub struct BufferedQueue<const N: usize, const SIZE: usize> {
+  queue: VirtQueue<N>,
+  // `N` buffers of `SIZE` bytes, `SIZE` is a power of two up to the page size so that none crosses a page
+  buffers: usize,
+  // head descriptor id -> buffer index
+  owner: [usize; N],
+  free: Vec<usize>,
+}
+
+impl<const N: usize, const SIZE: usize> BufferedQueue<N, SIZE> {
+  fn new(transport: &dyn Transport, index: u16) -> Result<Self, &'static str> {
+    if SIZE > PAGE_SIZE || !SIZE.is_power_of_two() {
+      return Err("buffer size not supported");
+    }
+    let queue = VirtQueue::new(transport, index)?;
+    let buffers = virtual_alloc((N * SIZE).div_ceil(PAGE_SIZE), true).ok_or("out of memory")?;
+    Ok(BufferedQueue {
+      queue,
+      buffers,
+      owner: [0; N],
+      free: (0..N).collect(),
+    })
+  }
+
+  /// Receive queue with every buffer made available, the device is to be notified once ready
+  pub fn receiver(transport: &dyn Transport, index: u16) -> Result<Self, &'static str> {
+    let mut queue = Self::new(transport, index)?;
+    while let Some(buffer) = queue.free.pop() {
+      queue.give(buffer, SIZE, true);
+    }
+    Ok(queue)
+  }
+
+  pub fn transmitter(transport: &dyn Transport, index: u16) -> Result<Self, &'static str> {
+    Self::new(transport, index)
+  }
+
+  fn buffer(&mut self, buffer: usize) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut((self.buffers + buffer * SIZE) as *mut u8, SIZE) }
+  }
+
+  fn give(&mut self, buffer: usize, len: usize, writable: bool) {
+    let pa = virt_to_phys(self.buffers + buffer * SIZE);
+    let head = self.queue.push(&[(pa, len, writable)]).unwrap();
+    self.owner[head as usize] = buffer;
+  }
+
+  pub fn notify(&self, transport: &dyn Transport) {
+    self.queue.notify(transport);
+  }
+
+  /// Pass the next received buffer to `f` and make it available again
+  pub fn receive<R, F: FnOnce(&[u8]) -> R>(&mut self, transport: &dyn Transport, f: F) -> Option<R> {
+    let (head, len) = self.queue.pop_used()?;
+    let buffer = self.owner[head as usize];
+    let r = f(&self.buffer(buffer)[..(len as usize).min(SIZE)]);
+    self.give(buffer, SIZE, true);
+    self.queue.notify(transport);
+    Some(r)
+  }
+
+  /// Take back buffers the device has sent, returns whether one is free
+  pub fn transmit_ready(&mut self) -> bool {
+    while let Some((head, _)) = self.queue.pop_used() {
+      self.free.push(self.owner[head as usize]);
+    }
+    !self.free.is_empty()
+  }
+
+  /// Fill the first `len` bytes of a free buffer by `f` and send them, `None` while every buffer is in flight
+  pub fn transmit<R, F: FnOnce(&mut [u8]) -> R>(&mut self, transport: &dyn Transport, len: usize, f: F) -> Option<R> {
+    if !self.transmit_ready() {
+      return None;
+    }
+    let buffer = self.free.pop().unwrap();
+    let len = len.min(SIZE);
+    let r = f(&mut self.buffer(buffer)[..len]);
+    self.give(buffer, len, false);
+    self.queue.notify(transport);
+    Some(r)
+  }
+}
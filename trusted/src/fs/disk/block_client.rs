@@ -0,0 +1,84 @@
+use rpservapi::blk_cache;
+use rpsyscall::message::Message;
+use syscall::{Result, Error, EIO, ENODEV};
+use redoxfs::{BLOCK_SIZE, Disk};
+
+/// A block device opened by name through the `SERVER_BLK` registry, accessed through `SERVER_BLK_CACHE`
+pub struct BlockClient {
+  server: usize,
+}
+
+impl BlockClient {
+  pub fn open(name: &str) -> Result<BlockClient> {
+    loop {
+      let msg = Message {
+        a: name.as_ptr() as usize,
+        b: name.len(),
+        c: 0,
+        d: rpservapi::blk::action::OPEN,
+      }.call(rpabi::server::SERVER_BLK).map_err(|_| Error::new(EIO))?;
+      match msg.a {
+        rpservapi::blk::result::OK => break Ok(BlockClient { server: msg.b }),
+        // Note: the driver is still probing the device
+        rpservapi::blk::result::HOLD_ON => rpsyscall::thread_yield(),
+        _ => break Err(Error::new(ENODEV)),
+      }
+    }
+  }
+}
+
+impl BlockClient {
+  /// Thread serving the device
+  pub fn server(&self) -> usize {
+    self.server
+  }
+}
+
+fn cache_call(server: usize, block: usize, buf: usize, action: usize) -> Result<()> {
+  let msg = Message::new(server, block, buf, action)
+    .call(rpabi::server::SERVER_BLK_CACHE)
+    .map_err(|_| Error::new(EIO))?;
+  if msg.a == blk_cache::result::OK {
+    Ok(())
+  } else {
+    Err(Error::new(EIO))
+  }
+}
+
+/// Write back cached blocks of the device served by `server` and make them durable
+pub fn flush(server: usize) -> Result<()> {
+  cache_call(server, 0, 0, blk_cache::action::FLUSH)
+}
+
+impl Disk for BlockClient {
+  fn read_at(&mut self, block: u64, buffer: &mut [u8]) -> Result<usize> {
+    assert_eq!(buffer.len() % BLOCK_SIZE as usize, 0);
+    for (i, chunk) in buffer.chunks_mut(blk_cache::BLOCK_SIZE).enumerate() {
+      cache_call(self.server, block as usize + i, chunk.as_mut_ptr() as usize, blk_cache::action::READ)?;
+    }
+    Ok(buffer.len())
+  }
+
+  fn write_at(&mut self, block: u64, buffer: &[u8]) -> Result<usize> {
+    assert_eq!(buffer.len() % BLOCK_SIZE as usize, 0);
+    for (i, chunk) in buffer.chunks(blk_cache::BLOCK_SIZE).enumerate() {
+      cache_call(self.server, block as usize + i, chunk.as_ptr() as usize, blk_cache::action::WRITE)?;
+    }
+    Ok(buffer.len())
+  }
+
+  fn size(&mut self) -> Result<u64> {
+    let msg = Message {
+      a: 0,
+      b: 0,
+      c: 0,
+      d: rpservapi::blk::action::SIZE,
+    };
+    let msg = msg.call_tid(self.server).map_err(|_| Error::new(EIO))?;
+    if msg.a == rpservapi::blk::result::OK {
+      Err(Error::new(EIO))
+    } else {
+      Ok(msg.a as u64)
+    }
+  }
+}
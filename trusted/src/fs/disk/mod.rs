@@ -1,2 +1,2 @@
-pub use self::virtio_client::VirtioClient;
-mod virtio_client;
\ No newline at end of file
+pub use self::block_client::{BlockClient, flush};
+mod block_client;
\ No newline at end of file
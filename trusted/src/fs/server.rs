@@ -10,7 +10,7 @@ use rpsyscall::{get_asid, get_tid};
 use rpsyscall::message::Message;
 use syscall::{Packet, Scheme};
 
-use crate::fs::disk::VirtioClient;
+use crate::fs::disk::BlockClient;
 use redoxfs::FileScheme;
 
 #[no_mangle]
@@ -29,6 +29,7 @@ fn redoxfs_proc(msg: Message, client_tid: usize) -> usize {
   packet.b = msg.b;
   packet.c = msg.c;
   packet.d = msg.d;
+  let fsync = packet.a == syscall::SYS_FSYNC;
   let asid = get_asid(client_tid).unwrap();
   if asid == get_asid(0).unwrap() {
     FILE_SCHEME.get().unwrap().handle(&mut packet);
@@ -39,7 +40,10 @@ fn redoxfs_proc(msg: Message, client_tid: usize) -> usize {
       | syscall::SYS_RMDIR
       | syscall::SYS_UNLINK
       => {
-        let s = ForeignSlice::new(asid, packet.b, packet.c).unwrap();
+        let s = match ForeignSlice::new(asid, packet.b, packet.c) {
+          Ok(s) => s,
+          Err(_) => return syscall::Error::mux(Err(syscall::Error::new(syscall::EFAULT))),
+        };
         packet.b = s.local_start;
         Some(s)
       }
@@ -54,7 +58,10 @@ fn redoxfs_proc(msg: Message, client_tid: usize) -> usize {
       | syscall::SYS_FSTATVFS
       | syscall::SYS_FUTIMENS
       => {
-        let s = ForeignSlice::new(asid, packet.c, packet.d).unwrap();
+        let s = match ForeignSlice::new(asid, packet.c, packet.d) {
+          Ok(s) => s,
+          Err(_) => return syscall::Error::mux(Err(syscall::Error::new(syscall::EFAULT))),
+        };
         packet.c = s.local_start;
         Some(s)
       }
@@ -74,15 +81,32 @@ fn redoxfs_proc(msg: Message, client_tid: usize) -> usize {
     FILE_SCHEME.get().unwrap().handle(&mut packet);
     drop(fs);
   }
+  // Note: the file system lies on a single device, its cached blocks are written back
+  if fsync {
+    if let Err(e) = crate::fs::disk::flush(*DEVICE.get().unwrap()) {
+      error!("fsync {}", e);
+      packet.a = syscall::Error::mux(Err(syscall::Error::new(syscall::EIO)));
+    }
+  }
   packet.a
 }
 
-static FILE_SCHEME: Once<FileScheme<VirtioClient>> = Once::new();
+static FILE_SCHEME: Once<FileScheme<BlockClient>> = Once::new();
+// thread serving the device of the file system
+static DEVICE: Once<usize> = Once::new();
 
-pub fn server() {
+/// File system server of the redoxfs on `device`, a block device name, `PARTUUID=uuid` or `PARTLABEL=label`
+pub fn server(device: &str) {
   info!("server started t{}", get_tid());
   rpsyscall::server_register(rpabi::server::SERVER_REDOX_FS).unwrap();
-  let disk = VirtioClient::new();
+  let disk = match BlockClient::open(device) {
+    Ok(disk) => disk,
+    Err(e) => {
+      error!("open {} {:?}", device, e);
+      return;
+    }
+  };
+  DEVICE.call_once(|| disk.server());
   match redoxfs::FileSystem::open(disk, Some(0)) {
     Ok(filesystem) => {
       FILE_SCHEME.call_once(|| { FileScheme::new(String::from("virtio"), filesystem) });
@@ -3,12 +3,29 @@ use crate::common::wrapper::request_wrapper;
 use rpsyscall::get_tid;
 use rpsyscall::message::Message;
 
+// Policy for faults forwarded by kernel (see `rpabi::pager`): grow stack on demand, leave others to the program
+fn fault(asid: u16, va: usize, access: usize) -> usize {
+  use rpabi::pager::*;
+  let on_stack = va > rpabi::CONFIG_USER_STACK_BTM && va < rpabi::CONFIG_USER_STACK_TOP;
+  if on_stack && (access == ACCESS_READ || access == ACCESS_WRITE) {
+    match rpsyscall::mem_alloc(asid, va, default_page_attribute()) {
+      Ok(_) => return REPLY_RETRY,
+      Err(_) => warn!("stack page allocate failed asid {} va {:x}", asid, va),
+    }
+    REPLY_KILL
+  } else {
+    REPLY_UPCALL
+  }
+}
+
 fn mm(msg: Message, tid: usize) -> usize {
   let asid = rpsyscall::get_asid(tid).unwrap();
   match msg.a {
+    rpabi::pager::MESSAGE_FAULT => fault(asid, msg.b, msg.c),
     rpservapi::mm::action::ALLOC => {
       match rpsyscall::mem_alloc(asid, msg.b, default_page_attribute()) {
         Ok(_) => rpservapi::mm::result::OK,
+        Err(rpabi::syscall::error::ERROR_OOM) => rpservapi::mm::result::OOM,
         Err(_) => rpservapi::mm::result::ERR
       }
     }
@@ -0,0 +1,390 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use rpabi::platform::Device;
+use rpservapi::net::{action, endpoint, endpoint_split, kind, result};
+use rpsyscall::message::Message;
+use rpsyscall::{get_asid, get_tid};
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::socket::{tcp, udp};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address};
+use spin::{Mutex, Once};
+
+use crate::common::foreign_slice::with_client_buffer;
+use crate::common::thread;
+
+use super::virtio_net::VirtioNet;
+
+// Note: static configuration matching QEMU user-mode networking
+const ADDRESS: [u8; 4] = [10, 0, 2, 15];
+const PREFIX_LEN: u8 = 24;
+const GATEWAY: [u8; 4] = [10, 0, 2, 2];
+
+const TCP_BUFFER_SIZE: usize = 16384;
+const UDP_BUFFER_SIZE: usize = 16384;
+const UDP_PACKET_NUM: usize = 16;
+const EPHEMERAL_PORT_START: u16 = 49152;
+// upper bound of a buffer mapped from a client per request
+const TRANSFER_MAX: usize = 65536;
+
+struct Socket {
+  asid: u16,
+  kind: usize,
+  handle: SocketHandle,
+  // TCP: connection attempt started; UDP: remote endpoint set by `CONNECT`
+  remote: Option<IpEndpoint>,
+  // TCP: port given to `LISTEN`, kept for the listener taking over on `ACCEPT`
+  port: Option<u16>,
+}
+
+struct Stack {
+  device: VirtioNet,
+  iface: Interface,
+  sockets: SocketSet<'static>,
+  table: BTreeMap<usize, Socket>,
+  // closed by clients, kept until the connection is shut down gracefully
+  closing: Vec<SocketHandle>,
+  next_handle: usize,
+  next_port: u16,
+}
+
+static STACK: Once<Mutex<Stack>> = Once::new();
+
+fn now() -> Instant {
+  let ms = rpsyscall::kernel_stat(rpabi::kstat::ITEM_CLOCK).map_or(0, |clock| clock.0);
+  Instant::from_millis(ms as i64)
+}
+
+// Note: only IPv4 is enabled in smoltcp
+#[allow(unreachable_patterns)]
+fn ipv4(addr: IpAddress) -> [u8; 4] {
+  match addr {
+    IpAddress::Ipv4(addr) => addr.0,
+    _ => [0; 4],
+  }
+}
+
+fn tcp_socket() -> tcp::Socket<'static> {
+  tcp::Socket::new(
+    tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+    tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+  )
+}
+
+fn udp_socket() -> udp::Socket<'static> {
+  udp::Socket::new(
+    udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKET_NUM], vec![0; UDP_BUFFER_SIZE]),
+    udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKET_NUM], vec![0; UDP_BUFFER_SIZE]),
+  )
+}
+
+impl Stack {
+  fn poll(&mut self) {
+    self.iface.poll(now(), &mut self.device, &mut self.sockets);
+    let sockets = &mut self.sockets;
+    self.closing.retain(|&handle| {
+      if sockets.get::<tcp::Socket>(handle).is_open() {
+        true
+      } else {
+        sockets.remove(handle);
+        false
+      }
+    });
+  }
+
+  fn ephemeral_port(&mut self) -> u16 {
+    let port = self.next_port;
+    self.next_port = if port == u16::MAX { EPHEMERAL_PORT_START } else { port + 1 };
+    port
+  }
+
+  fn insert(&mut self, asid: u16, kind: usize, handle: SocketHandle) -> usize {
+    let id = self.next_handle;
+    // Note: handles fit in 16 bits, see `action::SEND_TO`
+    self.next_handle = self.next_handle % 0xffff + 1;
+    self.table.insert(id, Socket { asid, kind, handle, remote: None, port: None });
+    id
+  }
+
+  fn socket(&mut self, asid: u16, kind: usize) -> (usize, usize) {
+    let handle = match kind {
+      kind::TCP => self.sockets.add(tcp_socket()),
+      kind::UDP => self.sockets.add(udp_socket()),
+      _ => return (result::INVARG, 0),
+    };
+    (result::OK, self.insert(asid, kind, handle))
+  }
+
+  // Note: a port is taken only as the socket leaves `Closed`, retries on `HOLD_ON` keep it
+  fn connect(&mut self, id: usize, remote: IpEndpoint) -> usize {
+    let socket = &self.table[&id];
+    let (kind, handle) = (socket.kind, socket.handle);
+    if kind == kind::UDP {
+      if !self.sockets.get::<udp::Socket>(handle).is_open() {
+        let port = self.ephemeral_port();
+        if self.sockets.get_mut::<udp::Socket>(handle).bind(port).is_err() {
+          return result::ERR;
+        }
+      }
+      self.table.get_mut(&id).unwrap().remote = Some(remote);
+      return result::OK;
+    }
+    match self.sockets.get::<tcp::Socket>(handle).state() {
+      tcp::State::Closed if self.table[&id].remote.is_none() => {
+        let port = self.ephemeral_port();
+        let tcp = self.sockets.get_mut::<tcp::Socket>(handle);
+        if tcp.connect(self.iface.context(), remote, port).is_err() {
+          return result::ERR;
+        }
+        self.table.get_mut(&id).unwrap().remote = Some(remote);
+        result::HOLD_ON
+      }
+      tcp::State::SynSent | tcp::State::SynReceived => result::HOLD_ON,
+      tcp::State::Established => result::OK,
+      _ => result::ERR,
+    }
+  }
+
+  fn bind(&mut self, id: usize, port: u16, listen: bool) -> usize {
+    let socket = self.table.get_mut(&id).unwrap();
+    let r = match (socket.kind, listen) {
+      (kind::UDP, false) => self.sockets.get_mut::<udp::Socket>(socket.handle).bind(port).is_ok(),
+      (kind::TCP, true) => self.sockets.get_mut::<tcp::Socket>(socket.handle).listen(port).is_ok(),
+      _ => return result::INVARG,
+    };
+    if r && listen {
+      socket.port = Some(port);
+    }
+    if r { result::OK } else { result::ERR }
+  }
+
+  // The listening socket becomes the connection, a fresh one keeps listening under the same handle
+  fn accept(&mut self, id: usize) -> (usize, usize) {
+    let socket = &self.table[&id];
+    if socket.kind != kind::TCP {
+      return (result::INVARG, 0);
+    }
+    let (asid, handle) = (socket.asid, socket.handle);
+    match self.sockets.get::<tcp::Socket>(handle).state() {
+      tcp::State::Listen | tcp::State::SynReceived => return (result::HOLD_ON, 0),
+      tcp::State::Closed => return (result::ERR, 0),
+      _ => {}
+    }
+    // Note: the endpoint of an accepted connection is not the listening one, the port comes from `LISTEN`
+    let port = match socket.port {
+      Some(port) => port,
+      None => return (result::INVARG, 0),
+    };
+    let mut listener = tcp_socket();
+    if listener.listen(port).is_err() {
+      return (result::ERR, 0);
+    }
+    let listener = self.sockets.add(listener);
+    self.table.get_mut(&id).unwrap().handle = listener;
+    (result::OK, self.insert(asid, kind::TCP, handle))
+  }
+
+  fn send(&mut self, id: usize, to: Option<IpEndpoint>, addr: usize, len: usize) -> (usize, usize) {
+    let socket = &self.table[&id];
+    let asid = socket.asid;
+    match socket.kind {
+      kind::TCP => {
+        let tcp = self.sockets.get_mut::<tcp::Socket>(socket.handle);
+        if !tcp.may_send() {
+          return (result::ERR, 0);
+        }
+        if !tcp.can_send() {
+          return (result::HOLD_ON, 0);
+        }
+        match with_client_buffer(asid, addr, len.min(TRANSFER_MAX), |buf| tcp.send_slice(buf)) {
+          Some(Ok(n)) => (result::OK, n),
+          _ => (result::ERR, 0),
+        }
+      }
+      _ => {
+        let remote = match to.or(socket.remote) {
+          Some(remote) => remote,
+          None => return (result::INVARG, 0),
+        };
+        let udp = self.sockets.get_mut::<udp::Socket>(socket.handle);
+        if !udp.is_open() {
+          return (result::INVARG, 0);
+        }
+        if !udp.can_send() {
+          return (result::HOLD_ON, 0);
+        }
+        let sent = with_client_buffer(asid, addr, len.min(TRANSFER_MAX), |buf| {
+          udp.send_slice(buf, remote).map(|_| buf.len())
+        });
+        match sent {
+          Some(Ok(n)) => (result::OK, n),
+          _ => (result::ERR, 0),
+        }
+      }
+    }
+  }
+
+  fn recv(&mut self, id: usize, addr: usize, len: usize) -> (usize, usize, usize) {
+    let socket = &self.table[&id];
+    let asid = socket.asid;
+    match socket.kind {
+      kind::TCP => {
+        let tcp = self.sockets.get_mut::<tcp::Socket>(socket.handle);
+        if !tcp.can_recv() {
+          // end of stream once the peer has closed and everything is read
+          return if tcp.may_recv() || tcp.state() == tcp::State::SynSent {
+            (result::HOLD_ON, 0, 0)
+          } else {
+            (result::OK, 0, 0)
+          };
+        }
+        let remote = tcp.remote_endpoint().map_or(0, |e| endpoint(ipv4(e.addr), e.port));
+        match with_client_buffer(asid, addr, len.min(TRANSFER_MAX), |buf| tcp.recv_slice(buf)) {
+          Some(Ok(n)) => (result::OK, n, remote),
+          _ => (result::ERR, 0, 0),
+        }
+      }
+      _ => {
+        let udp = self.sockets.get_mut::<udp::Socket>(socket.handle);
+        if !udp.can_recv() {
+          return (result::HOLD_ON, 0, 0);
+        }
+        match with_client_buffer(asid, addr, len.min(TRANSFER_MAX), |buf| udp.recv_slice(buf)) {
+          Some(Ok((n, meta))) => (result::OK, n, endpoint(ipv4(meta.endpoint.addr), meta.endpoint.port)),
+          _ => (result::ERR, 0, 0),
+        }
+      }
+    }
+  }
+
+  fn close(&mut self, id: usize) {
+    let socket = self.table.remove(&id).unwrap();
+    match socket.kind {
+      kind::TCP => {
+        self.sockets.get_mut::<tcp::Socket>(socket.handle).close();
+        self.closing.push(socket.handle);
+      }
+      _ => {
+        self.sockets.remove(socket.handle);
+      }
+    }
+  }
+
+  fn handle(&mut self, asid: u16, msg: &Message) -> Message {
+    let remote = |e: usize| {
+      let (addr, port) = endpoint_split(e);
+      IpEndpoint::new(IpAddress::Ipv4(Ipv4Address(addr)), port)
+    };
+    let id = if msg.a == action::SEND_TO { msg.b & 0xffff } else { msg.b };
+    if msg.a != action::SOCKET && !matches!(self.table.get(&id), Some(s) if s.asid == asid) {
+      return Message::new(result::INVARG, 0, 0, 0);
+    }
+    let (r, b, c) = match msg.a {
+      action::SOCKET => {
+        let (r, id) = self.socket(asid, msg.b);
+        (r, id, 0)
+      }
+      action::CONNECT => (self.connect(id, remote(msg.c)), 0, 0),
+      action::BIND => (self.bind(id, msg.c as u16, false), 0, 0),
+      action::LISTEN => (self.bind(id, msg.c as u16, true), 0, 0),
+      action::ACCEPT => {
+        let (r, id) = self.accept(id);
+        (r, id, 0)
+      }
+      action::SEND => {
+        let (r, n) = self.send(id, None, msg.c, msg.d);
+        (r, n, 0)
+      }
+      action::SEND_TO => {
+        let (r, n) = self.send(id, Some(remote(msg.b >> 16)), msg.c, msg.d);
+        (r, n, 0)
+      }
+      action::RECV => self.recv(id, msg.c, msg.d),
+      action::CLOSE => {
+        self.close(id);
+        (result::OK, 0, 0)
+      }
+      _ => (result::UNKNOWN_ACTION, 0, 0),
+    };
+    Message::new(r, b, c, 0)
+  }
+}
+
+fn requests() {
+  rpsyscall::server_register(rpabi::server::SERVER_NET).unwrap();
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let reply = match get_asid(client_tid) {
+      Ok(asid) => {
+        let mut stack = STACK.get().unwrap().lock();
+        let reply = stack.handle(asid, &msg);
+        // Note: clients retrying `HOLD_ON` keep the stack polled between interrupts (e.g., TCP timers)
+        stack.poll();
+        reply
+      }
+      Err(_) => Message::new(result::ERR, 0, 0, 0),
+    };
+    let _ = reply.send_to(client_tid);
+  }
+}
+
+/// Network server on top of the first virtio network card
+pub fn server(dev: Device<'static>) {
+  info!("server started t{}", get_tid());
+  let transport = match crate::common::virtio::transport(&dev) {
+    Ok(transport) => transport,
+    Err(e) => {
+      error!("{} {}", dev.name, e);
+      return;
+    }
+  };
+  let mut device = match VirtioNet::new(transport) {
+    Ok(device) => device,
+    Err(e) => {
+      error!("{} {}", dev.name, e);
+      return;
+    }
+  };
+  let mac = device.mac();
+  let mut config = Config::new(HardwareAddress::Ethernet(EthernetAddress(mac)));
+  // seeds TCP initial sequence numbers and the first ephemeral port
+  let mut seed = [0u8; 8];
+  crate::rng::fill(&mut seed);
+  let seed = u64::from_ne_bytes(seed);
+  config.random_seed = seed;
+  let mut iface = Interface::new(config, &mut device, now());
+  iface.update_ip_addrs(|addrs| {
+    let _ = addrs.push(IpCidr::new(IpAddress::Ipv4(Ipv4Address(ADDRESS)), PREFIX_LEN));
+  });
+  let _ = iface.routes_mut().add_default_ipv4_route(Ipv4Address(GATEWAY));
+  info!("{} mac {:02x?} address {:?}/{}", dev.name, mac, ADDRESS, PREFIX_LEN);
+  STACK.call_once(|| Mutex::new(Stack {
+    device,
+    iface,
+    sockets: SocketSet::new(vec![]),
+    table: BTreeMap::new(),
+    closing: vec![],
+    next_handle: 1,
+    next_port: EPHEMERAL_PORT_START + (seed % (u16::MAX - EPHEMERAL_PORT_START) as u64) as u16,
+  }));
+  let _requests = thread::spawn(requests);
+
+  let irq = dev.interrupt();
+  loop {
+    match irq {
+      Some(irq) if rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, irq).is_ok() => {
+        let mut stack = STACK.get().unwrap().lock();
+        stack.device.interrupt_ack();
+        stack.poll();
+        drop(stack);
+        let _ = rpsyscall::irq_ack(irq);
+      }
+      _ => {
+        STACK.get().unwrap().lock().poll();
+        rpsyscall::thread_yield();
+      }
+    }
+  }
+}
@@ -0,0 +1,122 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+
+use crate::common::virtio::{self, BufferedQueue, Transport};
+
+const QUEUE_SIZE: usize = 32;
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+const VIRTIO_ID_NET: u32 = 1;
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const CONFIG_MAC: usize = 0;
+// used if the device does not tell its MAC, the default of QEMU
+const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+// `struct virtio_net_hdr` with `num_buffers`, which is always present with VIRTIO_F_VERSION_1
+const NET_HDR_SIZE: usize = 12;
+const MTU: usize = 1514;
+const BUFFER_SIZE: usize = 2048;
+
+/// virtio network card without offloading, one receive and one transmit queue
+pub struct VirtioNet {
+  transport: Box<dyn Transport>,
+  mac: [u8; 6],
+  rx: BufferedQueue<QUEUE_SIZE, BUFFER_SIZE>,
+  tx: BufferedQueue<QUEUE_SIZE, BUFFER_SIZE>,
+}
+
+impl VirtioNet {
+  pub fn new(transport: Box<dyn Transport>) -> Result<Self, &'static str> {
+    if transport.device_id() != VIRTIO_ID_NET {
+      return Err("not a network card");
+    }
+    let features = virtio::negotiate(transport.as_ref(), VIRTIO_NET_F_MAC)?;
+    let mut mac = DEFAULT_MAC;
+    if features & VIRTIO_NET_F_MAC != 0 {
+      for (i, b) in mac.iter_mut().enumerate() {
+        *b = transport.config::<u8>(CONFIG_MAC + i);
+      }
+    }
+    let rx = BufferedQueue::receiver(transport.as_ref(), RX_QUEUE)?;
+    let tx = BufferedQueue::transmitter(transport.as_ref(), TX_QUEUE)?;
+    let net = VirtioNet { transport, mac, rx, tx };
+    virtio::driver_ok(net.transport.as_ref());
+    net.rx.notify(net.transport.as_ref());
+    Ok(net)
+  }
+
+  pub fn mac(&self) -> [u8; 6] {
+    self.mac
+  }
+
+  pub fn interrupt_ack(&self) -> u32 {
+    self.transport.interrupt_ack()
+  }
+
+  /// Take a received frame without the virtio header, returning its buffer to the device
+  fn receive_frame(&mut self) -> Option<Vec<u8>> {
+    self.rx.receive(self.transport.as_ref(), |data| data[NET_HDR_SIZE.min(data.len())..].to_vec())
+  }
+
+  fn transmit_frame<R, F: FnOnce(&mut [u8]) -> R>(&mut self, len: usize, f: F) -> R {
+    while !self.tx.transmit_ready() {
+      rpsyscall::thread_yield();
+    }
+    let len = len.min(MTU);
+    self.tx.transmit(self.transport.as_ref(), NET_HDR_SIZE + len, |data| {
+      data[..NET_HDR_SIZE].fill(0);
+      f(&mut data[NET_HDR_SIZE..])
+    }).unwrap()
+  }
+}
+
+pub struct RxToken(Vec<u8>);
+
+pub struct TxToken<'a>(&'a mut VirtioNet);
+
+impl phy::RxToken for RxToken {
+  fn consume<R, F>(mut self, f: F) -> R
+  where
+    F: FnOnce(&mut [u8]) -> R,
+  {
+    f(&mut self.0)
+  }
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+  fn consume<R, F>(self, len: usize, f: F) -> R
+  where
+    F: FnOnce(&mut [u8]) -> R,
+  {
+    self.0.transmit_frame(len, f)
+  }
+}
+
+impl phy::Device for VirtioNet {
+  type RxToken<'a> = RxToken where Self: 'a;
+  type TxToken<'a> = TxToken<'a> where Self: 'a;
+
+  fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+    let frame = self.receive_frame()?;
+    Some((RxToken(frame), TxToken(self)))
+  }
+
+  fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+    if self.tx.transmit_ready() {
+      Some(TxToken(self))
+    } else {
+      None
+    }
+  }
+
+  fn capabilities(&self) -> DeviceCapabilities {
+    let mut caps = DeviceCapabilities::default();
+    caps.max_transmission_unit = MTU;
+    caps.medium = Medium::Ethernet;
+    caps
+  }
+}
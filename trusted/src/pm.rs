@@ -7,6 +7,7 @@ use spin::Mutex;
 
 use crate::common::foreign_slice::ForeignSlice;
 use crate::common::wrapper::request_wrapper;
+use rpabi::syscall::error::ERROR_HOLD_ON;
 use rpsyscall::{get_asid, get_tid};
 use rpsyscall::message::Message;
 
@@ -14,6 +15,7 @@ use rpsyscall::message::Message;
 enum ProcessStatus {
   Running,
   Exited,
+  Killed,
 }
 
 static PID_ALLOCATOR: AtomicUsize = AtomicUsize::new(200);
@@ -56,16 +58,35 @@ impl ProcessManager {
   fn poll_exit(&self, pid: usize) -> bool {
     let mut map = self.list.lock();
     if let Some(p) = map.get_mut(&pid) {
-      if p.status == ProcessStatus::Exited {
+      if p.status != ProcessStatus::Running {
         return true;
       } else {
-        if let Ok(_) = rpsyscall::event_wait(rpabi::event::EVENT_THREAD_EXIT, p.main_tid) {
-          p.status = ProcessStatus::Exited;
-          rpsyscall::address_space_destroy(p.asid).expect("process address space destroy failed");
-          return true;
-        } else {
-          return false;
+        match rpsyscall::event_wait(rpabi::event::EVENT_THREAD_EXIT, p.main_tid) {
+          Ok(_) => {}
+          Err(ERROR_HOLD_ON) => return false,
+          Err(e) => warn!("pid {} main thread t{} lost error {}", pid, p.main_tid, e),
+        }
+        p.status = ProcessStatus::Exited;
+        if let Err(e) = rpsyscall::address_space_destroy(p.asid) {
+          warn!("pid {} address space {} destroy error {}", pid, p.asid, e);
+        }
+        crate::blk::grant::release(p.asid);
+        return true;
+      }
+    }
+    false
+  }
+
+  fn kill(&self, pid: usize) -> bool {
+    let mut map = self.list.lock();
+    if let Some(p) = map.get_mut(&pid) {
+      if p.status == ProcessStatus::Running {
+        if let Err(e) = rpsyscall::address_space_destroy(p.asid) {
+          warn!("pid {} address space {} destroy error {}", pid, p.asid, e);
         }
+        crate::blk::grant::release(p.asid);
+        p.status = ProcessStatus::Killed;
+        return true;
       }
     }
     false
@@ -102,7 +123,10 @@ fn pm(msg: Message, tid: usize) -> (usize, usize) {
       if length >= 128 {
         return (rpservapi::pm::result::INVARG, 0);
       }
-      let s = ForeignSlice::new(asid, msg.b, msg.c).unwrap();
+      let s = match ForeignSlice::new(asid, msg.b, msg.c) {
+        Ok(s) => s,
+        Err(_) => return (rpservapi::pm::result::INVARG, 0),
+      };
       let cmd = s.local_slice();
       let cmd = core::str::from_utf8(cmd);
       if let Ok(cmd) = cmd {
@@ -125,6 +149,14 @@ fn pm(msg: Message, tid: usize) -> (usize, usize) {
         (rpservapi::pm::result::HOLD_ON, 0)
       }
     }
+    rpservapi::pm::action::KILL => {
+      let pid = msg.b;
+      if PROCESS_MANAGER.kill(pid) {
+        (rpservapi::pm::result::OK, 0)
+      } else {
+        (rpservapi::pm::result::INVARG, 0)
+      }
+    }
     rpservapi::pm::action::PS => {
       PROCESS_MANAGER.ps();
       (rpservapi::pm::result::OK, 0)
@@ -0,0 +1,113 @@
+//! ChaCha20 keystream as a random number generator
+//!
+//! The key is replaced by fresh keystream after every request (fast key erasure), so output already handed out
+//! cannot be recomputed from a later state.
+
+const KEY_WORDS: usize = 8;
+const BLOCK_SIZE: usize = 64;
+const ROUNDS: usize = 20;
+// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+  s[a] = s[a].wrapping_add(s[b]);
+  s[d] = (s[d] ^ s[a]).rotate_left(16);
+  s[c] = s[c].wrapping_add(s[d]);
+  s[b] = (s[b] ^ s[c]).rotate_left(12);
+  s[a] = s[a].wrapping_add(s[b]);
+  s[d] = (s[d] ^ s[a]).rotate_left(8);
+  s[c] = s[c].wrapping_add(s[d]);
+  s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn block(key: &[u32; KEY_WORDS], counter: u64, out: &mut [u8; BLOCK_SIZE]) {
+  let mut input = [0u32; 16];
+  input[..4].copy_from_slice(&CONSTANTS);
+  input[4..12].copy_from_slice(key);
+  input[12] = counter as u32;
+  input[13] = (counter >> 32) as u32;
+  let mut s = input;
+  for _ in 0..ROUNDS / 2 {
+    quarter_round(&mut s, 0, 4, 8, 12);
+    quarter_round(&mut s, 1, 5, 9, 13);
+    quarter_round(&mut s, 2, 6, 10, 14);
+    quarter_round(&mut s, 3, 7, 11, 15);
+    quarter_round(&mut s, 0, 5, 10, 15);
+    quarter_round(&mut s, 1, 6, 11, 12);
+    quarter_round(&mut s, 2, 7, 8, 13);
+    quarter_round(&mut s, 3, 4, 9, 14);
+  }
+  for (i, word) in s.iter().enumerate() {
+    out[i * 4..i * 4 + 4].copy_from_slice(&word.wrapping_add(input[i]).to_le_bytes());
+  }
+}
+
+pub struct ChaChaRng {
+  key: [u32; KEY_WORDS],
+  counter: u64,
+}
+
+impl ChaChaRng {
+  pub fn new(seed: &[u8; 32]) -> Self {
+    let mut rng = ChaChaRng { key: [0; KEY_WORDS], counter: 0 };
+    rng.reseed(seed);
+    rng
+  }
+
+  /// Mix fresh entropy into the key
+  pub fn reseed(&mut self, seed: &[u8; 32]) {
+    for (i, word) in self.key.iter_mut().enumerate() {
+      *word ^= u32::from_le_bytes([seed[i * 4], seed[i * 4 + 1], seed[i * 4 + 2], seed[i * 4 + 3]]);
+    }
+    self.rekey();
+  }
+
+  fn rekey(&mut self) {
+    let mut out = [0u8; BLOCK_SIZE];
+    block(&self.key, self.counter, &mut out);
+    for (i, word) in self.key.iter_mut().enumerate() {
+      *word = u32::from_le_bytes([out[i * 4], out[i * 4 + 1], out[i * 4 + 2], out[i * 4 + 3]]);
+    }
+    self.counter = 0;
+  }
+
+  pub fn fill(&mut self, buf: &mut [u8]) {
+    let mut out = [0u8; BLOCK_SIZE];
+    for chunk in buf.chunks_mut(BLOCK_SIZE) {
+      block(&self.key, self.counter, &mut out);
+      self.counter += 1;
+      chunk.copy_from_slice(&out[..chunk.len()]);
+    }
+    self.rekey();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(s: &str) -> [u8; BLOCK_SIZE] {
+    let mut out = [0u8; BLOCK_SIZE];
+    for (i, b) in out.iter_mut().enumerate() {
+      *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    out
+  }
+
+  // RFC 8439 appendix A.1, keystream test vectors with an all-zero nonce
+  #[test]
+  fn rfc8439_keystream() {
+    let mut key = [0u32; KEY_WORDS];
+    let mut out = [0u8; BLOCK_SIZE];
+    block(&key, 0, &mut out);
+    assert_eq!(out, hex("76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1aa836efcc8b770dc7\
+                         da41597c5157488d7724e03fb8d84a376a43b8f41518a11cc387b669b2ee6586"));
+    block(&key, 1, &mut out);
+    assert_eq!(out, hex("9f07e7be5551387a98ba977c732d080dcb0f29a048e3656912c6533e32ee7aed\
+                         29b721769ce64e43d57133b074d839d531ed1f28510afb45ace10a1f4b794d6f"));
+    key[0] = 0xff00;
+    block(&key, 2, &mut out);
+    assert_eq!(out, hex("72d54dfbf12ec44b362692df94137f328fea8da73990265ec1bbbea1ae9af0ca\
+                         13b25aa26cb4a648cb9b9d1be65b2c0924a66c54d545ec1b7374f4872e99f096"));
+  }
+}
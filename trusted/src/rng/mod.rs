@@ -0,0 +1,111 @@
+//! Random numbers from a ChaCha20 generator seeded by a virtio entropy device, or by timer jitter without one
+
+use rpabi::platform::Device;
+use rpservapi::rng::{action, result};
+use rpsyscall::message::Message;
+use rpsyscall::{get_asid, get_tid};
+use spin::{Mutex, Once};
+
+use crate::common::foreign_slice::with_client_buffer;
+
+use self::chacha::ChaChaRng;
+use self::virtio_rng::VirtioRng;
+
+pub mod chacha;
+pub mod virtio_rng;
+
+const SEED_SIZE: usize = 32;
+// bytes handed out before fresh entropy is mixed in
+const RESEED_INTERVAL: usize = 1 << 20;
+// upper bound of a buffer mapped from a client per request
+const TRANSFER_MAX: usize = 65536;
+const JITTER_SAMPLES_PER_BYTE: usize = 16;
+
+struct Generator {
+  rng: ChaChaRng,
+  source: Option<VirtioRng>,
+  output: usize,
+}
+
+static GENERATOR: Once<Mutex<Generator>> = Once::new();
+
+fn clock_us() -> u64 {
+  rpsyscall::kernel_stat(rpabi::kstat::ITEM_CLOCK).map_or(0, |(ms, us, ..)| ms as u64 * 1000 + us as u64)
+}
+
+// Note: weak entropy, the time a yield takes varies with scheduling, interrupts and caches
+fn jitter(buf: &mut [u8]) {
+  let mut pool = clock_us();
+  for b in buf.iter_mut() {
+    for _ in 0..JITTER_SAMPLES_PER_BYTE {
+      let start = clock_us();
+      rpsyscall::thread_yield();
+      let delta = clock_us().wrapping_sub(start);
+      pool = (pool ^ delta).rotate_left(7).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+    *b = (pool >> 56) as u8;
+  }
+}
+
+fn entropy(source: &mut Option<VirtioRng>, seed: &mut [u8; SEED_SIZE]) {
+  match source {
+    Some(device) => device.fill(seed),
+    None => jitter(seed),
+  }
+}
+
+/// Seed the generator, from the entropy device `dev` if there is one
+///
+/// Called by root before starting other servers so that they can take random numbers right away.
+pub fn init(dev: Option<Device>) {
+  let mut source = dev.and_then(|dev| match crate::common::virtio::transport(&dev).and_then(VirtioRng::new) {
+    Ok(device) => Some(device),
+    Err(e) => {
+      warn!("{} {}, seeded by timer jitter", dev.name, e);
+      None
+    }
+  });
+  if source.is_none() {
+    warn!("no entropy device, seeded by timer jitter");
+  }
+  let mut seed = [0u8; SEED_SIZE];
+  entropy(&mut source, &mut seed);
+  GENERATOR.call_once(|| Mutex::new(Generator {
+    rng: ChaChaRng::new(&seed),
+    source,
+    output: 0,
+  }));
+}
+
+/// Fill `buf` with random bytes, for servers in the trusted address space
+pub fn fill(buf: &mut [u8]) {
+  let mut generator = GENERATOR.get().expect("rng not initialized").lock();
+  if generator.output >= RESEED_INTERVAL {
+    let mut seed = [0u8; SEED_SIZE];
+    entropy(&mut generator.source, &mut seed);
+    generator.rng.reseed(&seed);
+    generator.output = 0;
+  }
+  generator.rng.fill(buf);
+  generator.output += buf.len();
+}
+
+pub fn server() {
+  info!("server started t{}", get_tid());
+  rpsyscall::server_register(rpabi::server::SERVER_RNG).unwrap();
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let (a, b) = match (msg.a, get_asid(client_tid)) {
+      (action::FILL, Ok(asid)) => {
+        let len = msg.d.min(TRANSFER_MAX);
+        match with_client_buffer(asid, msg.c, len, fill) {
+          Some(()) => (result::OK, len),
+          None => (result::ERR, 0),
+        }
+      }
+      (action::FILL, Err(_)) => (result::ERR, 0),
+      _ => (result::UNKNOWN_ACTION, 0),
+    };
+    let _ = Message::new(a, b, 0, 0).send_to(client_tid);
+  }
+}
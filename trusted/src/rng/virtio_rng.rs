@@ -0,0 +1,54 @@
+use alloc::boxed::Box;
+
+use crate::common::mm::virt_to_phys;
+use crate::common::virtio::{self, Transport, VirtQueue};
+
+const VIRTIO_ID_RNG: u32 = 4;
+const QUEUE_SIZE: usize = 4;
+const BUFFER_SIZE: usize = 64;
+
+// Note: the alignment keeps the buffer in one page so that it is physically contiguous
+#[repr(C, align(64))]
+struct Buffer([u8; BUFFER_SIZE]);
+
+/// virtio entropy device, requests are polled as they are rare
+pub struct VirtioRng {
+  transport: Box<dyn Transport>,
+  queue: VirtQueue<QUEUE_SIZE>,
+  buffer: Box<Buffer>,
+}
+
+impl VirtioRng {
+  pub fn new(transport: Box<dyn Transport>) -> Result<Self, &'static str> {
+    if transport.device_id() != VIRTIO_ID_RNG {
+      return Err("not an entropy device");
+    }
+    virtio::negotiate(transport.as_ref(), 0)?;
+    let mut queue = VirtQueue::new(transport.as_ref(), 0)?;
+    queue.suppress_interrupt();
+    virtio::driver_ok(transport.as_ref());
+    Ok(VirtioRng {
+      transport,
+      queue,
+      buffer: Box::new(Buffer([0; BUFFER_SIZE])),
+    })
+  }
+
+  pub fn fill(&mut self, buf: &mut [u8]) {
+    let pa = virt_to_phys(self.buffer.0.as_ptr() as usize);
+    let mut filled = 0;
+    while filled < buf.len() {
+      let len = (buf.len() - filled).min(BUFFER_SIZE);
+      self.queue.push(&[(pa, len, true)]).unwrap();
+      self.queue.notify(self.transport.as_ref());
+      let written = loop {
+        match self.queue.pop_used() {
+          Some((_, written)) => break (written as usize).min(len),
+          None => rpsyscall::thread_yield(),
+        }
+      };
+      buf[filled..(filled + written)].copy_from_slice(&self.buffer.0[..written]);
+      filled += written;
+    }
+  }
+}
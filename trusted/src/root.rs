@@ -1,60 +1,169 @@
-use rpabi::platform::PlatformInfo;
+use alloc::string::String;
+
+use rpabi::platform::{Device, PlatformInfo};
 
 use crate::common::thread;
+use crate::common::thread::JoinHandle;
 use crate::common::wrapper::server_wrapper;
 
+// Hand the device interrupt to its driver thread only, see `rpsyscall::irq_bind`
+fn bind_interrupt<T>(dev: &Device, handler: &JoinHandle<T>) {
+  if let Some(irq) = dev.interrupt() {
+    rpsyscall::irq_bind(irq, handler.native(), 0).expect("root bind interrupt failed");
+  }
+}
+
+/// The original device tree passed by firmware, present on FDT platforms
+///
+/// Drivers not described by `PlatformInfo` can be probed by `compatible` from it.
+pub fn device_tree(info: &'static PlatformInfo) -> Option<fdt::Fdt<'static>> {
+  let range = info.device_tree()?;
+  let blob = unsafe { core::slice::from_raw_parts(range.start as *const u8, range.len()) };
+  match fdt::Fdt::new(blob) {
+    Ok(fdt) => Some(fdt),
+    Err(e) => {
+      warn!("device tree malformed {:?}", e);
+      None
+    }
+  }
+}
+
 pub fn main(info: &'static PlatformInfo) {
   let mut join_handlers = vec![];
   let mut has_user_space_serial = false;
   let mut has_user_space_rtc = false;
-  for dev in &info.devices {
-    match dev {
-      Some(dev) => match dev.driver {
-        Some(driver) => match driver {
-          rpabi::platform::Driver::VirtioBlk => {
-            join_handlers.push(thread::spawn(|| {
-              crate::blk::virtio_blk::server(dev.register.start, dev.interrupt.unwrap());
-            }));
-          }
-          rpabi::platform::Driver::Ns16550 => {
-            has_user_space_serial = true;
-            join_handlers.push(thread::spawn(|| {
-              crate::serial::ns16550::server(dev.register.start, dev.interrupt.unwrap());
-            }));
-          }
-          rpabi::platform::Driver::Pl011 => {
-            has_user_space_serial = true;
-            join_handlers.push(thread::spawn(|| {
-              crate::serial::pl011::server(dev.register.start, dev.interrupt.unwrap());
-            }));
-          }
-          rpabi::platform::Driver::Pl031 => {
-            has_user_space_rtc = true;
-            join_handlers.push(thread::spawn(|| {
-              crate::rtc::pl031::server(dev.register.start, 0);
-            }));
-          }
-          rpabi::platform::Driver::GoldfishRtc => {
-            has_user_space_rtc = true;
-            join_handlers.push(thread::spawn(|| {
-              crate::rtc::goldfish::server(dev.register.start, 0);
-            }));
-          }
+  let mut virtio_blk_count = 0;
+  // block device holding the root file system
+  let mut root_disk: Option<String> = None;
+  let mut disks = vec![];
+  let mut has_user_space_net = false;
+  let mut has_user_space_console = false;
+  if !info.is_valid() {
+    error!("platform info version mismatch");
+  }
+  if let Some(fdt) = device_tree(info) {
+    info!("device tree {} with {} nodes", fdt.root().model(), fdt.all_nodes().count());
+  }
+  if let Some(rsdp) = info.acpi_rsdp() {
+    info!("acpi rsdp {:x} mapped at {:x}", rsdp, rpabi::platform::USER_SPACE_ACPI_OFFSET + rsdp);
+  }
+  // Note: only the first entropy device is used
+  crate::rng::init(info.devices().find(|dev| dev.driver == Some(rpabi::platform::Driver::VirtioRng)));
+  for dev in info.devices() {
+    let driver = match dev.driver {
+      Some(driver) => driver,
+      None => continue,
+    };
+    let base = dev.register().map_or(0, |r| r.start);
+    match driver {
+      // Note: disks are named in discovery order, the first one holds the root file system
+      rpabi::platform::Driver::VirtioBlk if virtio_blk_count < 26 => {
+        let name = format!("vd{}", (b'a' + virtio_blk_count as u8) as char);
+        virtio_blk_count += 1;
+        crate::blk::registry::add(&name);
+        root_disk.get_or_insert_with(|| name.clone());
+        disks.push(name.clone());
+        let blk = dev.clone();
+        let handler = thread::spawn(move || {
+          crate::blk::virtio_blk::server(blk, name);
+        });
+        bind_interrupt(&dev, &handler);
+        join_handlers.push(handler);
+      }
+      // Note: only the first network card is used
+      rpabi::platform::Driver::VirtioNet if !has_user_space_net => {
+        has_user_space_net = true;
+        let net = dev.clone();
+        let handler = thread::spawn(move || {
+          crate::net::server::server(net);
+        });
+        bind_interrupt(&dev, &handler);
+        join_handlers.push(handler);
+      }
+      // Note: the console port serves as the terminal only when there is no UART
+      rpabi::platform::Driver::VirtioConsole if !has_user_space_console => {
+        has_user_space_console = true;
+        let console = dev.clone();
+        let handler = thread::spawn(move || {
+          crate::serial::virtio_console::server(console);
+        });
+        bind_interrupt(&dev, &handler);
+        join_handlers.push(handler);
+      }
+      rpabi::platform::Driver::Ns16550 => {
+        has_user_space_serial = true;
+        let irq = dev.interrupt().unwrap();
+        let handler = match dev.ports.first().cloned() {
+          // Note: threads of root share its address space, the grant covers the server thread
           #[cfg(target_arch = "x86_64")]
-          rpabi::platform::Driver::Ramdisk => {
-            join_handlers.push(thread::spawn(|| {
-              crate::blk::ramdisk::server();
-            }));
+          Some(ports) => {
+            rpsyscall::io_port_grant(0, ports.start, ports.len()).expect("root grant io ports failed");
+            thread::spawn(move || {
+              crate::serial::ns16550::port_server(ports.start as u16, irq);
+            })
           }
-          _ => {}
-        },
-        None => {}
-      },
-      None => continue,
+          _ => thread::spawn(move || {
+            crate::serial::ns16550::server(base, irq);
+          }),
+        };
+        bind_interrupt(&dev, &handler);
+        join_handlers.push(handler);
+      }
+      rpabi::platform::Driver::Pl011 => {
+        has_user_space_serial = true;
+        let irq = dev.interrupt().unwrap();
+        let handler = thread::spawn(move || {
+          crate::serial::pl011::server(base, irq);
+        });
+        bind_interrupt(&dev, &handler);
+        join_handlers.push(handler);
+      }
+      rpabi::platform::Driver::Pl031 => {
+        has_user_space_rtc = true;
+        join_handlers.push(thread::spawn(move || {
+          crate::rtc::pl031::server(base, 0);
+        }));
+      }
+      rpabi::platform::Driver::GoldfishRtc => {
+        has_user_space_rtc = true;
+        join_handlers.push(thread::spawn(move || {
+          crate::rtc::goldfish::server(base, 0);
+        }));
+      }
+      #[cfg(target_arch = "x86_64")]
+      rpabi::platform::Driver::Ramdisk => {
+        // Note: the ramdisk carries the root file system whenever present
+        crate::blk::registry::add("ramdisk0");
+        root_disk = Some(String::from("ramdisk0"));
+        disks.push(String::from("ramdisk0"));
+        join_handlers.push(thread::spawn(|| {
+          crate::blk::ramdisk::server("ramdisk0");
+        }));
+      }
+      _ => {}
     }
   }
 
-  if !has_user_space_serial {
+  for disk in disks {
+    crate::blk::registry::scan_begin();
+    join_handlers.push(thread::spawn(move || {
+      crate::blk::partition::scan(disk);
+    }));
+  }
+
+  // Note: `root=` on the kernel command line picks the file system, e.g. `root=PARTLABEL=rustpi` or `root=vda2`
+  let root_arg = device_tree(info)
+    .and_then(|fdt| fdt.find_node("/chosen")?.property("bootargs")?.as_str())
+    .and_then(|args| args.split_whitespace().find_map(|arg| arg.strip_prefix("root=")));
+  if let Some(root_arg) = root_arg {
+    root_disk = Some(String::from(root_arg));
+  }
+
+  if !has_user_space_serial && has_user_space_console {
+    join_handlers.push(thread::spawn(|| {
+      server_wrapper(crate::serial::virtio_console::terminal);
+    }));
+  } else if !has_user_space_serial {
     join_handlers.push(thread::spawn(|| {
       server_wrapper(crate::serial::default::input_server);
     }));
@@ -70,9 +179,24 @@ pub fn main(info: &'static PlatformInfo) {
   }
 
   join_handlers.push(thread::spawn(|| {
-    server_wrapper(crate::fs::server::server);
+    server_wrapper(crate::blk::registry::server);
+  }));
+
+  join_handlers.push(thread::spawn(|| {
+    server_wrapper(crate::blk::cache::server);
   }));
 
+  join_handlers.push(thread::spawn(|| {
+    server_wrapper(crate::blk::cache::flusher);
+  }));
+
+  match root_disk {
+    Some(root_disk) => join_handlers.push(thread::spawn(move || {
+      server_wrapper(|| crate::fs::server::server(&root_disk));
+    })),
+    None => warn!("no block device for the root file system"),
+  }
+
   join_handlers.push(thread::spawn(|| {
     server_wrapper(crate::mm::server);
   }));
@@ -81,6 +205,14 @@ pub fn main(info: &'static PlatformInfo) {
     server_wrapper(crate::pm::server);
   }));
 
+  join_handlers.push(thread::spawn(|| {
+    server_wrapper(crate::unix::server);
+  }));
+
+  join_handlers.push(thread::spawn(|| {
+    server_wrapper(crate::rng::server);
+  }));
+
   join_handlers.push(thread::spawn(|| {
     match crate::common::loader::spawn("shell") {
       Ok((_asid, tid)) => {
@@ -1,3 +1,4 @@
 pub mod default;
 pub mod ns16550;
-pub mod pl011;
\ No newline at end of file
+pub mod pl011;
+pub mod virtio_console;
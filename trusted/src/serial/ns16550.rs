@@ -6,22 +6,48 @@ use rpsyscall::get_tid;
 use rpsyscall::message::Message;
 use tock_registers::interfaces::{Readable, Writeable};
 
-fn init(ns16550: &Ns16550Mmio) {
-  ns16550.ISR_FCR.write(ISR_FCR::EN_FIFO::Mode16550); // enable FIFO
-  ns16550.IER_DLM.write(IER_DLM::IE_RHR::SET) // enable IE_RHR
+// Register access differs between MMIO and x86 I/O port UARTs, the server logic does not
+trait Uart {
+  fn init(&self);
+  fn getc(&self) -> Option<u8>;
 }
 
-fn getc(ns16550: &Ns16550Mmio) -> Option<u8> {
-  if ns16550.LSR.is_set(LSR::RDR) {
-    Some(ns16550.RHR_THR_DLL.get() as u8)
-  } else {
-    None
+impl Uart for Ns16550Mmio {
+  fn init(&self) {
+    self.ISR_FCR.write(ISR_FCR::EN_FIFO::Mode16550); // enable FIFO
+    self.IER_DLM.write(IER_DLM::IE_RHR::SET) // enable IE_RHR
+  }
+
+  fn getc(&self) -> Option<u8> {
+    if self.LSR.is_set(LSR::RDR) {
+      Some(self.RHR_THR_DLL.get() as u8)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Uart for Ns16550Port {
+  fn init(&self) {
+    self.ISR_FCR.write(ISR_FCR::EN_FIFO::Mode16550); // enable FIFO
+    // Note: PC UARTs gate their interrupt line by OUT2
+    self.MCR.write(MCR::OUT2::SET + MCR::RTS::SET + MCR::DTR::SET);
+    self.IER_DLM.write(IER_DLM::IE_RHR::SET) // enable IE_RHR
+  }
+
+  fn getc(&self) -> Option<u8> {
+    if self.LSR.is_set(LSR::RDR) {
+      Some(self.RHR_THR_DLL.get())
+    } else {
+      None
+    }
   }
 }
 
-fn drain_rx_fifo(ns16550: &Ns16550Mmio, buf: &mut VecDeque<u8>) {
+fn drain_rx_fifo<U: Uart>(uart: &U, buf: &mut VecDeque<u8>) {
   loop {
-    match getc(ns16550) {
+    match uart.getc() {
       Some(c) => {
         buf.push_back(c);
       }
@@ -33,8 +59,18 @@ fn drain_rx_fifo(ns16550: &Ns16550Mmio, buf: &mut VecDeque<u8>) {
 pub fn server(base_addr: usize, irq_num: usize) {
   info!("server started t{}", get_tid());
   let base_addr = base_addr + rpabi::platform::USER_SPACE_DRIVER_MMIO_OFFSET;
-  let ns16550 = Ns16550Mmio::new(base_addr);
-  init(&ns16550);
+  serve(Ns16550Mmio::new(base_addr), irq_num);
+}
+
+/// Server of a UART at I/O ports `base_port..base_port + 8`, which must be granted to the address space
+#[cfg(target_arch = "x86_64")]
+pub fn port_server(base_port: u16, irq_num: usize) {
+  info!("server started t{} port {:x}", get_tid(), base_port);
+  serve(Ns16550Port::new(base_port), irq_num);
+}
+
+fn serve<U: Uart>(uart: U, irq_num: usize) {
+  uart.init();
   rpsyscall::server_register(rpabi::server::SERVER_TERMINAL).unwrap();
   let mut buf = VecDeque::new();
   let mut client_tid;
@@ -42,11 +78,17 @@ pub fn server(base_addr: usize, irq_num: usize) {
   loop {
     let mut msg = rpsyscall::message::Message::default();
     loop {
-      drain_rx_fifo(&ns16550, &mut buf);
+      drain_rx_fifo(&uart, &mut buf);
       if !buf.is_empty() {
         break;
       }
-      let _ = rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, irq_num);
+      if rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, irq_num).is_ok() {
+        drain_rx_fifo(&uart, &mut buf);
+        let _ = rpsyscall::irq_ack(irq_num);
+      } else {
+        // Note: interrupt is not bound by root yet
+        rpsyscall::thread_yield();
+      }
     }
     msg.a = buf.pop_front().unwrap() as usize;
     client_tid = msg.reply_recv(client_tid).unwrap().0;
@@ -49,7 +49,13 @@ pub fn server(base_addr: usize, irq_num: usize) {
       if !buf.is_empty() {
         break;
       }
-      let _ = rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, irq_num);
+      if rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, irq_num).is_ok() {
+        drain_rx_fifo(&pl011, &mut buf);
+        let _ = rpsyscall::irq_ack(irq_num);
+      } else {
+        // Note: interrupt is not bound by root yet
+        rpsyscall::thread_yield();
+      }
     }
     msg.a = buf.pop_front().unwrap() as usize;
     client_tid = msg.reply_recv(client_tid).unwrap().0;
@@ -0,0 +1,350 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use rpabi::platform::Device;
+use rpservapi::console::{action, result};
+use rpsyscall::message::Message;
+use rpsyscall::{get_asid, get_tid};
+use spin::{Mutex, Once};
+
+use crate::common::foreign_slice::with_client_buffer;
+use crate::common::thread;
+use crate::common::virtio::{self, BufferedQueue, Transport};
+
+const VIRTIO_ID_CONSOLE: u32 = 3;
+const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1 << 1;
+const CONFIG_MAX_NR_PORTS: usize = 4;
+
+// ports beyond are left to the device
+const PORT_MAX: usize = 8;
+const QUEUE_SIZE: usize = 16;
+const BUFFER_SIZE: usize = 512;
+// bytes kept per port until read by a client, older input is dropped
+const INPUT_MAX: usize = 4096;
+const PORT_NAME_MAX: usize = 64;
+
+const CONTROL_RX_QUEUE: u16 = 2;
+
+// `struct virtio_console_control` events
+const DEVICE_READY: u16 = 0;
+const DEVICE_ADD: u16 = 1;
+const DEVICE_REMOVE: u16 = 2;
+const PORT_READY: u16 = 3;
+const CONSOLE_PORT: u16 = 4;
+const PORT_OPEN: u16 = 6;
+const PORT_NAME: u16 = 7;
+const CONTROL_SIZE: usize = 8;
+
+/// A receive and a transmit queue, used by every port and by the control channel
+struct Channel {
+  rx: BufferedQueue<QUEUE_SIZE, BUFFER_SIZE>,
+  tx: BufferedQueue<QUEUE_SIZE, BUFFER_SIZE>,
+}
+
+impl Channel {
+  fn new(transport: &dyn Transport, rx_queue: u16) -> Result<Self, &'static str> {
+    Ok(Channel {
+      rx: BufferedQueue::receiver(transport, rx_queue)?,
+      tx: BufferedQueue::transmitter(transport, rx_queue + 1)?,
+    })
+  }
+
+  fn receive(&mut self, transport: &dyn Transport) -> Option<Vec<u8>> {
+    self.rx.receive(transport, |data| data.to_vec())
+  }
+
+  /// Queue the head of `data`, returns the bytes taken or 0 if every transmit buffer is in flight
+  fn transmit(&mut self, transport: &dyn Transport, data: &[u8]) -> usize {
+    self.tx.transmit(transport, data.len(), |buf| {
+      buf.copy_from_slice(&data[..buf.len()]);
+      buf.len()
+    }).unwrap_or(0)
+  }
+}
+
+struct Port {
+  channel: Channel,
+  // announced by the device
+  added: bool,
+  console: bool,
+  name: Option<String>,
+  input: VecDeque<u8>,
+}
+
+/// virtio console, each port is a byte channel to the host
+pub struct VirtioConsole {
+  transport: Box<dyn Transport>,
+  control: Option<Channel>,
+  ports: Vec<Port>,
+}
+
+// Queues of port 0 are 0 and 1, the control channel takes 2 and 3, port n > 0 follows at 2n + 2
+fn port_rx_queue(port: usize) -> u16 {
+  if port == 0 {
+    0
+  } else {
+    (port * 2 + 2) as u16
+  }
+}
+
+impl VirtioConsole {
+  pub fn new(transport: Box<dyn Transport>) -> Result<Self, &'static str> {
+    if transport.device_id() != VIRTIO_ID_CONSOLE {
+      return Err("not a console");
+    }
+    let features = virtio::negotiate(transport.as_ref(), VIRTIO_CONSOLE_F_MULTIPORT)?;
+    let multiport = features & VIRTIO_CONSOLE_F_MULTIPORT != 0;
+    let port_num = if multiport {
+      (transport.config::<u32>(CONFIG_MAX_NR_PORTS) as usize).clamp(1, PORT_MAX)
+    } else {
+      1
+    };
+    // Note: queues must be set up before the device is told the driver is ready
+    let mut ports = Vec::new();
+    for i in 0..port_num {
+      ports.push(Port {
+        channel: Channel::new(transport.as_ref(), port_rx_queue(i))?,
+        added: false,
+        console: false,
+        name: None,
+        input: VecDeque::new(),
+      });
+    }
+    let control = if multiport {
+      Some(Channel::new(transport.as_ref(), CONTROL_RX_QUEUE)?)
+    } else {
+      // the only port is the console, it exists without being announced
+      ports[0].added = true;
+      ports[0].console = true;
+      None
+    };
+    let mut console = VirtioConsole { transport, control, ports };
+    virtio::driver_ok(console.transport.as_ref());
+    for port in console.ports.iter() {
+      port.channel.rx.notify(console.transport.as_ref());
+    }
+    if let Some(control) = &console.control {
+      control.rx.notify(console.transport.as_ref());
+    }
+    console.control_send(0, DEVICE_READY, 1);
+    Ok(console)
+  }
+
+  pub fn interrupt_ack(&self) -> u32 {
+    self.transport.interrupt_ack()
+  }
+
+  fn control_send(&mut self, id: u32, event: u16, value: u16) {
+    let control = match &mut self.control {
+      Some(control) => control,
+      None => return,
+    };
+    let mut msg = [0u8; CONTROL_SIZE];
+    msg[0..4].copy_from_slice(&id.to_le_bytes());
+    msg[4..6].copy_from_slice(&event.to_le_bytes());
+    msg[6..8].copy_from_slice(&value.to_le_bytes());
+    while control.transmit(self.transport.as_ref(), &msg) == 0 {
+      rpsyscall::thread_yield();
+    }
+  }
+
+  fn control_receive(&mut self, msg: &[u8]) {
+    if msg.len() < CONTROL_SIZE {
+      return;
+    }
+    let id = u32::from_le_bytes([msg[0], msg[1], msg[2], msg[3]]);
+    let event = u16::from_le_bytes([msg[4], msg[5]]);
+    let value = u16::from_le_bytes([msg[6], msg[7]]);
+    let port = match self.ports.get_mut(id as usize) {
+      Some(port) => port,
+      None => {
+        if event == DEVICE_ADD {
+          self.control_send(id, PORT_READY, 0);
+        }
+        return;
+      }
+    };
+    match event {
+      DEVICE_ADD => {
+        port.added = true;
+        self.control_send(id, PORT_READY, 1);
+        // every port is kept open towards the host, input is buffered until read
+        self.control_send(id, PORT_OPEN, 1);
+      }
+      DEVICE_REMOVE => {
+        port.added = false;
+        port.console = false;
+        port.name = None;
+        port.input.clear();
+      }
+      CONSOLE_PORT => {
+        port.console = true;
+        info!("console port {}", id);
+      }
+      PORT_NAME => {
+        let name = &msg[CONTROL_SIZE..];
+        let name = &name[..name.len().min(PORT_NAME_MAX)];
+        port.name = core::str::from_utf8(name).ok().map(|name| String::from(name.trim_end_matches('\0')));
+        info!("port {} named {:?}", id, port.name);
+      }
+      PORT_OPEN => {
+        trace!("port {} host open {}", id, value);
+      }
+      _ => {}
+    }
+  }
+
+  /// Move received data of every port to its input buffer and handle control messages
+  pub fn poll(&mut self) {
+    loop {
+      let msg = match &mut self.control {
+        Some(control) => control.receive(self.transport.as_ref()),
+        None => None,
+      };
+      match msg {
+        Some(msg) => self.control_receive(&msg),
+        None => break,
+      }
+    }
+    for port in self.ports.iter_mut() {
+      while let Some(data) = port.channel.receive(self.transport.as_ref()) {
+        port.input.extend(data);
+        let overflow = port.input.len().saturating_sub(INPUT_MAX);
+        port.input.drain(..overflow);
+      }
+    }
+  }
+
+  fn port(&mut self, id: usize) -> Option<&mut Port> {
+    self.ports.get_mut(id).filter(|port| port.added)
+  }
+
+  fn console_port(&self) -> Option<usize> {
+    self.ports.iter().position(|port| port.added && port.console)
+  }
+
+  fn open(&self, name: &[u8]) -> Option<usize> {
+    self.ports.iter().position(|port| port.added && port.name.as_deref().map(str::as_bytes) == Some(name))
+  }
+
+  fn read(&mut self, id: usize, buf: &mut [u8]) -> Option<usize> {
+    let port = self.port(id)?;
+    let n = port.input.len().min(buf.len());
+    for (b, c) in buf.iter_mut().zip(port.input.drain(..n)) {
+      *b = c;
+    }
+    Some(n)
+  }
+
+  fn write(&mut self, id: usize, data: &[u8]) -> Option<usize> {
+    self.port(id)?;
+    let transport = self.transport.as_ref();
+    let port = &mut self.ports[id];
+    let mut n = 0;
+    while n < data.len() {
+      match port.channel.transmit(transport, &data[n..]) {
+        0 => break,
+        sent => n += sent,
+      }
+    }
+    Some(n)
+  }
+}
+
+static CONSOLE: Once<Mutex<VirtioConsole>> = Once::new();
+
+// Next character of the console port, 0 if none
+fn getc(console: &mut VirtioConsole) -> usize {
+  let mut c = [0u8];
+  let n = console.console_port().and_then(|id| console.read(id, &mut c)).unwrap_or(0);
+  if n == 0 { 0 } else { c[0] as usize }
+}
+
+fn request(asid: u16, msg: &Message) -> (usize, usize) {
+  let mut console = CONSOLE.get().unwrap().lock();
+  // Note: buffers larger than the transmit queue are served partially
+  let len = msg.d.min(QUEUE_SIZE * BUFFER_SIZE);
+  let handled = match msg.a {
+    action::GETC => return (getc(&mut console), 0),
+    action::OPEN => with_client_buffer(asid, msg.c, msg.d.min(PORT_NAME_MAX), |name| console.open(name))
+      .map(|id| id.ok_or(result::NOT_FOUND)),
+    action::READ => with_client_buffer(asid, msg.c, len, |buf| console.read(msg.b, buf))
+      .map(|n| match n {
+        Some(0) if len != 0 => Err(result::HOLD_ON),
+        n => n.ok_or(result::INVARG),
+      }),
+    action::WRITE => with_client_buffer(asid, msg.c, len, |buf| console.write(msg.b, buf))
+      .map(|n| match n {
+        Some(0) if len != 0 => Err(result::HOLD_ON),
+        n => n.ok_or(result::INVARG),
+      }),
+    _ => return (result::UNKNOWN_ACTION, 0),
+  };
+  match handled {
+    Some(Ok(value)) => (result::OK, value),
+    Some(Err(r)) => (r, 0),
+    None => (result::ERR, 0),
+  }
+}
+
+fn requests() {
+  rpsyscall::server_register(rpabi::server::SERVER_CONSOLE).unwrap();
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let (a, b) = match get_asid(client_tid) {
+      Ok(asid) => request(asid, &msg),
+      Err(_) => (result::ERR, 0),
+    };
+    let _ = Message::new(a, b, 0, 0).send_to(client_tid);
+  }
+}
+
+/// Terminal server reading from the console port, used when there is no UART
+pub fn terminal() {
+  info!("terminal started t{}", get_tid());
+  rpsyscall::server_register(rpabi::server::SERVER_TERMINAL).unwrap();
+  let mut client_tid = Message::receive().unwrap().0;
+  loop {
+    let mut msg = Message::default();
+    // Note: input before the device is set up reads as none
+    if let Some(console) = CONSOLE.get() {
+      msg.a = getc(&mut console.lock());
+    }
+    client_tid = msg.reply_recv(client_tid).unwrap().0;
+  }
+}
+
+/// Console server of the first virtio console
+pub fn server(dev: Device<'static>) {
+  info!("server started t{}", get_tid());
+  let console = crate::common::virtio::transport(&dev).and_then(VirtioConsole::new);
+  let console = match console {
+    Ok(console) => console,
+    Err(e) => {
+      error!("{} {}", dev.name, e);
+      return;
+    }
+  };
+  info!("{} {} ports", dev.name, console.ports.len());
+  CONSOLE.call_once(|| Mutex::new(console));
+  let _requests = thread::spawn(requests);
+
+  let irq = dev.interrupt();
+  loop {
+    match irq {
+      Some(irq) if rpsyscall::event_wait(rpabi::event::EVENT_INTERRUPT, irq).is_ok() => {
+        let mut console = CONSOLE.get().unwrap().lock();
+        console.interrupt_ack();
+        console.poll();
+        drop(console);
+        let _ = rpsyscall::irq_ack(irq);
+      }
+      _ => {
+        CONSOLE.get().unwrap().lock().poll();
+        rpsyscall::thread_yield();
+      }
+    }
+  }
+}
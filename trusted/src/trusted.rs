@@ -23,9 +23,12 @@ mod serial;
 mod fs;
 mod logger;
 mod mm;
+mod net;
 mod pm;
+mod rng;
 mod root;
 mod rtc;
+mod unix;
 
 #[no_mangle]
 extern "C" fn _start(arg: *const PlatformInfo) -> ! {
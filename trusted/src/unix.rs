@@ -0,0 +1,334 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use rpservapi::unix::{action, kind, result};
+use rpsyscall::message::Message;
+use rpsyscall::{get_asid, get_tid};
+use spin::Mutex;
+
+use crate::common::foreign_slice::with_client_buffer;
+use crate::common::wrapper::request_wrapper;
+
+const PATH_MAX: usize = 128;
+// bytes buffered towards one end of a stream
+const STREAM_BUFFER_SIZE: usize = 65536;
+const DATAGRAM_SIZE_MAX: usize = 65536;
+const DATAGRAM_QUEUE_MAX: usize = 32;
+const BACKLOG_MAX: usize = 16;
+// permissions of the file system node of a bound socket
+const NODE_MODE: usize = 0o666;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Peer {
+  None,
+  Connected(usize),
+  // the stream peer is gone, readers see end of stream once drained
+  Closed,
+}
+
+struct Socket {
+  asid: u16,
+  kind: usize,
+  path: Option<String>,
+  peer: Peer,
+  listening: bool,
+  // connections made to a listening socket, not accepted yet
+  backlog: VecDeque<usize>,
+  stream: VecDeque<u8>,
+  datagrams: VecDeque<Vec<u8>>,
+}
+
+impl Socket {
+  fn new(asid: u16, kind: usize) -> Self {
+    Socket {
+      asid,
+      kind,
+      path: None,
+      peer: Peer::None,
+      listening: false,
+      backlog: VecDeque::new(),
+      stream: VecDeque::new(),
+      datagrams: VecDeque::new(),
+    }
+  }
+}
+
+struct SocketTable {
+  sockets: BTreeMap<usize, Socket>,
+  // bound path -> handle
+  names: BTreeMap<String, usize>,
+  next_handle: usize,
+}
+
+static SOCKET_TABLE: Mutex<SocketTable> = Mutex::new(SocketTable {
+  sockets: BTreeMap::new(),
+  names: BTreeMap::new(),
+  next_handle: 1,
+});
+
+fn client_path(asid: u16, addr: usize, len: usize) -> Option<String> {
+  if len == 0 || len > PATH_MAX {
+    return None;
+  }
+  let path = with_client_buffer(asid, addr, len, |buf| core::str::from_utf8(buf).ok().map(String::from))??;
+  if path.starts_with('/') {
+    Some(path)
+  } else {
+    None
+  }
+}
+
+fn fs_call(msg: Message) -> Result<usize, usize> {
+  let fs = rpsyscall::server_tid(rpabi::server::SERVER_REDOX_FS).map_err(|_| result::NOT_FOUND)?;
+  let msg = msg.call_tid(fs).map_err(|_| result::ERR)?;
+  syscall::Error::demux(msg.a).map_err(|e| match e.errno {
+    syscall::EEXIST => result::ADDR_IN_USE,
+    syscall::ENOENT | syscall::ENOTDIR => result::NOT_FOUND,
+    _ => result::ERR,
+  })
+}
+
+// Note: a bound socket is a node of the file system, so its name follows directories and cannot shadow a file
+fn node_create(path: &str) -> usize {
+  let open = Message::new(
+    syscall::SYS_OPEN,
+    path.as_ptr() as usize,
+    path.len(),
+    syscall::O_CREAT | syscall::O_EXCL | syscall::O_WRONLY | NODE_MODE,
+  );
+  match fs_call(open) {
+    Ok(file) => {
+      let _ = fs_call(Message::new(syscall::SYS_CLOSE, file, 0, 0));
+      result::OK
+    }
+    Err(e) => e,
+  }
+}
+
+fn node_remove(path: &str) {
+  if let Err(e) = fs_call(Message::new(syscall::SYS_UNLINK, path.as_ptr() as usize, path.len(), 0)) {
+    warn!("unlink {} {}", path, e);
+  }
+}
+
+impl SocketTable {
+  fn insert(&mut self, socket: Socket) -> usize {
+    let handle = self.next_handle;
+    self.next_handle += 1;
+    self.sockets.insert(handle, socket);
+    handle
+  }
+
+  fn bind(&mut self, handle: usize, path: String) -> usize {
+    if self.names.contains_key(&path) {
+      return result::ADDR_IN_USE;
+    }
+    let socket = self.sockets.get_mut(&handle).unwrap();
+    if socket.path.is_some() || socket.peer != Peer::None {
+      return result::INVARG;
+    }
+    let r = node_create(&path);
+    if r != result::OK {
+      return r;
+    }
+    socket.path = Some(path.clone());
+    self.names.insert(path, handle);
+    result::OK
+  }
+
+  fn listen(&mut self, handle: usize) -> usize {
+    let socket = self.sockets.get_mut(&handle).unwrap();
+    if socket.kind != kind::STREAM || socket.path.is_none() || socket.peer != Peer::None {
+      return result::INVARG;
+    }
+    socket.listening = true;
+    result::OK
+  }
+
+  fn accept(&mut self, handle: usize) -> (usize, usize) {
+    let socket = self.sockets.get_mut(&handle).unwrap();
+    if !socket.listening {
+      return (result::INVARG, 0);
+    }
+    match socket.backlog.pop_front() {
+      Some(connection) => (result::OK, connection),
+      None => (result::HOLD_ON, 0),
+    }
+  }
+
+  // Note: a stream connects as soon as it is queued on the listener, like `connect(2)` on a Unix socket
+  fn connect(&mut self, handle: usize, path: &str) -> usize {
+    let target = match self.names.get(path) {
+      Some(&target) => target,
+      None => return result::NOT_FOUND,
+    };
+    let socket = &self.sockets[&handle];
+    let kind = socket.kind;
+    if socket.listening || socket.peer != Peer::None || self.sockets[&target].kind != kind {
+      return result::INVARG;
+    }
+    if kind == kind::DATAGRAM {
+      self.sockets.get_mut(&handle).unwrap().peer = Peer::Connected(target);
+      return result::OK;
+    }
+    let listener = &self.sockets[&target];
+    if !listener.listening {
+      return result::NOT_FOUND;
+    }
+    if listener.backlog.len() >= BACKLOG_MAX {
+      return result::HOLD_ON;
+    }
+    let mut server_end = Socket::new(listener.asid, kind);
+    server_end.peer = Peer::Connected(handle);
+    let server_end = self.insert(server_end);
+    self.sockets.get_mut(&target).unwrap().backlog.push_back(server_end);
+    self.sockets.get_mut(&handle).unwrap().peer = Peer::Connected(server_end);
+    result::OK
+  }
+
+  fn send(&mut self, handle: usize, addr: usize, len: usize) -> (usize, usize) {
+    let socket = &self.sockets[&handle];
+    let (asid, kind) = (socket.asid, socket.kind);
+    let peer = match socket.peer {
+      Peer::Connected(peer) => match self.sockets.get_mut(&peer) {
+        Some(peer) => peer,
+        None => return (result::ERR, 0),
+      },
+      Peer::None => return (result::INVARG, 0),
+      Peer::Closed => return (result::ERR, 0),
+    };
+    if kind == kind::DATAGRAM {
+      if len > DATAGRAM_SIZE_MAX {
+        return (result::INVARG, 0);
+      }
+      if peer.datagrams.len() >= DATAGRAM_QUEUE_MAX {
+        return (result::HOLD_ON, 0);
+      }
+      return match with_client_buffer(asid, addr, len, |buf| buf.to_vec()) {
+        Some(datagram) => {
+          peer.datagrams.push_back(datagram);
+          (result::OK, len)
+        }
+        None => (result::ERR, 0),
+      };
+    }
+    let len = len.min(STREAM_BUFFER_SIZE - peer.stream.len());
+    if len == 0 {
+      return (result::HOLD_ON, 0);
+    }
+    match with_client_buffer(asid, addr, len, |buf| peer.stream.extend(buf.iter())) {
+      Some(()) => (result::OK, len),
+      None => (result::ERR, 0),
+    }
+  }
+
+  fn recv(&mut self, handle: usize, addr: usize, len: usize) -> (usize, usize) {
+    let socket = self.sockets.get_mut(&handle).unwrap();
+    let asid = socket.asid;
+    if socket.kind == kind::DATAGRAM {
+      if socket.path.is_none() && socket.peer == Peer::None {
+        return (result::INVARG, 0);
+      }
+      // Note: the tail of a datagram longer than the buffer is discarded
+      return match socket.datagrams.pop_front() {
+        Some(datagram) => {
+          let n = datagram.len().min(len);
+          match with_client_buffer(asid, addr, n, |buf| buf.copy_from_slice(&datagram[..n])) {
+            Some(()) => (result::OK, n),
+            None => (result::ERR, 0),
+          }
+        }
+        None => (result::HOLD_ON, 0),
+      };
+    }
+    if socket.stream.is_empty() {
+      return match socket.peer {
+        Peer::Connected(_) => (result::HOLD_ON, 0),
+        Peer::Closed => (result::OK, 0),
+        Peer::None => (result::INVARG, 0),
+      };
+    }
+    let n = socket.stream.len().min(len);
+    let stream = &mut socket.stream;
+    match with_client_buffer(asid, addr, n, |buf| {
+      for (b, c) in buf.iter_mut().zip(stream.drain(..n)) {
+        *b = c;
+      }
+    }) {
+      Some(()) => (result::OK, n),
+      None => (result::ERR, 0),
+    }
+  }
+
+  fn close(&mut self, handle: usize) {
+    let socket = match self.sockets.remove(&handle) {
+      Some(socket) => socket,
+      None => return,
+    };
+    if let Some(path) = socket.path {
+      self.names.remove(&path);
+      node_remove(&path);
+    }
+    if socket.kind == kind::STREAM {
+      if let Peer::Connected(peer) = socket.peer {
+        if let Some(peer) = self.sockets.get_mut(&peer) {
+          peer.peer = Peer::Closed;
+        }
+      }
+    }
+    // connections never accepted are dropped with their listener
+    for connection in socket.backlog {
+      self.close(connection);
+    }
+  }
+}
+
+fn unix(msg: Message, tid: usize) -> (usize, usize) {
+  let asid = match get_asid(tid) {
+    Ok(asid) => asid,
+    Err(_) => return (result::ERR, 0),
+  };
+  let mut table = SOCKET_TABLE.lock();
+  let handle = msg.b;
+  if msg.a != action::SOCKET && !matches!(table.sockets.get(&handle), Some(s) if s.asid == asid) {
+    return (result::INVARG, 0);
+  }
+  match msg.a {
+    action::SOCKET => match msg.b {
+      kind::STREAM | kind::DATAGRAM => (result::OK, table.insert(Socket::new(asid, msg.b))),
+      _ => (result::INVARG, 0),
+    },
+    action::BIND => match client_path(asid, msg.c, msg.d) {
+      Some(path) => (table.bind(handle, path), 0),
+      None => (result::INVARG, 0),
+    },
+    action::LISTEN => (table.listen(handle), 0),
+    action::ACCEPT => table.accept(handle),
+    action::CONNECT => match client_path(asid, msg.c, msg.d) {
+      Some(path) => (table.connect(handle, &path), 0),
+      None => (result::INVARG, 0),
+    },
+    action::SEND => table.send(handle, msg.c, msg.d),
+    action::RECV => table.recv(handle, msg.c, msg.d),
+    action::CLOSE => {
+      table.close(handle);
+      (result::OK, 0)
+    }
+    _ => (result::UNKNOWN_ACTION, 0),
+  }
+}
+
+/// Local socket server
+///
+/// Sockets of a process killed without closing them are not reclaimed.
+pub fn server() {
+  info!("server started t{}", get_tid());
+  rpsyscall::server_register(rpabi::server::SERVER_UNIX).unwrap();
+  loop {
+    let (client_tid, msg) = Message::receive().unwrap();
+    let (a, b) = request_wrapper(unix, msg, client_tid).unwrap();
+    let result = Message::new(a, b, 0, 0);
+    let _ = result.send_to(client_tid);
+  }
+}
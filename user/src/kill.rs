@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+#[macro_use]
+extern crate rpstdlib;
+
+use alloc::vec::Vec;
+
+#[no_mangle]
+fn main(arg: Vec<&'static str>) -> i32 {
+  if arg.is_empty() {
+    println!("usage: kill PID...");
+    return 1;
+  }
+  for pid in arg {
+    match pid.parse::<usize>() {
+      Ok(pid) => {
+        if let Err(e) = rpstdlib::pm::kill(pid) {
+          println!("kill {}: {}", pid, e);
+        }
+      }
+      Err(_) => {
+        println!("kill: invalid pid {}", pid);
+      }
+    }
+  }
+  0
+}
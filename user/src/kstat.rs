@@ -0,0 +1,17 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+#[macro_use]
+extern crate rpstdlib;
+
+use alloc::vec::Vec;
+
+#[no_mangle]
+fn main(_arg: Vec<&'static str>) -> i32 {
+  if let Err(e) = rpstdlib::mm::kstat() {
+    println!("kstat: {}", e);
+    return 1;
+  }
+  0
+}
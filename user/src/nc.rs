@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+#[macro_use]
+extern crate rpstdlib;
+
+use alloc::vec::Vec;
+use rpstdlib::net::{SocketAddrV4, TcpListener, TcpStream};
+
+fn print_received(stream: &mut TcpStream, echo: bool) -> Result<(), &'static str> {
+  let mut buf = [0u8; 512];
+  loop {
+    let read = stream.read(&mut buf)?;
+    if read == 0 {
+      return Ok(());
+    }
+    print!("{}", core::str::from_utf8(&buf[..read]).unwrap_or("?"));
+    if echo {
+      stream.write_all(&buf[..read])?;
+    }
+  }
+}
+
+fn listen(port: u16) -> Result<(), &'static str> {
+  let listener = TcpListener::bind(port)?;
+  let mut stream = listener.accept()?;
+  print_received(&mut stream, true)
+}
+
+fn connect(addr: SocketAddrV4, text: &[&str]) -> Result<(), &'static str> {
+  let mut stream = TcpStream::connect(addr)?;
+  if !text.is_empty() {
+    for (i, s) in text.iter().enumerate() {
+      if i != 0 {
+        stream.write_all(b" ")?;
+      }
+      stream.write_all(s.as_bytes())?;
+    }
+    stream.write_all(b"\n")?;
+  }
+  print_received(&mut stream, false)
+}
+
+#[no_mangle]
+fn main(arg: Vec<&'static str>) -> i32 {
+  let r = match arg.as_slice() {
+    ["-l", port] => match port.parse() {
+      Ok(port) => listen(port),
+      Err(_) => Err("invalid port"),
+    },
+    [addr, text @ ..] => SocketAddrV4::parse(addr).and_then(|addr| connect(addr, text)),
+    _ => {
+      println!("usage: nc ADDRESS:PORT [TEXT...] | nc -l PORT");
+      return 0;
+    }
+  };
+  if let Err(e) = r {
+    println!("nc: {}", e);
+    return 1;
+  }
+  0
+}
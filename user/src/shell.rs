@@ -39,7 +39,15 @@ fn main(_arg: Vec<&'static str>) -> i32 {
     if cmd.trim().is_empty() {
       continue;
     }
-    match rpstdlib::pm::exec(cmd.as_str()) {
+    // trailing `&` runs the command in background, e.g. to `kill` it later
+    let (cmd, background) = match cmd.trim().strip_suffix('&') {
+      Some(cmd) => (cmd, true),
+      None => (cmd.as_str(), false),
+    };
+    match rpstdlib::pm::exec(cmd) {
+      Ok(pid) if background => {
+        println!("[{}]", pid);
+      }
       Ok(pid) => {
         rpstdlib::pm::wait(pid);
       }